@@ -0,0 +1,648 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::time::Duration;
+
+use crate::correction::{decode_stage, encode_correction, CorrectionType};
+
+/* Automatic repeat request: the message is cut into frames, every frame gets a
+ * header (type, message id, sequence number, last frame flag) and a CRC. The
+ * receiver ACKs frames that pass the CRC and NACKs the ones that don't, the
+ * sender retransmits until everything is acknowledged.
+ *
+ * Stop-and-wait: one frame at a time.
+ * Go-Back-N: up to N frames in flight, the receiver only takes them in order,
+ * so one bad frame means resending it and everything after it.
+ * Selective repeat: up to N frames in flight, the receiver buffers out of order
+ * frames, so only the bad frame gets resent.
+ */
+
+pub const SEQUENCE_BITS: usize = 4;
+const SEQUENCE_MODULO: usize = 1 << SEQUENCE_BITS;
+pub const FRAME_PAYLOAD_BITS: usize = 16;
+pub const ARQ_TIMEOUT: Duration = Duration::from_millis(200);
+
+// A frame that fails the check is treated as if it never arrived
+const FRAME_CHECK: CorrectionType = CorrectionType::Crc;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArqScheme {
+    StopAndWait,
+    GoBackN,
+    SelectiveRepeat,
+}
+
+impl ArqScheme {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "stop-and-wait" | "saw" => Some(ArqScheme::StopAndWait),
+            "go-back-n" | "gbn" => Some(ArqScheme::GoBackN),
+            "selective-repeat" | "sr" => Some(ArqScheme::SelectiveRepeat),
+            _ => None,
+        }
+    }
+
+    // The largest windows the sequence number space allows for each scheme
+    pub fn window_size(&self) -> usize {
+        match self {
+            ArqScheme::StopAndWait => 1,
+            ArqScheme::GoBackN => SEQUENCE_MODULO - 1,
+            ArqScheme::SelectiveRepeat => SEQUENCE_MODULO / 2,
+        }
+    }
+}
+
+/// Looks for `--arq <scheme>` in the command line arguments.
+pub fn arq_scheme_from_args(args: &[String]) -> Result<Option<ArqScheme>, String> {
+    match args.iter().position(|arg| arg == "--arq") {
+        None => Ok(None),
+        Some(index) => match args.get(index + 1) {
+            Some(name) => ArqScheme::from_name(name)
+                .map(Some)
+                .ok_or(format!("Unknown ARQ scheme: {}", name)),
+            None => Err("--arq needs stop-and-wait, go-back-n or selective-repeat".to_string()),
+        },
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameKind {
+    Data,
+    Ack,
+    Nack,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    // Alternates between messages, so stray frames from the last message are not mixed in
+    pub message_id: usize,
+    pub sequence: usize,
+    pub is_last: bool,
+    pub payload: String,
+}
+
+impl Frame {
+    fn reply(kind: FrameKind, message_id: usize, sequence: usize) -> Self {
+        Frame {
+            kind,
+            message_id,
+            sequence: sequence % SEQUENCE_MODULO,
+            is_last: false,
+            payload: "".to_string(),
+        }
+    }
+}
+
+/* Header: 2 bits frame kind, 1 bit message id, SEQUENCE_BITS bits sequence number,
+ * 1 bit last frame flag. Then the payload, then the CRC over all of it.
+ */
+pub fn encode_frame(frame: &Frame) -> String {
+    let kind = match frame.kind {
+        FrameKind::Data => "00",
+        FrameKind::Ack => "01",
+        FrameKind::Nack => "10",
+    };
+    let bits = format!(
+        "{}{}{:0>width$b}{}{}",
+        kind,
+        frame.message_id % 2,
+        frame.sequence % SEQUENCE_MODULO,
+        frame.is_last as u8,
+        frame.payload,
+        width = SEQUENCE_BITS
+    );
+
+    encode_correction(&FRAME_CHECK, &bits).1
+}
+
+pub fn decode_frame(bits: &str) -> Option<Frame> {
+    if !bits.chars().all(|c| c == '0' || c == '1') {
+        return None;
+    }
+
+    let (has_error, _, _, frame_bits) = decode_stage(&FRAME_CHECK, bits);
+    if has_error || frame_bits.len() < 4 + SEQUENCE_BITS {
+        return None;
+    }
+
+    let kind = match &frame_bits[..2] {
+        "00" => FrameKind::Data,
+        "01" => FrameKind::Ack,
+        "10" => FrameKind::Nack,
+        _ => return None,
+    };
+
+    Some(Frame {
+        kind,
+        message_id: (&frame_bits[2..3] == "1") as usize,
+        sequence: usize::from_str_radix(&frame_bits[3..3 + SEQUENCE_BITS], 2).unwrap(),
+        is_last: &frame_bits[3 + SEQUENCE_BITS..4 + SEQUENCE_BITS] == "1",
+        payload: frame_bits[4 + SEQUENCE_BITS..].to_string(),
+    })
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ArqSenderStats {
+    pub messages: usize,
+    pub frames_sent: usize,
+    pub retransmissions: usize,
+    pub bits_sent: usize,
+    pub payload_bits: usize,
+    pub acks_received: usize,
+    pub nacks_received: usize,
+    pub timeouts: usize,
+    pub elapsed: Duration,
+}
+
+impl ArqSenderStats {
+    pub fn add(&mut self, other: &ArqSenderStats) {
+        self.messages += other.messages;
+        self.frames_sent += other.frames_sent;
+        self.retransmissions += other.retransmissions;
+        self.bits_sent += other.bits_sent;
+        self.payload_bits += other.payload_bits;
+        self.acks_received += other.acks_received;
+        self.nacks_received += other.nacks_received;
+        self.timeouts += other.timeouts;
+        self.elapsed += other.elapsed;
+    }
+
+    // Share of the transmitted bits that were message bits
+    pub fn efficiency(&self) -> f64 {
+        if self.bits_sent == 0 {
+            return 0.0;
+        }
+        self.payload_bits as f64 / self.bits_sent as f64
+    }
+
+    pub fn throughput(&self) -> f64 {
+        if self.elapsed.is_zero() {
+            return 0.0;
+        }
+        self.payload_bits as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl fmt::Display for ArqSenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Messages delivered: {}", self.messages)?;
+        writeln!(
+            f,
+            "Frames sent: {} ({} retransmissions)",
+            self.frames_sent, self.retransmissions
+        )?;
+        writeln!(
+            f,
+            "ACKs: {}, NACKs: {}, timeouts: {}",
+            self.acks_received, self.nacks_received, self.timeouts
+        )?;
+        writeln!(
+            f,
+            "Efficiency: {} message bits / {} bits sent = {:.1}%",
+            self.payload_bits,
+            self.bits_sent,
+            self.efficiency() * 100.0
+        )?;
+        write!(f, "Throughput: {:.0} message bits/s", self.throughput())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ArqReceiverStats {
+    pub messages: usize,
+    pub frames_received: usize,
+    pub accepted_frames: usize,
+    pub corrupted_frames: usize,
+    pub duplicate_frames: usize,
+    pub out_of_order_frames: usize,
+    pub acks_sent: usize,
+    pub nacks_sent: usize,
+    pub payload_bits: usize,
+}
+
+impl ArqReceiverStats {
+    pub fn add(&mut self, other: &ArqReceiverStats) {
+        self.messages += other.messages;
+        self.frames_received += other.frames_received;
+        self.accepted_frames += other.accepted_frames;
+        self.corrupted_frames += other.corrupted_frames;
+        self.duplicate_frames += other.duplicate_frames;
+        self.out_of_order_frames += other.out_of_order_frames;
+        self.acks_sent += other.acks_sent;
+        self.nacks_sent += other.nacks_sent;
+        self.payload_bits += other.payload_bits;
+    }
+
+    // Share of the received frames that were new and kept
+    pub fn efficiency(&self) -> f64 {
+        if self.frames_received == 0 {
+            return 0.0;
+        }
+        self.accepted_frames as f64 / self.frames_received as f64
+    }
+}
+
+impl fmt::Display for ArqReceiverStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Messages received: {}", self.messages)?;
+        writeln!(
+            f,
+            "Frames received: {} ({} corrupted, {} duplicates, {} out of order)",
+            self.frames_received,
+            self.corrupted_frames,
+            self.duplicate_frames,
+            self.out_of_order_frames
+        )?;
+        writeln!(
+            f,
+            "ACKs sent: {}, NACKs sent: {}",
+            self.acks_sent, self.nacks_sent
+        )?;
+        write!(
+            f,
+            "Efficiency: {:.1}% of frames were useful, {} message bits",
+            self.efficiency() * 100.0,
+            self.payload_bits
+        )
+    }
+}
+
+pub struct ArqSender {
+    pub scheme: ArqScheme,
+    pub message_id: usize,
+    pub stats: ArqSenderStats,
+    chunks: Vec<String>,
+    acked: Vec<bool>,
+    sent_once: Vec<bool>,
+    base: usize,
+    next_to_send: usize,
+    resend: BTreeSet<usize>,
+    // Where Go-Back-N last went back to, and how many frames had been sent by then
+    went_back_to: Option<(usize, usize)>,
+}
+
+impl ArqSender {
+    pub fn new(scheme: ArqScheme, message_id: usize, bits: &str) -> Self {
+        let mut chunks: Vec<String> = bits
+            .as_bytes()
+            .chunks(FRAME_PAYLOAD_BITS)
+            .map(|chunk| String::from_utf8_lossy(chunk).to_string())
+            .collect();
+        if chunks.is_empty() {
+            chunks.push("".to_string());
+        }
+
+        ArqSender {
+            scheme,
+            message_id,
+            stats: ArqSenderStats {
+                messages: 1,
+                payload_bits: bits.len(),
+                ..Default::default()
+            },
+            acked: vec![false; chunks.len()],
+            sent_once: vec![false; chunks.len()],
+            chunks,
+            base: 0,
+            next_to_send: 0,
+            resend: BTreeSet::new(),
+            went_back_to: None,
+        }
+    }
+
+    fn window_end(&self) -> usize {
+        (self.base + self.scheme.window_size()).min(self.chunks.len())
+    }
+
+    fn frame(&mut self, index: usize) -> Frame {
+        let frame = Frame {
+            kind: FrameKind::Data,
+            message_id: self.message_id,
+            sequence: index % SEQUENCE_MODULO,
+            is_last: index == self.chunks.len() - 1,
+            payload: self.chunks[index].clone(),
+        };
+
+        self.stats.frames_sent += 1;
+        self.stats.bits_sent += encode_frame(&frame).len();
+        if self.sent_once[index] {
+            self.stats.retransmissions += 1;
+        }
+        self.sent_once[index] = true;
+
+        frame
+    }
+
+    /// Everything the window allows to go out right now, retransmissions first.
+    pub fn frames_to_send(&mut self) -> Vec<Frame> {
+        let mut frames = vec![];
+
+        let resend: Vec<usize> = std::mem::take(&mut self.resend).into_iter().collect();
+        for index in resend {
+            if !self.acked[index] && index < self.next_to_send {
+                frames.push(self.frame(index));
+            }
+        }
+
+        while self.next_to_send < self.window_end() {
+            frames.push(self.frame(self.next_to_send));
+            self.next_to_send += 1;
+        }
+
+        frames
+    }
+
+    // Which outstanding frame a wire sequence number refers to
+    fn outstanding_index(&self, sequence: usize) -> Option<usize> {
+        (self.base..self.next_to_send.max(self.base)).find(|i| i % SEQUENCE_MODULO == sequence)
+    }
+
+    pub fn on_response(&mut self, frame: &Frame) {
+        if frame.message_id != self.message_id % 2 {
+            return;
+        }
+
+        match frame.kind {
+            FrameKind::Data => {}
+            FrameKind::Ack => {
+                self.stats.acks_received += 1;
+                let Some(index) = self.outstanding_index(frame.sequence) else {
+                    return;
+                };
+
+                match self.scheme {
+                    // Cumulative: everything up to this one made it
+                    ArqScheme::StopAndWait | ArqScheme::GoBackN => {
+                        for acked in &mut self.acked[self.base..=index] {
+                            *acked = true;
+                        }
+                    }
+                    ArqScheme::SelectiveRepeat => self.acked[index] = true,
+                }
+                while self.base < self.chunks.len() && self.acked[self.base] {
+                    self.base += 1;
+                }
+            }
+            FrameKind::Nack => {
+                self.stats.nacks_received += 1;
+                let Some(index) = self.outstanding_index(frame.sequence) else {
+                    return;
+                };
+
+                match self.scheme {
+                    ArqScheme::StopAndWait | ArqScheme::SelectiveRepeat => {
+                        self.resend.insert(index);
+                    }
+                    ArqScheme::GoBackN => {
+                        /* Frames that were already in flight will NACK the same one again,
+                         * those answers are stale. Every frame gets one answer, so once
+                         * there have been more answers than frames sent before going back,
+                         * a NACK for it means the retransmission didn't make it either.
+                         */
+                        let answers = self.stats.acks_received + self.stats.nacks_received;
+                        let is_stale = self
+                            .went_back_to
+                            .is_some_and(|(went_back, sent)| went_back == index && answers <= sent);
+                        if !is_stale {
+                            for acked in &mut self.acked[self.base..index] {
+                                *acked = true;
+                            }
+                            self.base = index;
+                            self.next_to_send = index;
+                            self.went_back_to = Some((index, self.stats.frames_sent));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn on_timeout(&mut self) {
+        self.stats.timeouts += 1;
+        self.went_back_to = None;
+
+        match self.scheme {
+            ArqScheme::StopAndWait | ArqScheme::GoBackN => self.next_to_send = self.base,
+            ArqScheme::SelectiveRepeat => {
+                self.resend.extend(self.base..self.next_to_send);
+            }
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.base >= self.chunks.len()
+    }
+}
+
+pub struct ArqReceiver {
+    pub scheme: ArqScheme,
+    pub message_id: usize,
+    pub stats: ArqReceiverStats,
+    expected: usize,
+    buffer: BTreeMap<usize, (String, bool)>,
+    message: String,
+    is_done: bool,
+}
+
+impl ArqReceiver {
+    pub fn new(scheme: ArqScheme, message_id: usize) -> Self {
+        ArqReceiver {
+            scheme,
+            message_id,
+            stats: ArqReceiverStats::default(),
+            expected: 0,
+            buffer: BTreeMap::new(),
+            message: String::new(),
+            is_done: false,
+        }
+    }
+
+    fn reply(&mut self, kind: FrameKind, message_id: usize, sequence: usize) -> Option<Frame> {
+        match kind {
+            FrameKind::Nack => self.stats.nacks_sent += 1,
+            _ => self.stats.acks_sent += 1,
+        }
+        Some(Frame::reply(kind, message_id, sequence))
+    }
+
+    fn deliver_buffered(&mut self) {
+        while let Some((payload, is_last)) = self.buffer.remove(&self.expected) {
+            self.message.push_str(&payload);
+            self.expected += 1;
+            if is_last {
+                self.is_done = true;
+                self.stats.messages = 1;
+                self.stats.payload_bits = self.message.len();
+            }
+        }
+    }
+
+    /// Takes a received frame (None if it failed the check) and returns the reply.
+    pub fn on_frame(&mut self, frame: Option<Frame>) -> Option<Frame> {
+        self.stats.frames_received += 1;
+
+        let frame = match frame {
+            Some(frame) if frame.kind == FrameKind::Data => frame,
+            Some(_) => return None,
+            None => {
+                self.stats.corrupted_frames += 1;
+                return self.reply(FrameKind::Nack, self.message_id, self.expected);
+            }
+        };
+
+        if frame.message_id != self.message_id % 2 || self.is_done {
+            self.stats.duplicate_frames += 1;
+            return self.reply(FrameKind::Ack, frame.message_id, frame.sequence);
+        }
+
+        // How far ahead of the frame we are waiting for this one is
+        let offset =
+            (frame.sequence + SEQUENCE_MODULO - self.expected % SEQUENCE_MODULO) % SEQUENCE_MODULO;
+        if offset >= self.scheme.window_size() {
+            // Already have it, the ACK must have been too slow
+            self.stats.duplicate_frames += 1;
+            return self.reply(FrameKind::Ack, frame.message_id, frame.sequence);
+        }
+
+        match self.scheme {
+            ArqScheme::StopAndWait | ArqScheme::GoBackN => {
+                if offset != 0 {
+                    self.stats.out_of_order_frames += 1;
+                    return self.reply(FrameKind::Nack, self.message_id, self.expected);
+                }
+            }
+            ArqScheme::SelectiveRepeat => {
+                if offset != 0 {
+                    self.stats.out_of_order_frames += 1;
+                }
+                if self.buffer.contains_key(&(self.expected + offset)) {
+                    self.stats.duplicate_frames += 1;
+                    return self.reply(FrameKind::Ack, frame.message_id, frame.sequence);
+                }
+            }
+        }
+
+        self.stats.accepted_frames += 1;
+        self.buffer
+            .insert(self.expected + offset, (frame.payload, frame.is_last));
+        self.deliver_buffered();
+        self.reply(FrameKind::Ack, frame.message_id, frame.sequence)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.is_done
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs a whole message through, corrupting the data frame transmissions listed in `bad`
+    fn run(scheme: ArqScheme, bits: &str, bad: &[usize]) -> (ArqSender, ArqReceiver) {
+        let mut sender = ArqSender::new(scheme, 0, bits);
+        let mut receiver = ArqReceiver::new(scheme, 0);
+        let mut transmission = 0;
+
+        while !sender.is_done() {
+            let frames = sender.frames_to_send();
+            if frames.is_empty() {
+                sender.on_timeout();
+                continue;
+            }
+            for frame in frames {
+                let mut wire = encode_frame(&frame);
+                if bad.contains(&transmission) {
+                    wire.replace_range(0..1, if wire.starts_with('0') { "1" } else { "0" });
+                }
+                transmission += 1;
+
+                if let Some(reply) = receiver.on_frame(decode_frame(&wire)) {
+                    sender.on_response(&decode_frame(&encode_frame(&reply)).unwrap());
+                }
+            }
+        }
+
+        (sender, receiver)
+    }
+
+    #[test]
+    fn test_frame_round_trip() {
+        let frame = Frame {
+            kind: FrameKind::Data,
+            message_id: 1,
+            sequence: 9,
+            is_last: true,
+            payload: "1011".to_string(),
+        };
+        let bits = encode_frame(&frame);
+        assert_eq!(&bits[..8], "00110011");
+        assert_eq!(decode_frame(&bits), Some(frame));
+        assert_eq!(decode_frame(&format!("1{}", &bits[1..])), None);
+    }
+
+    #[test]
+    fn test_all_schemes_deliver_with_errors() {
+        let bits = "1100101011110000".repeat(6);
+        for scheme in [
+            ArqScheme::StopAndWait,
+            ArqScheme::GoBackN,
+            ArqScheme::SelectiveRepeat,
+        ] {
+            let (sender, receiver) = run(scheme, &bits, &[1, 2, 5]);
+            assert!(receiver.is_done(), "{:?} did not finish", scheme);
+            assert_eq!(receiver.message(), bits, "{:?} garbled the message", scheme);
+            assert!(sender.stats.retransmissions >= 3);
+        }
+    }
+
+    #[test]
+    fn test_selective_repeat_resends_less_than_go_back_n() {
+        let bits = "1100101011110000".repeat(6);
+        let (go_back_n, _) = run(ArqScheme::GoBackN, &bits, &[0]);
+        let (selective_repeat, _) = run(ArqScheme::SelectiveRepeat, &bits, &[0]);
+
+        assert_eq!(selective_repeat.stats.retransmissions, 1);
+        assert!(go_back_n.stats.retransmissions > 1);
+    }
+
+    #[test]
+    fn test_go_back_n_goes_back_again_when_the_retransmission_fails() {
+        let bits = "1100101011110000".repeat(4);
+        // Frame 1 goes bad, then its retransmission (the 5th transmission) as well
+        let (sender, receiver) = run(ArqScheme::GoBackN, &bits, &[1, 4]);
+        assert_eq!(receiver.message(), bits);
+        assert_eq!(sender.stats.timeouts, 0);
+        assert_eq!(sender.stats.retransmissions, 6);
+    }
+
+    #[test]
+    fn test_receiver_ignores_stale_message_frames() {
+        let mut receiver = ArqReceiver::new(ArqScheme::StopAndWait, 1);
+        let stale = Frame {
+            kind: FrameKind::Data,
+            message_id: 0,
+            sequence: 0,
+            is_last: true,
+            payload: "1".to_string(),
+        };
+        let reply = receiver.on_frame(Some(stale)).unwrap();
+        assert_eq!((reply.kind, reply.message_id), (FrameKind::Ack, 0));
+        assert!(!receiver.is_done());
+    }
+
+    #[test]
+    fn test_arq_scheme_from_args() {
+        let args: Vec<String> = ["sender", "--arq", "gbn"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(arq_scheme_from_args(&args), Ok(Some(ArqScheme::GoBackN)));
+        assert_eq!(arq_scheme_from_args(&args[..1]), Ok(None));
+        assert!(arq_scheme_from_args(&args[..2]).is_err());
+    }
+}
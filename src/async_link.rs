@@ -0,0 +1,213 @@
+use std::io::{self, ErrorKind};
+
+use futures::{SinkExt, StreamExt};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::bytes::{Buf, BytesMut};
+use tokio_util::codec::{Decoder, Encoder, Framed};
+
+use crate::arq::ArqScheme;
+use crate::correction::{encode_correction, CorrectionType};
+use crate::handshake::{hello_packet, read_accept};
+use crate::huffman::{encode_message, HuffmanEncoding};
+use crate::noise::add_noise;
+use crate::protocol::{encode_packet, parse_packet, Packet, PacketType};
+use crate::report::DecodeReport;
+use crate::session::ReceiverSession;
+use crate::stats::ReceiverStats;
+
+/* Async versions of both ends of a link, for running lots of them in one process.
+ * They speak the same packets as the blocking binaries and the receiver side runs
+ * the same ReceiverSession, PacketCodec only lets tokio's Framed do the buffering
+ * that PacketDecoder does for a blocking stream.
+ */
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PacketCodec;
+
+impl Decoder for PacketCodec {
+    type Item = Packet;
+    type Error = io::Error;
+
+    // Same rules as read_packet: noise is skipped, another version or an unknown code is an error
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Packet>> {
+        loop {
+            match parse_packet(src) {
+                Ok(Some((packet, length))) => {
+                    src.advance(length);
+                    return Ok(Some(packet));
+                }
+                Ok(None) => return Ok(None),
+                Err((error, length)) if error.is_fatal() => {
+                    src.advance(length);
+                    return Err(error.into());
+                }
+                Err((error, length)) => {
+                    println!("Skipping bad data: {}", error);
+                    src.advance(length);
+                }
+            }
+        }
+    }
+}
+
+impl Encoder<Packet> for PacketCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, packet: Packet, dst: &mut BytesMut) -> io::Result<()> {
+        dst.extend_from_slice(&encode_packet(&packet)?);
+        Ok(())
+    }
+}
+
+async fn next_packet<S: AsyncRead + AsyncWrite + Unpin>(
+    framed: &mut Framed<S, PacketCodec>,
+) -> io::Result<Packet> {
+    framed
+        .next()
+        .await
+        .unwrap_or(Err(io::Error::from(ErrorKind::UnexpectedEof)))
+}
+
+/// The receiver's side of one connection, until the sender hangs up or gets an Error.
+pub async fn serve<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    arq_scheme: Option<ArqScheme>,
+) -> io::Result<ReceiverStats> {
+    let mut framed = Framed::new(stream, PacketCodec);
+    let mut session = ReceiverSession::new(arq_scheme);
+
+    while !session.is_closed() {
+        let packet = match framed.next().await {
+            Some(Ok(packet)) => packet,
+            None => break,
+            Some(Err(e)) => {
+                println!("Closing connection: {}", e);
+                if e.kind() == ErrorKind::InvalidData {
+                    framed.send(session.reject(&e.to_string())).await?;
+                }
+                break;
+            }
+        };
+
+        for reply in session.handle_packet(packet) {
+            framed.send(reply).await?;
+        }
+    }
+
+    Ok(session.finish())
+}
+
+/// The sender's side: handshake, a table built from `words`, then every message
+/// encoded with `correction_type` and put through add_noise, seeded with `seed`.
+/// Messages can only use characters from `words`. Returns the receiver's report for
+/// each message.
+pub async fn send_messages<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    words: &[&str],
+    messages: &[&str],
+    correction_type: &CorrectionType,
+    seed: u64,
+) -> io::Result<Vec<DecodeReport>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut framed = Framed::new(stream, PacketCodec);
+
+    framed.send(hello_packet()).await?;
+    let accepted_codes = read_accept(&next_packet(&mut framed).await?).map_err(io::Error::other)?;
+    if !accepted_codes.contains(correction_type) {
+        return Err(io::Error::other("the receiver did not accept this code"));
+    }
+
+    let hf = HuffmanEncoding::new(words);
+    let table_encoding = HuffmanEncoding::encode_table(&hf);
+    framed
+        .send(Packet::bits(PacketType::Table, None, &table_encoding))
+        .await?;
+
+    let mut reports = vec![];
+    for (message_id, message) in messages.iter().enumerate() {
+        let (encoded, bits) = encode_correction(correction_type, &encode_message(message, &hf));
+        if !encoded {
+            return Err(io::Error::other(bits));
+        }
+
+        let noisy = add_noise(bits, correction_type, &mut rng);
+        let packet = Packet::data(correction_type.clone(), message_id as u8, &noisy);
+        framed.send(packet).await?;
+
+        let response = next_packet(&mut framed).await?;
+        if response.packet_type == PacketType::Error {
+            let reason = format!("receiver refused: {}", response.payload_text());
+            return Err(io::Error::other(reason));
+        }
+        let report = DecodeReport::from_packet(&response).ok_or(io::Error::new(
+            ErrorKind::InvalidData,
+            "bad report from the receiver",
+        ))?;
+        reports.push(report);
+    }
+
+    Ok(reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::VERSION;
+    use crate::stats::DecodeOutcome;
+    use tokio::io::{duplex, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_async_link_round_trip() {
+        let (sender_end, receiver_end) = duplex(1024);
+        let receiver = tokio::spawn(serve(receiver_end, None));
+
+        // Row/column parity always fixes the single bit add_noise flips. With this seed
+        // it lands on a data bit, not a parity bit, so the report has its position
+        let code = CorrectionType::Product(Box::default());
+        let words = ["hello", "how", "are", "you", " "];
+        let reports = send_messages(sender_end, &words, &["hello how are you"], &code, 2)
+            .await
+            .unwrap();
+
+        let stats = receiver.await.unwrap().unwrap();
+        assert_eq!(reports.len(), 1);
+        assert!(reports[0].matches("hello how are you"));
+        assert_eq!(reports[0].outcome, DecodeOutcome::Corrected);
+        assert_eq!(reports[0].corrected_positions.len(), 1);
+        assert_eq!((stats.messages, stats.corrected), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_peer_on_another_version_gets_an_error() {
+        let (sender_end, receiver_end) = duplex(1024);
+        let receiver = tokio::spawn(serve(receiver_end, None));
+
+        // A Hello from a build that still speaks version 1
+        let mut hello = encode_packet(&hello_packet()).unwrap();
+        hello[2] = 1;
+        let mut framed = Framed::new(sender_end, PacketCodec);
+        framed.get_mut().write_all(&hello).await.unwrap();
+
+        let reply = next_packet(&mut framed).await.unwrap();
+        assert_eq!(reply.packet_type, PacketType::Error);
+        let reason = reply.payload_text();
+        assert!(reason.contains("version 1"), "{}", reason);
+        assert!(reason.contains(&format!("version {}", VERSION)), "{}", reason);
+
+        let stats = receiver.await.unwrap().unwrap();
+        assert_eq!((stats.messages, stats.errors_sent), (0, 1));
+    }
+
+    #[test]
+    fn test_codec_skips_noise_before_packet() {
+        let packet = Packet::text(PacketType::Response, "hello");
+        let mut src = BytesMut::from(&b"xy"[..]);
+        src.extend_from_slice(&encode_packet(&packet).unwrap());
+
+        let mut codec = PacketCodec;
+        assert_eq!(codec.decode(&mut src).unwrap(), Some(packet));
+        assert!(src.is_empty());
+    }
+}
@@ -0,0 +1,330 @@
+use std::f64::consts::LN_2;
+
+use crate::correction::{encoded_length, vt_check_bits, CorrectionType};
+use crate::modulation::Modulation;
+use crate::noise::Fading;
+use crate::weights::LinearCode;
+
+/* What theory says the codes in correction.rs should do, to hold simulated error
+ * rates against. All the decoders here work on hard decisions, so whatever the
+ * channel, they see a binary symmetric channel with some crossover probability p,
+ * and most figures are worked out for that.
+ *
+ *   frame_error_rate       what the decoder should get, exact where the decoder
+ *                          corrects everything up to t errors and nothing more
+ *   sphere_packing_bound   the least any code of that size and rate could get
+ *   union_bound_*          the most a maximum likelihood decoder would get
+ *   bsc_capacity           the highest rate that can be made reliable at all
+ *
+ * Error rates are per frame, a frame being the data bits one codeword carries.
+ */
+
+// The triple code sends the message length in front, unprotected
+const TRIPLE_HEADER_BITS: usize = 9;
+
+fn ln_binomial(n: usize, k: usize) -> f64 {
+    let k = k.min(n - k);
+    (0..k).map(|i| ((n - i) as f64 / (i + 1) as f64).ln()).sum()
+}
+
+/// The chance that exactly `i` of `n` bits flip.
+pub fn binomial_pmf(n: usize, i: usize, p: f64) -> f64 {
+    if i > n {
+        return 0.0;
+    }
+    if p <= 0.0 {
+        return if i == 0 { 1.0 } else { 0.0 };
+    }
+    if p >= 1.0 {
+        return if i == n { 1.0 } else { 0.0 };
+    }
+    (ln_binomial(n, i) + i as f64 * p.ln() + (n - i) as f64 * (-p).ln_1p()).exp()
+}
+
+/// The chance that more than `t` of `n` bits flip, so a decoder that corrects `t` fails.
+pub fn bounded_distance_error(n: usize, t: usize, p: f64) -> f64 {
+    // Summing the tail keeps small results accurate, 1 - the rest would not
+    (t + 1..=n).map(|i| binomial_pmf(n, i, p)).sum()
+}
+
+// The chance that any of `bits` flips
+fn any_flip(bits: usize, p: f64) -> f64 {
+    -(bits as f64 * (-p).ln_1p()).exp_m1()
+}
+
+// Numerical Recipes' erfc, good to 1.2e-7 relative
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let polynomial = -1.26551223
+        + t * (1.00002368
+            + t * (0.37409196
+                + t * (0.09678418
+                    + t * (-0.18628806
+                        + t * (0.27886807
+                            + t * (-1.13520398
+                                + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277))))))));
+    let result = t * (-z * z + polynomial).exp();
+    if x >= 0.0 {
+        result
+    } else {
+        2.0 - result
+    }
+}
+
+/// The tail of the standard normal distribution beyond `x`.
+pub fn q_function(x: f64) -> f64 {
+    0.5 * erfc(x / 2f64.sqrt())
+}
+
+pub fn binary_entropy(p: f64) -> f64 {
+    if p <= 0.0 || p >= 1.0 {
+        return 0.0;
+    }
+    -p * p.log2() - (1.0 - p) * (1.0 - p).log2()
+}
+
+/// Bits per channel bit the BSC can carry reliably.
+pub fn bsc_capacity(p: f64) -> f64 {
+    1.0 - binary_entropy(p)
+}
+
+/* Shannon's limit: the least Eb/N0 in dB at which `bits_per_dimension` data bits per
+ * real dimension can get through without errors, with any code and any signal.
+ * BPSK at rate R sends R bits per dimension, QPSK too, 16-QAM twice that.
+ * Down to -1.59 dB as the rate goes to 0.
+ */
+pub fn shannon_limit_db(bits_per_dimension: f64) -> f64 {
+    let spectral_efficiency = 2.0 * bits_per_dimension;
+    10.0 * ((2f64.powf(spectral_efficiency) - 1.0) / spectral_efficiency).log10()
+}
+
+/* The sphere packing bound on the BSC. Any code with 2^k codewords of n bits leaves
+ * each codeword 2^(n - k) received words on average to be decoded to it. At best those
+ * are all the words closest to it, a ball plus part of the next shell, and the frame is
+ * only right when the errors stay inside. So no (n, k) code gets a lower error rate.
+ * Perfect codes like Hamming (7, 4) reach it.
+ */
+pub fn sphere_packing_bound(n: usize, k: usize, p: f64) -> f64 {
+    let budget = n.saturating_sub(k) as f64 * LN_2;
+    let mut filled = 0.0;
+    let mut error = 0.0;
+
+    for i in 0..=n {
+        // The shell at distance i as a part of the words a codeword gets
+        let share = (ln_binomial(n, i) - budget).exp();
+        let inside = ((1.0 - filled) / share).clamp(0.0, 1.0);
+        filled = (filled + share).min(1.0);
+        error += binomial_pmf(n, i, p) * (1.0 - inside);
+    }
+
+    error
+}
+
+// The chance that a maximum likelihood decoder on the BSC picks a codeword `weight` away
+fn pairwise_error_bsc(weight: usize, p: f64) -> f64 {
+    let mut error = bounded_distance_error(weight, weight / 2, p);
+    if weight.is_multiple_of(2) {
+        error += 0.5 * binomial_pmf(weight, weight / 2, p);
+    }
+    error
+}
+
+/// Union bound for maximum likelihood decoding on the BSC. `weights[w]` is the
+/// number of codewords of weight w.
+pub fn union_bound_bsc(weights: &[f64], p: f64) -> f64 {
+    let bound: f64 = weights
+        .iter()
+        .enumerate()
+        .skip(1)
+        .filter(|(_, count)| **count > 0.0)
+        .map(|(weight, count)| count * pairwise_error_bsc(weight, p))
+        .sum();
+    bound.min(1.0)
+}
+
+/// Union bound for maximum likelihood decoding of soft BPSK or QPSK symbols on AWGN,
+/// at `ebn0_db` per data bit.
+pub fn union_bound_awgn(weights: &[f64], rate: f64, ebn0_db: f64) -> f64 {
+    let ebn0 = 10f64.powf(ebn0_db / 10.0);
+    let bound: f64 = weights
+        .iter()
+        .enumerate()
+        .skip(1)
+        .filter(|(_, count)| **count > 0.0)
+        .map(|(weight, count)| count * q_function((2.0 * weight as f64 * rate * ebn0).sqrt()))
+        .sum();
+    bound.min(1.0)
+}
+
+/* The crossover probability the decoder sees behind a hard decision demapper, at
+ * `ebn0_db` per bit that goes over the channel. None where there is no simple formula.
+ * QPSK puts one bit on each axis, so it's the same as BPSK. For 16-QAM it is the
+ * average over the bits, the outer ones of an axis are safer than the inner ones.
+ */
+pub fn hard_decision_crossover(
+    modulation: Modulation,
+    fading: Fading,
+    ebn0_db: f64,
+) -> Option<f64> {
+    let ebn0 = 10f64.powf(ebn0_db / 10.0);
+    match (modulation, fading) {
+        (Modulation::Bpsk | Modulation::Qpsk, Fading::None) => {
+            Some(q_function((2.0 * ebn0).sqrt()))
+        }
+        (Modulation::Qam16, Fading::None) => {
+            let x = (0.8 * ebn0).sqrt();
+            Some((3.0 * q_function(x) + 2.0 * q_function(3.0 * x) - q_function(5.0 * x)) / 4.0)
+        }
+        (Modulation::Bpsk | Modulation::Qpsk, Fading::Rayleigh) => {
+            Some(0.5 * (1.0 - (ebn0 / (1.0 + ebn0)).sqrt()))
+        }
+        _ => None,
+    }
+}
+
+/* The frame error rate of the decoders as they are written, on the BSC:
+ *
+ *   parity, crc   they only detect, so the frame is wrong when a data bit flips
+ *   vt            a flip keeps the length, so it's only noticed, like parity.
+ *                 The padding after the data has to come through as well
+ *   triple        every bit is a majority of 3, and the length in front has no
+ *                 protection at all
+ *   hamming       corrects 1 error in the whole codeword, fails on 2 or more. A
+ *                 hair high: 2 errors in the check bits can point past the end of a
+ *                 shortened codeword, and then the data comes through right
+ *
+ * None for the convolutional, concatenated and product codes, they get more right than
+ * their distance says.
+ */
+pub fn frame_error_rate(correction_type: &CorrectionType, data_bits: usize, p: f64) -> Option<f64> {
+    match correction_type {
+        CorrectionType::Parity | CorrectionType::Crc => Some(any_flip(data_bits, p)),
+        CorrectionType::Vt => {
+            let length = encoded_length(correction_type, data_bits)?;
+            Some(any_flip(length - vt_check_bits(length), p))
+        }
+        CorrectionType::Triple => {
+            let majority_fails = bounded_distance_error(3, 1, p);
+            let right = TRIPLE_HEADER_BITS as f64 * (-p).ln_1p()
+                + data_bits as f64 * (-majority_fails).ln_1p();
+            Some(-right.exp_m1())
+        }
+        CorrectionType::Hamming => Some(bounded_distance_error(
+            encoded_length(correction_type, data_bits)?,
+            1,
+            p,
+        )),
+        CorrectionType::Convolutional
+        | CorrectionType::Concatenated(_)
+        | CorrectionType::Product(_) => None,
+    }
+}
+
+/* How many codewords of each weight. Parity and triple have a closed form at any
+ * length, the other linear codes are counted by weights.rs while that's quick enough.
+ * A maximum likelihood decoder knows the length of a triple coded message, so its
+ * header is left out.
+ */
+pub fn weight_distribution(correction_type: &CorrectionType, data_bits: usize) -> Option<Vec<f64>> {
+    match correction_type {
+        // Every even weight word of k + 1 bits
+        CorrectionType::Parity => {
+            let n = data_bits + 1;
+            Some(
+                (0..=n)
+                    .map(|weight| {
+                        if weight.is_multiple_of(2) {
+                            ln_binomial(n, weight).exp()
+                        } else {
+                            0.0
+                        }
+                    })
+                    .collect(),
+            )
+        }
+        // j of the data bits set gives weight 3j
+        CorrectionType::Triple => {
+            let mut weights = vec![0.0; 3 * data_bits + 1];
+            for ones in 0..=data_bits {
+                weights[3 * ones] = ln_binomial(data_bits, ones).exp();
+            }
+            Some(weights)
+        }
+        _ => LinearCode::from_encoder(correction_type, data_bits)
+            .and_then(|code| code.weight_distribution())
+            .ok()
+            .map(|weights| weights.iter().map(|count| *count as f64).collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_perfect_codes_meet_the_sphere_packing_bound() {
+        for p in [0.001, 0.01, 0.1] {
+            // Hamming (7, 4) and the (3, 1) repetition code correct 1 error and fill the space
+            let hamming = bounded_distance_error(7, 1, p);
+            assert!((sphere_packing_bound(7, 4, p) - hamming).abs() < 1e-12);
+            let repetition = bounded_distance_error(3, 1, p);
+            assert!((sphere_packing_bound(3, 1, p) - repetition).abs() < 1e-12);
+
+            // Real codes can only do worse
+            let shortened = frame_error_rate(&CorrectionType::Hamming, 64, p).unwrap();
+            assert!(sphere_packing_bound(71, 64, p) <= shortened);
+            let triple = frame_error_rate(&CorrectionType::Triple, 64, p).unwrap();
+            assert!(sphere_packing_bound(9 + 3 * 64, 64, p) <= triple);
+        }
+
+        // 71 coded bits at p = 0.01, 2 errors or more about 15.9% of the time
+        let hamming = frame_error_rate(&CorrectionType::Hamming, 64, 0.01).unwrap();
+        assert!((hamming - 0.1588).abs() < 1e-3, "{}", hamming);
+        // The tail stays accurate where 1 - (1 - x) would round to 0
+        assert!(bounded_distance_error(71, 1, 1e-9) > 2e-15);
+    }
+
+    #[test]
+    fn test_channel_figures() {
+        assert!((q_function(0.0) - 0.5).abs() < 1e-7);
+        assert!((q_function(3.0) - 1.3499e-3).abs() < 1e-6);
+        assert!((q_function(-1.0) - 0.8413).abs() < 1e-4);
+
+        assert!((bsc_capacity(0.11) - 0.5).abs() < 1e-3);
+        assert_eq!(bsc_capacity(0.0), 1.0);
+        assert!((shannon_limit_db(0.5) - 0.0).abs() < 1e-9);
+        assert!((shannon_limit_db(1e-6) + 1.59).abs() < 0.01);
+
+        // The same values the noise tests measure
+        let awgn = hard_decision_crossover(Modulation::Bpsk, Fading::None, 4.0).unwrap();
+        assert!((awgn - 0.0125).abs() < 1e-4, "{}", awgn);
+        let rayleigh = hard_decision_crossover(Modulation::Qpsk, Fading::Rayleigh, 10.0).unwrap();
+        assert!((rayleigh - 0.0233).abs() < 1e-4, "{}", rayleigh);
+        assert_eq!(
+            hard_decision_crossover(Modulation::Bpsk, Fading::Rician(2.0), 4.0),
+            None
+        );
+    }
+
+    #[test]
+    fn test_union_bounds() {
+        let weights = weight_distribution(&CorrectionType::Triple, 4).unwrap();
+        assert_eq!(weights.iter().sum::<f64>().round(), 16.0);
+        assert_eq!(weights[3], 4.0);
+
+        // Maximum likelihood for a repetition code is the majority, and the union of
+        // the 4 single bit events bounds the frame error from above
+        let p = 0.01;
+        let exact = any_flip(4, bounded_distance_error(3, 1, p));
+        let bound = union_bound_bsc(&weights, p);
+        assert!(exact <= bound && bound < 1.1 * exact, "{} {}", exact, bound);
+
+        let parity = weight_distribution(&CorrectionType::Parity, 7).unwrap();
+        assert_eq!(parity.iter().sum::<f64>().round(), 128.0);
+        // Soft decisions at high SNR: the 28 weight 2 words dominate
+        let bound = union_bound_awgn(&parity, 7.0 / 8.0, 8.0);
+        let nearest = 28.0 * q_function((2.0 * 2.0 * 7.0 / 8.0 * 10f64.powf(0.8)).sqrt());
+        assert!(bound >= nearest && bound < 1.1 * nearest);
+    }
+}
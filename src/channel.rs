@@ -0,0 +1,287 @@
+use std::fmt;
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::rate_matching::ERASURE;
+
+/* Channel models for the `channel` proxy. Each one is written on the command line
+ * as name:parameters and they are applied one after the other to every packet:
+ *
+ *   bsc:P            flip every bit with probability P
+ *   burst:LEN:P      with probability P flip LEN bits in a row somewhere
+ *   erasure:P        erase every bit with probability P
+ *   drop:P           lose the whole packet with probability P
+ *   delay:MS         hold the packet back for MS milliseconds
+ *   duplicate:P      deliver the packet twice with probability P
+ */
+
+/// Where the proxy listens by default, senders started with --channel connect here instead.
+pub const CHANNEL_ADDRESS: &str = "127.0.0.1:7070";
+// The proxy can drop packets, so senders talking through it never wait longer than
+// this for an answer. A packet held back for longer counts as lost
+pub const CHANNEL_RESPONSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub fn channel_from_args(args: &[String]) -> bool {
+    args.iter().any(|arg| arg == "--channel")
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChannelModel {
+    BinarySymmetric(f64),
+    Burst { length: usize, probability: f64 },
+    Erasure(f64),
+    Drop(f64),
+    Delay(Duration),
+    Duplicate(f64),
+}
+
+fn parse_probability(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(probability) if (0.0..=1.0).contains(&probability) => Ok(probability),
+        _ => Err(format!("{} is not a probability between 0 and 1", value)),
+    }
+}
+
+impl ChannelModel {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = spec.split(':').collect();
+
+        match parts.as_slice() {
+            ["bsc", p] => Ok(ChannelModel::BinarySymmetric(parse_probability(p)?)),
+            ["burst", length, p] => match length.parse::<usize>() {
+                Ok(length) if length > 0 => Ok(ChannelModel::Burst {
+                    length,
+                    probability: parse_probability(p)?,
+                }),
+                _ => Err(format!("burst length {} has to be at least 1", length)),
+            },
+            ["erasure", p] => Ok(ChannelModel::Erasure(parse_probability(p)?)),
+            ["drop", p] => Ok(ChannelModel::Drop(parse_probability(p)?)),
+            ["delay", ms] => ms
+                .parse::<u64>()
+                .map(|ms| ChannelModel::Delay(Duration::from_millis(ms)))
+                .map_err(|_| format!("{} is not a number of milliseconds", ms)),
+            ["duplicate", p] => Ok(ChannelModel::Duplicate(parse_probability(p)?)),
+            _ => Err(format!("Unknown channel model: {}", spec)),
+        }
+    }
+}
+
+/// Every `--model` on the command line, in order.
+pub fn channel_models_from_args(args: &[String]) -> Result<Vec<ChannelModel>, String> {
+    let mut models = vec![];
+
+    for (index, arg) in args.iter().enumerate() {
+        if arg == "--model" {
+            match args.get(index + 1) {
+                Some(spec) => models.push(ChannelModel::parse(spec)?),
+                None => return Err("--model needs a channel model, like bsc:0.01".to_string()),
+            }
+        }
+    }
+
+    Ok(models)
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ChannelStats {
+    pub packets: usize,
+    pub bits: usize,
+    pub flipped_bits: usize,
+    pub erased_bits: usize,
+    pub bursts: usize,
+    pub dropped: usize,
+    pub duplicated: usize,
+}
+
+impl ChannelStats {
+    pub fn add(&mut self, other: &ChannelStats) {
+        self.packets += other.packets;
+        self.bits += other.bits;
+        self.flipped_bits += other.flipped_bits;
+        self.erased_bits += other.erased_bits;
+        self.bursts += other.bursts;
+        self.dropped += other.dropped;
+        self.duplicated += other.duplicated;
+    }
+}
+
+impl fmt::Display for ChannelStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Packets: {} ({} dropped, {} duplicated)",
+            self.packets, self.dropped, self.duplicated
+        )?;
+        write!(
+            f,
+            "Bits: {} ({} flipped, {} bursts, {} erased)",
+            self.bits, self.flipped_bits, self.bursts, self.erased_bits
+        )
+    }
+}
+
+/// What comes out of the channel for one packet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transmission {
+    pub bits: String,
+    // 0 when the packet was dropped
+    pub copies: usize,
+    pub delay: Duration,
+}
+
+fn flip(bit: char) -> char {
+    if bit == '0' {
+        '1'
+    } else {
+        '0'
+    }
+}
+
+// Erased bits come out as ERASURE, the receiver decides what to do with them
+pub fn transmit(
+    models: &[ChannelModel],
+    bits: &str,
+    rng: &mut impl Rng,
+    stats: &mut ChannelStats,
+) -> Transmission {
+    let mut output: Vec<char> = bits.chars().collect();
+    let mut copies = 1;
+    let mut delay = Duration::ZERO;
+
+    stats.packets += 1;
+    stats.bits += output.len();
+
+    for model in models {
+        match model {
+            ChannelModel::BinarySymmetric(p) => {
+                for bit in output.iter_mut() {
+                    if rng.gen_bool(*p) {
+                        *bit = flip(*bit);
+                        stats.flipped_bits += 1;
+                    }
+                }
+            }
+            ChannelModel::Burst {
+                length,
+                probability,
+            } => {
+                if !output.is_empty() && rng.gen_bool(*probability) {
+                    let length = (*length).min(output.len());
+                    let start = rng.gen_range(0..=output.len() - length);
+                    for bit in &mut output[start..start + length] {
+                        *bit = flip(*bit);
+                    }
+                    stats.flipped_bits += length;
+                    stats.bursts += 1;
+                }
+            }
+            ChannelModel::Erasure(p) => {
+                for bit in output.iter_mut() {
+                    if rng.gen_bool(*p) {
+                        *bit = ERASURE;
+                        stats.erased_bits += 1;
+                    }
+                }
+            }
+            ChannelModel::Drop(p) => {
+                if copies > 0 && rng.gen_bool(*p) {
+                    copies = 0;
+                    stats.dropped += 1;
+                }
+            }
+            ChannelModel::Delay(duration) => delay += *duration,
+            ChannelModel::Duplicate(p) => {
+                if copies > 0 && rng.gen_bool(*p) {
+                    copies += 1;
+                    stats.duplicated += 1;
+                }
+            }
+        }
+    }
+
+    let output: String = output.into_iter().collect();
+    Transmission {
+        bits: output,
+        copies,
+        delay,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_parse_channel_models() {
+        assert_eq!(
+            ChannelModel::parse("burst:8:0.5"),
+            Ok(ChannelModel::Burst {
+                length: 8,
+                probability: 0.5
+            })
+        );
+        assert_eq!(
+            ChannelModel::parse("delay:20"),
+            Ok(ChannelModel::Delay(Duration::from_millis(20)))
+        );
+        assert!(ChannelModel::parse("bsc:1.5").is_err());
+        assert!(ChannelModel::parse("gaussian:0.1").is_err());
+
+        let args: Vec<String> = ["channel", "--model", "bsc:0.1", "--model", "drop:0"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        assert_eq!(
+            channel_models_from_args(&args),
+            Ok(vec![
+                ChannelModel::BinarySymmetric(0.1),
+                ChannelModel::Drop(0.0)
+            ])
+        );
+    }
+
+    #[test]
+    fn test_transmit_certain_events() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut stats = ChannelStats::default();
+
+        let models = [
+            ChannelModel::BinarySymmetric(1.0),
+            ChannelModel::Duplicate(1.0),
+        ];
+        let transmission = transmit(&models, "1100", &mut rng, &mut stats);
+        assert_eq!(transmission.bits, "0011");
+        assert_eq!(transmission.copies, 2);
+
+        let models = [ChannelModel::Erasure(1.0), ChannelModel::Drop(1.0)];
+        let transmission = transmit(&models, "1111", &mut rng, &mut stats);
+        assert_eq!(transmission.bits, "????");
+        assert_eq!(transmission.copies, 0);
+
+        assert_eq!(stats.packets, 2);
+        assert_eq!(stats.flipped_bits, 4);
+        assert_eq!(stats.erased_bits, 4);
+        assert_eq!((stats.dropped, stats.duplicated), (1, 1));
+    }
+
+    #[test]
+    fn test_burst_flips_consecutive_bits() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut stats = ChannelStats::default();
+        let models = [ChannelModel::Burst {
+            length: 3,
+            probability: 1.0,
+        }];
+
+        let bits = "0".repeat(20);
+        let transmission = transmit(&models, &bits, &mut rng, &mut stats);
+        let start = transmission.bits.find('1').unwrap();
+        assert_eq!(&transmission.bits[start..start + 3], "111");
+        assert_eq!(transmission.bits.matches('1').count(), 3);
+        assert_eq!(stats.bursts, 1);
+    }
+}
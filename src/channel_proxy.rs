@@ -0,0 +1,240 @@
+use std::io::{self, ErrorKind};
+use std::net::Shutdown;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use channel::{channel_models_from_args, transmit, ChannelModel, ChannelStats, CHANNEL_ADDRESS};
+use endpoint::{endpoint_from_args, Endpoint, Listener, Stream, DEFAULT_RECEIVER_ADDRESS};
+use noise::seed_from_args;
+use protocol::{
+    pack_bits, read_packet, unpack_bits, write_packet, Packet, PacketDecoder, PacketType,
+};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use script::EXIT_USAGE;
+
+pub mod arq;
+pub mod channel;
+pub mod correction;
+pub mod endpoint;
+pub mod harq;
+pub mod modulation;
+pub mod noise;
+pub mod protocol;
+pub mod rate_matching;
+pub mod report;
+pub mod script;
+pub mod stats;
+
+/* The noisy channel as its own process: senders started with --channel connect
+ * here and send clean packets, this forwards them to the receiver after putting
+ * the payload bits through the channel models. Only packets carrying coded bits
+ * are touched, the handshake and the table get through as they are, and so does
+ * everything the receiver sends back.
+ */
+
+const DEFAULT_MODEL: ChannelModel = ChannelModel::BinarySymmetric(0.01);
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// For packets carrying coded bits, how many bytes in front of the bits (the message
+// id, and the round for HARQ) get through as they are
+fn coded_bits_offset(packet_type: PacketType) -> Option<usize> {
+    match packet_type {
+        PacketType::Data => Some(1),
+        PacketType::HarqIncrementalRedundancy | PacketType::HarqChaseCombining => Some(2),
+        PacketType::Arq => Some(0),
+        _ => None,
+    }
+}
+
+// Writes every packet to the receiver once it is due. They come in the order they
+// are due, so waiting for each in turn holds none back longer than its own delay
+fn deliver(mut receiver: Stream, scheduled: Receiver<(Instant, Packet)>) {
+    for (due, packet) in scheduled {
+        thread::sleep(due.saturating_duration_since(Instant::now()));
+        if write_packet(&mut receiver, &packet).is_err() {
+            return;
+        }
+    }
+    let _ = receiver.shutdown(Shutdown::Write);
+}
+
+fn forward(
+    mut sender: Stream,
+    receiver: Stream,
+    models: &[ChannelModel],
+    seed: u64,
+) -> ChannelStats {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut decoder = PacketDecoder::new();
+    let mut stats = ChannelStats::default();
+
+    let (schedule, scheduled) = mpsc::channel();
+    let delivery = thread::spawn(move || deliver(receiver, scheduled));
+    // Nothing is due before the packet in front of it, the stream keeps them in order
+    let mut last_due = Instant::now();
+
+    while let Ok(Some(packet)) = read_packet(&mut sender, &mut decoder) {
+        let offset = coded_bits_offset(packet.packet_type)
+            .filter(|&offset| packet.payload.len() >= offset);
+        let (packet, copies, delay) = match offset {
+            None => (packet, 1, Duration::ZERO),
+            Some(offset) => {
+                let (fields, packed_bits) = packet.payload.split_at(offset);
+                let transmission =
+                    transmit(models, &unpack_bits(packed_bits), &mut rng, &mut stats);
+                let mut payload = fields.to_vec();
+                payload.extend(pack_bits(&transmission.bits));
+                let noisy = Packet::new(packet.packet_type, packet.code, payload);
+                (noisy, transmission.copies, transmission.delay)
+            }
+        };
+
+        last_due = last_due.max(Instant::now() + delay);
+        // Only fails once the receiver is gone
+        if (0..copies).any(|_| schedule.send((last_due, packet.clone())).is_err()) {
+            break;
+        }
+    }
+
+    // Whatever is still held back goes out before the receiver's side is closed
+    drop(schedule);
+    let _ = delivery.join();
+    stats
+}
+
+fn handle_sender(
+    sender: Stream,
+    receiver: &Endpoint,
+    models: &[ChannelModel],
+    seed: u64,
+) -> io::Result<ChannelStats> {
+    let receiver = Stream::connect(receiver)?;
+
+    // Answers from the receiver go straight back
+    let mut from_receiver = receiver.try_clone()?;
+    let mut to_sender = sender.try_clone()?;
+    let backward = thread::spawn(move || {
+        let _ = io::copy(&mut from_receiver, &mut to_sender);
+        let _ = to_sender.shutdown(Shutdown::Write);
+    });
+
+    let stats = forward(sender, receiver, models, seed);
+    let _ = backward.join();
+    Ok(stats)
+}
+
+struct Connection {
+    // Kept to unblock the forwarding thread's read on shutdown
+    sender: Stream,
+    handle: JoinHandle<()>,
+}
+
+fn main() -> io::Result<ExitCode> {
+    let args: Vec<String> = std::env::args().collect();
+    let mut models = match channel_models_from_args(&args) {
+        Ok(models) => models,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Ok(ExitCode::from(EXIT_USAGE));
+        }
+    };
+    if models.is_empty() {
+        models.push(DEFAULT_MODEL);
+    }
+    let models = Arc::new(models);
+    let seed = match seed_from_args(&args) {
+        Ok(seed) => seed.unwrap_or_else(rand::random),
+        Err(e) => {
+            eprintln!("{}", e);
+            return Ok(ExitCode::from(EXIT_USAGE));
+        }
+    };
+
+    let listen = endpoint_from_args(&args, "--listen", "CHANNEL_LISTEN", CHANNEL_ADDRESS);
+    let connect = endpoint_from_args(
+        &args,
+        "--connect",
+        "CHANNEL_CONNECT",
+        DEFAULT_RECEIVER_ADDRESS,
+    );
+    let (listen, connect) = match (listen, connect) {
+        (Ok(listen), Ok(connect)) => (listen, Arc::new(connect)),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("{}", e);
+            return Ok(ExitCode::from(EXIT_USAGE));
+        }
+    };
+
+    let shutdown = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&shutdown))?;
+
+    let listener = Listener::bind(&listen)?;
+    listener.set_nonblocking(true)?;
+    println!(
+        "Channel is listening on {}, forwarding to {} through {:?}",
+        listen, connect, models
+    );
+    println!("Noise seed: {}", seed);
+
+    let total_stats = Arc::new(Mutex::new(ChannelStats::default()));
+
+    let mut senders: u64 = 0;
+    let mut connections: Vec<Connection> = vec![];
+    while !shutdown.load(Ordering::Relaxed) {
+        connections.retain(|connection| !connection.handle.is_finished());
+
+        let sender = match listener.accept() {
+            Ok(sender) => sender,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+                continue;
+            }
+            Err(e) => {
+                println!("Connection failed: {}", e);
+                continue;
+            }
+        };
+        sender.set_nonblocking(false)?;
+
+        println!("New sender, connecting it to the receiver");
+        let models = Arc::clone(&models);
+        let connect = Arc::clone(&connect);
+        let total_stats = Arc::clone(&total_stats);
+        // The n-th sender gets the same noise every time the proxy is started with --seed
+        let sender_seed = seed.wrapping_add(senders);
+        senders += 1;
+        let handle = match sender.try_clone() {
+            Ok(handler_sender) => thread::spawn(move || {
+                match handle_sender(handler_sender, &connect, &models, sender_seed) {
+                    Ok(stats) => {
+                        println!("Sender left:\n{}", stats);
+                        total_stats.lock().unwrap().add(&stats);
+                    }
+                    Err(e) => println!("Could not reach the receiver: {}", e),
+                }
+            }),
+            Err(e) => {
+                println!("Connection failed: {}", e);
+                continue;
+            }
+        };
+        connections.push(Connection { sender, handle });
+    }
+
+    // Their statistics only get added up once they are done
+    println!("Shutting down, closing {} sender(s)", connections.len());
+    for connection in connections {
+        let _ = connection.sender.shutdown(Shutdown::Both);
+        let _ = connection.handle.join();
+    }
+
+    println!("Channel statistics over all senders:");
+    println!("{}", total_stats.lock().unwrap());
+
+    Ok(ExitCode::SUCCESS)
+}
@@ -1,10 +1,94 @@
+#[derive(Clone, Debug, PartialEq)]
 pub enum CorrectionType {
     Parity,
     Triple,
     Hamming,
+    Crc,
+    Vt,
+    Convolutional,
+    Concatenated(Box<ConcatenatedCode>),
+    Product(Box<ProductCode>),
 }
 
-pub fn encode_parity_bit(encoded_string: &String) -> (bool, String) {
+/// Chains two codes: the outer code is applied first, its output is optionally
+/// spread out by a block interleaver, and the inner code protects the result.
+/// On decode the inner code cleans up what it can and the outer code fixes the rest.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConcatenatedCode {
+    pub outer: CorrectionType,
+    pub interleaver_depth: Option<usize>,
+    pub inner: CorrectionType,
+}
+
+impl ConcatenatedCode {
+    pub fn new(
+        outer: CorrectionType,
+        interleaver_depth: Option<usize>,
+        inner: CorrectionType,
+    ) -> Self {
+        ConcatenatedCode {
+            outer,
+            interleaver_depth,
+            inner,
+        }
+    }
+}
+
+impl Default for ConcatenatedCode {
+    // Hamming outer, depth 8 interleaver, TPC inner: the pipeline the sender offers
+    fn default() -> Self {
+        ConcatenatedCode::new(CorrectionType::Hamming, Some(8), CorrectionType::Triple)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StageStats {
+    pub found_errors: bool,
+    pub corrected_bits: usize,
+}
+
+/// How many payload bits each layer of a concatenated code flipped back.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConcatenatedStats {
+    pub inner: StageStats,
+    pub outer: StageStats,
+}
+
+/// 2D product code: the message is laid out in rows of `row_length` bits, every row
+/// gets the row code's check bits, then every column of that gets the column code's.
+/// Both component codes have to be linear block codes (Parity or Hamming), so that
+/// the check rows are themselves valid row codewords.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProductCode {
+    pub row: CorrectionType,
+    pub column: CorrectionType,
+    pub row_length: usize,
+}
+
+impl ProductCode {
+    pub fn new(row: CorrectionType, column: CorrectionType, row_length: usize) -> Self {
+        ProductCode {
+            row,
+            column,
+            row_length,
+        }
+    }
+}
+
+impl Default for ProductCode {
+    // Row/column parity over 8 bit rows: corrects any single error
+    fn default() -> Self {
+        ProductCode::new(CorrectionType::Parity, CorrectionType::Parity, 8)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProductStats {
+    pub iterations: usize,
+    pub corrected_bits: usize,
+}
+
+pub fn encode_parity_bit(encoded_string: &str) -> (bool, String) {
     let mut count_of_ones = 0;
 
     for char in encoded_string.chars() {
@@ -14,13 +98,13 @@ pub fn encode_parity_bit(encoded_string: &String) -> (bool, String) {
     }
 
     if count_of_ones % 2 == 0 {
-        return (true, format!("0{}", encoded_string));
+        (true, format!("0{}", encoded_string))
     } else {
-        return (true, format!("1{}", encoded_string));
+        (true, format!("1{}", encoded_string))
     }
 }
 
-pub fn decode_parity_bit(encoded_string: &String) -> (bool, bool, String, String) {
+pub fn decode_parity_bit(encoded_string: &str) -> (bool, bool, String, String) {
     let mut count_of_ones = 0;
 
     for char in encoded_string.chars() {
@@ -30,13 +114,13 @@ pub fn decode_parity_bit(encoded_string: &String) -> (bool, bool, String, String
     }
 
     if count_of_ones % 2 == 0 {
-        return (false, false, "".to_string(), "".to_string());
+        (false, false, "".to_string(), "".to_string())
     } else {
-        return (true, false, "".to_string(), "".to_string());
+        (true, false, "".to_string(), "".to_string())
     }
 }
 
-pub fn encode_triple(encoded_string: &String) -> (bool, String) {
+pub fn encode_triple(encoded_string: &str) -> (bool, String) {
     let message_length = encoded_string.len();
 
     if message_length >= 511 {
@@ -49,12 +133,21 @@ pub fn encode_triple(encoded_string: &String) -> (bool, String) {
     let encoded_string = encoded_string.repeat(3);
     let mut binary_string = format!("{:0>width$b}", message_length, width = 9);
     binary_string.push_str(&encoded_string);
-    return (true, binary_string);
+    (true, binary_string)
 }
 
-pub fn decode_triple(encoded_string: &String) -> (bool, bool, String, String) {
-    let first_nine_bits = &encoded_string[..9];
-    let length_of_message = usize::from_str_radix(first_nine_bits, 2).unwrap();
+// The message length from the 9 bit header, None when it is cut short or has erased bits
+fn triple_header(encoded_string: &str) -> Option<usize> {
+    encoded_string
+        .get(..9)
+        .and_then(|first_nine_bits| usize::from_str_radix(first_nine_bits, 2).ok())
+}
+
+pub fn decode_triple(encoded_string: &str) -> (bool, bool, String, String) {
+    // A header like that leaves us nothing to go on
+    let Some(length_of_message) = triple_header(encoded_string) else {
+        return (true, false, "".to_string(), "".to_string());
+    };
 
     if encoded_string.len() < 9 + length_of_message * 3 {
         println!(
@@ -90,7 +183,8 @@ pub fn decode_triple(encoded_string: &String) -> (bool, bool, String, String) {
             + (second_chunk_char == '1') as u32
             + (third_chunk_char == '1') as u32;
 
-        if !(count_0 == 3 || count_1 == 3) {
+        // Erased copies ('?') count for neither side, they are not errors
+        if count_0 > 0 && count_1 > 0 {
             num_errors += 1;
         }
 
@@ -100,15 +194,15 @@ pub fn decode_triple(encoded_string: &String) -> (bool, bool, String, String) {
     let final_string: String = first_chunk.into_iter().collect();
     let original_string: String = original_message.into_iter().collect();
 
-    return (
+    (
         num_errors > 0,
         num_errors > 0,
         original_string,
         final_string,
-    );
+    )
 }
 
-pub fn encode_hamming(encoded_string: &String, is_extended: bool) -> (bool, String) {
+pub fn encode_hamming(encoded_string: &str, _is_extended: bool) -> (bool, String) {
     let message_length = encoded_string.len();
 
     if message_length >= 502 {
@@ -152,10 +246,10 @@ pub fn encode_hamming(encoded_string: &String, is_extended: bool) -> (bool, Stri
 
     let final_code: String = hamming_code.into_iter().collect();
 
-    return (true, final_code);
+    (true, final_code)
 }
 
-pub fn decode_hamming(received_code: &String, is_extended: bool) -> (bool, bool, String, String) {
+pub fn decode_hamming(received_code: &str, is_extended: bool) -> (bool, bool, String, String) {
     let mut hamming_code: Vec<char> = received_code.chars().collect();
     let mut received_overall_parity: bool = false;
     if is_extended {
@@ -183,7 +277,7 @@ pub fn decode_hamming(received_code: &String, is_extended: bool) -> (bool, bool,
 
         for bit in (parity_pos..=total_length).step_by(2 * parity_pos) {
             for k in 0..parity_pos {
-                if bit + k - 1 <= total_length - 1 {
+                if bit + k - 1 < total_length {
                     parity ^= (hamming_code[bit + k - 1] as u8 - b'0') as i32;
                 }
             }
@@ -194,7 +288,6 @@ pub fn decode_hamming(received_code: &String, is_extended: bool) -> (bool, bool,
         }
     }
     let mut has_double_error = false;
-    let mut corrected_string = "".to_string();
 
     if curr_error_position > 0 {
         let idx = curr_error_position - 1;
@@ -216,7 +309,7 @@ pub fn decode_hamming(received_code: &String, is_extended: bool) -> (bool, bool,
             extracted_data.push(hamming_code[i - 1]);
         }
     }
-    corrected_string = extracted_data;
+    let corrected_string = extracted_data;
 
     (
         has_error,
@@ -225,26 +318,642 @@ pub fn decode_hamming(received_code: &String, is_extended: bool) -> (bool, bool,
         corrected_string,
     )
 }
-pub fn encode_correction(
+/* CRC-8 with polynomial x^8 + x^2 + x + 1. Like the parity bit it only detects
+ * errors, but it catches every burst up to 8 bits long and all odd numbers of flips.
+ * The 8 check bits go at the end of the message.
+ */
+const CRC_POLYNOMIAL: u8 = 0x07;
+const CRC_BITS: usize = 8;
+
+pub fn crc8(bits: &str) -> u8 {
+    let mut register: u8 = 0;
+
+    for char in bits.chars() {
+        let top_bit = (register >> 7) ^ (char == '1') as u8;
+        register <<= 1;
+        if top_bit == 1 {
+            register ^= CRC_POLYNOMIAL;
+        }
+    }
+
+    register
+}
+
+pub fn encode_crc(encoded_string: &str) -> (bool, String) {
+    let checksum = crc8(encoded_string);
+    (
+        true,
+        format!("{}{:0>width$b}", encoded_string, checksum, width = CRC_BITS),
+    )
+}
+
+pub fn decode_crc(encoded_string: &str) -> (bool, bool, String, String) {
+    if encoded_string.len() < CRC_BITS {
+        return (true, false, "".to_string(), "".to_string());
+    }
+
+    let (message, checksum_bits) = encoded_string.split_at(encoded_string.len() - CRC_BITS);
+    let checksum = u8::from_str_radix(checksum_bits, 2).unwrap_or(0);
+    let has_error = crc8(message) != checksum;
+
+    (has_error, false, message.to_string(), message.to_string())
+}
+
+/* Varshamov-Tenengolts code: a codeword x_1 .. x_n has sum(i * x_i) = 0 mod n + 1,
+ * which is enough to put back one bit that got lost or added anywhere in it. Every
+ * other code here finds its blocks by position and loses track after that.
+ * The check bits sit at the powers of two like in Hamming and spell out in binary
+ * what the data bits leave the sum short of. Codewords are always a multiple of 3
+ * long, so the length alone tells the receiver whether a bit went missing or got
+ * added. To get there the data is padded with a 1 and then 0s, which come off again
+ * after decoding. A flipped bit keeps the length, the code only notices it.
+ */
+pub fn vt_check_bits(length: usize) -> usize {
+    (usize::BITS - length.leading_zeros()) as usize
+}
+
+fn vt_length(data_length: usize) -> usize {
+    let mut length = 3;
+    while length - vt_check_bits(length) < data_length + 1 {
+        length += 3;
+    }
+    length
+}
+
+fn vt_sum(bits: &[char], modulus: usize) -> usize {
+    bits.iter()
+        .enumerate()
+        .filter(|(_, bit)| **bit == '1')
+        .fold(0, |sum, (index, _)| (sum + index + 1) % modulus)
+}
+
+// The data bits without the padding
+fn vt_data(codeword: &[char]) -> String {
+    let data: String = codeword
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| !(index + 1).is_power_of_two())
+        .map(|(_, bit)| bit)
+        .collect();
+    let data = data.trim_end_matches('0');
+    data.strip_suffix('1').unwrap_or(data).to_string()
+}
+
+pub fn encode_vt(encoded_string: &str) -> (bool, String) {
+    let length = vt_length(encoded_string.len());
+    let mut data = format!("{}1", encoded_string);
+    data.push_str(&"0".repeat(length - vt_check_bits(length) - data.len()));
+
+    let mut data_bits = data.chars();
+    let mut codeword: Vec<char> = (1..=length)
+        .map(|position| {
+            if position.is_power_of_two() {
+                '0'
+            } else {
+                data_bits.next().unwrap_or('0')
+            }
+        })
+        .collect();
+
+    let missing = (length + 1 - vt_sum(&codeword, length + 1)) % (length + 1);
+    for check in 0..vt_check_bits(length) {
+        if (missing >> check) & 1 == 1 {
+            codeword[(1 << check) - 1] = '1';
+        }
+    }
+
+    (true, codeword.into_iter().collect())
+}
+
+/* Levenshtein's decoder. With w ones left and the sum short by d of a codeword's:
+ * d <= w means a 0 went missing, with d ones to the right of it, otherwise a 1 went
+ * missing, with d - w - 1 zeros to the left of it. Anywhere in the run of equal bits
+ * there gives the same codeword.
+ */
+fn vt_restore_deleted(received: &[char]) -> Vec<char> {
+    let length = received.len() + 1;
+    let ones = received.iter().filter(|bit| **bit == '1').count();
+    let missing = (length + 1 - vt_sum(received, length + 1)) % (length + 1);
+    let mut restored = received.to_vec();
+
+    if missing <= ones {
+        let (mut position, mut ones_right) = (received.len(), 0);
+        while ones_right < missing {
+            position -= 1;
+            if received[position] == '1' {
+                ones_right += 1;
+            }
+        }
+        restored.insert(position, '0');
+    } else {
+        let (mut position, mut zeros_left) = (0, 0);
+        while zeros_left < missing - ones - 1 {
+            if received[position] == '0' {
+                zeros_left += 1;
+            }
+            position += 1;
+        }
+        restored.insert(position, '1');
+    }
+
+    restored
+}
+
+// Only one codeword is a single insertion away, so the first bit whose removal gives one will do
+fn vt_remove_inserted(received: &[char]) -> Option<Vec<char>> {
+    let length = received.len() - 1;
+    (0..received.len()).find_map(|position| {
+        let mut candidate = received.to_vec();
+        candidate.remove(position);
+        (vt_sum(&candidate, length + 1) == 0).then_some(candidate)
+    })
+}
+
+pub fn decode_vt(encoded_string: &str) -> (bool, bool, String, String) {
+    let received: Vec<char> = encoded_string.chars().collect();
+    // What the data would be taking the bits as they came
+    let original = vt_data(&received);
+
+    match received.len() % 3 {
+        0 => {
+            let has_error = vt_sum(&received, received.len() + 1) != 0;
+            (has_error, false, original.clone(), original)
+        }
+        // One short
+        2 => {
+            let restored = vt_restore_deleted(&received);
+            (true, true, original, vt_data(&restored))
+        }
+        // One long
+        _ => match vt_remove_inserted(&received) {
+            Some(restored) => (true, true, original, vt_data(&restored)),
+            None => (true, false, original.clone(), original),
+        },
+    }
+}
+
+/* Convolutional code, rate 1/2 with constraint length 3 (generators 7 and 5 in
+ * octal). Every data bit comes out as two bits: the bit plus the two before it, and
+ * the bit plus the one two back. Two 0s go in after the data so the encoder ends
+ * up back in state 0 and the decoder knows where the path ends.
+ * The Viterbi decoder looks for the data whose output differs from what came in by
+ * the fewest bits, which fixes any 2 flips that are a few bits apart (free distance 5).
+ * Erased bits ('?') count for neither branch, so a punctured codeword can go
+ * straight into the decoder without filling them in first.
+ */
+const CONVOLUTIONAL_MEMORY: usize = 2;
+const CONVOLUTIONAL_STATES: usize = 1 << CONVOLUTIONAL_MEMORY;
+
+// The state holds the previous bit in bit 0 and the one before that in bit 1
+fn convolutional_output(state: usize, bit: usize) -> [char; 2] {
+    let (previous, older) = (state & 1, state >> 1);
+    let to_char = |value: usize| if value == 1 { '1' } else { '0' };
+    [to_char(bit ^ previous ^ older), to_char(bit ^ older)]
+}
+
+fn convolutional_next_state(state: usize, bit: usize) -> usize {
+    ((state << 1) | bit) & (CONVOLUTIONAL_STATES - 1)
+}
+
+pub fn encode_convolutional(encoded_string: &str) -> (bool, String) {
+    let data = format!("{}{}", encoded_string, "0".repeat(CONVOLUTIONAL_MEMORY));
+    let mut state = 0;
+    let mut codeword = String::new();
+
+    for char in data.chars() {
+        let bit = (char == '1') as usize;
+        codeword.extend(convolutional_output(state, bit));
+        state = convolutional_next_state(state, bit);
+    }
+
+    (true, codeword)
+}
+
+pub fn decode_convolutional(encoded_string: &str) -> (bool, bool, String, String) {
+    let received: Vec<char> = encoded_string.chars().collect();
+    if !received.len().is_multiple_of(2) || received.len() < 2 * CONVOLUTIONAL_MEMORY {
+        return (true, false, "".to_string(), "".to_string());
+    }
+    let steps = received.len() / 2;
+    let data_length = steps - CONVOLUTIONAL_MEMORY;
+
+    // The two outputs added up give back the data bit one step earlier
+    let original: String = (1..=data_length)
+        .map(|step| {
+            if (received[2 * step] == '1') != (received[2 * step + 1] == '1') {
+                '1'
+            } else {
+                '0'
+            }
+        })
+        .collect();
+
+    // Fewest differences to get to every state, and which state and bit led there
+    let mut distances: [Option<usize>; CONVOLUTIONAL_STATES] = [None; CONVOLUTIONAL_STATES];
+    distances[0] = Some(0);
+    let mut history: Vec<[(usize, usize); CONVOLUTIONAL_STATES]> = Vec::with_capacity(steps);
+
+    for step in 0..steps {
+        let pair = &received[2 * step..2 * step + 2];
+        // Only 0s go in after the data
+        let last_bit = if step < data_length { 1 } else { 0 };
+        let mut next_distances = [None; CONVOLUTIONAL_STATES];
+        let mut came_from = [(0, 0); CONVOLUTIONAL_STATES];
+
+        for (state, distance) in distances.iter().enumerate() {
+            let Some(distance) = distance else { continue };
+            for bit in 0..=last_bit {
+                let differences = convolutional_output(state, bit)
+                    .iter()
+                    .zip(pair)
+                    .filter(|(expected, got)| matches!(got, '0' | '1') && expected != got)
+                    .count();
+                let next_state = convolutional_next_state(state, bit);
+                if next_distances[next_state].is_none_or(|best| distance + differences < best) {
+                    next_distances[next_state] = Some(distance + differences);
+                    came_from[next_state] = (state, bit);
+                }
+            }
+        }
+
+        distances = next_distances;
+        history.push(came_from);
+    }
+
+    let mut state = 0;
+    let mut bits = Vec::with_capacity(steps);
+    for came_from in history.iter().rev() {
+        let (previous_state, bit) = came_from[state];
+        bits.push(if bit == 1 { '1' } else { '0' });
+        state = previous_state;
+    }
+    let decoded: String = bits.into_iter().rev().take(data_length).collect();
+
+    let has_error = distances[0].is_some_and(|distance| distance > 0);
+    (has_error, has_error, original, decoded)
+}
+
+/* Block interleaver: write the bits row by row into a matrix with `depth` rows,
+ * read them out column by column. A burst of up to `depth` consecutive errors on
+ * the wire lands in different rows after deinterleaving.
+ * The last column may be short, those cells are just skipped.
+ */
+fn interleave_order(length: usize, depth: usize) -> Vec<usize> {
+    let depth = depth.max(1);
+    let columns = length.div_ceil(depth);
+    let mut order = Vec::with_capacity(length);
+
+    for column in 0..columns {
+        for row in 0..depth {
+            let index = row * columns + column;
+            if index < length {
+                order.push(index);
+            }
+        }
+    }
+
+    order
+}
+
+pub fn interleave(bits: &str, depth: usize) -> String {
+    let chars: Vec<char> = bits.chars().collect();
+    interleave_order(chars.len(), depth)
+        .into_iter()
+        .map(|index| chars[index])
+        .collect()
+}
+
+pub fn deinterleave(bits: &str, depth: usize) -> String {
+    let chars: Vec<char> = bits.chars().collect();
+    let mut output = vec!['0'; chars.len()];
+
+    for (position, index) in interleave_order(chars.len(), depth).into_iter().enumerate() {
+        output[index] = chars[position];
+    }
+
+    output.into_iter().collect()
+}
+
+// Every code here has a codeword length that only depends on the message length.
+// None when the code can't encode a message that long
+pub fn encoded_length(correction_type: &CorrectionType, message_length: usize) -> Option<usize> {
+    let (ok, codeword) = encode_correction(correction_type, &"0".repeat(message_length));
+    ok.then_some(codeword.len())
+}
+
+fn count_differences(first: &str, second: &str) -> usize {
+    first
+        .chars()
+        .zip(second.chars())
+        .filter(|(a, b)| a != b)
+        .count()
+}
+
+// Like decode_correction, but parity hands back the payload so a later stage can use it
+pub fn decode_stage(
     correction_type: &CorrectionType,
-    encoded_string: &String,
-) -> (bool, String) {
+    encoded_string: &str,
+) -> (bool, bool, String, String) {
+    match correction_type {
+        CorrectionType::Parity => {
+            let (has_error, _, _, _) = decode_parity_bit(encoded_string);
+            let payload = encoded_string.get(1..).unwrap_or("").to_string();
+            (has_error, false, payload.clone(), payload)
+        }
+        // Noise in the header can ask for more bits than there are. Just a frame
+        // that failed for the stage, not worth an error message on every one
+        CorrectionType::Triple => match triple_header(encoded_string) {
+            Some(length) if encoded_string.len() >= 9 + length * 3 => decode_triple(encoded_string),
+            _ => (true, false, "".to_string(), "".to_string()),
+        },
+        CorrectionType::Hamming => decode_hamming(encoded_string, false),
+        CorrectionType::Crc => decode_crc(encoded_string),
+        CorrectionType::Vt => decode_vt(encoded_string),
+        CorrectionType::Convolutional => decode_convolutional(encoded_string),
+        CorrectionType::Concatenated(code) => {
+            let (result, _) = decode_concatenated(code, encoded_string);
+            result
+        }
+        CorrectionType::Product(code) => {
+            let (result, _) = decode_product(code, encoded_string);
+            result
+        }
+    }
+}
+
+pub fn encode_concatenated(code: &ConcatenatedCode, encoded_string: &str) -> (bool, String) {
+    let (ok, outer_encoded) = encode_correction(&code.outer, encoded_string);
+    if !ok {
+        return (false, outer_encoded);
+    }
+
+    let interleaved = match code.interleaver_depth {
+        Some(depth) => interleave(&outer_encoded, depth),
+        None => outer_encoded,
+    };
+
+    encode_correction(&code.inner, &interleaved)
+}
+
+pub fn decode_concatenated(
+    code: &ConcatenatedCode,
+    encoded_string: &str,
+) -> ((bool, bool, String, String), ConcatenatedStats) {
+    let inner = decode_stage(&code.inner, encoded_string);
+
+    let (inner_original, inner_corrected) = match code.interleaver_depth {
+        Some(depth) => (deinterleave(&inner.2, depth), deinterleave(&inner.3, depth)),
+        None => (inner.2.clone(), inner.3.clone()),
+    };
+
+    let outer = decode_stage(&code.outer, &inner_corrected);
+    // What the message would have been if neither layer had corrected anything
+    let uncorrected = decode_stage(&code.outer, &inner_original);
+
+    let stats = ConcatenatedStats {
+        inner: StageStats {
+            found_errors: inner.0,
+            corrected_bits: count_differences(&inner.2, &inner.3),
+        },
+        outer: StageStats {
+            found_errors: outer.0,
+            corrected_bits: count_differences(&outer.2, &outer.3),
+        },
+    };
+
+    let has_error = inner.0 || outer.0;
+    let outer_gave_up = outer.0 && !outer.1;
+
+    (
+        (
+            has_error,
+            has_error && !outer_gave_up,
+            uncorrected.2,
+            outer.3,
+        ),
+        stats,
+    )
+}
+
+const PRODUCT_MAX_ITERATIONS: usize = 8;
+
+pub fn is_product_component(correction_type: &CorrectionType) -> bool {
+    matches!(
+        correction_type,
+        CorrectionType::Parity | CorrectionType::Hamming
+    )
+}
+
+fn flip_char(bit: char) -> char {
+    if bit == '0' {
+        '1'
+    } else {
+        '0'
+    }
+}
+
+pub fn encode_product(code: &ProductCode, encoded_string: &str) -> (bool, String) {
+    if !is_product_component(&code.row) || !is_product_component(&code.column) {
+        return (
+            false,
+            "Product codes need Parity or Hamming as components".to_string(),
+        );
+    }
+    if code.row_length == 0 {
+        return (false, "Row length has to be at least 1".to_string());
+    }
+
+    // Pad with a 1 and then 0s to fill the last row, the decoder strips back to the last 1
+    let mut padded = format!("{}1", encoded_string);
+    while padded.len() % code.row_length != 0 {
+        padded.push('0');
+    }
+
+    let mut rows: Vec<Vec<char>> = vec![];
+    for chunk in padded.as_bytes().chunks(code.row_length) {
+        let (ok, row) = encode_correction(&code.row, std::str::from_utf8(chunk).unwrap());
+        if !ok {
+            return (false, row);
+        }
+        rows.push(row.chars().collect());
+    }
+
+    let row_codeword_length = rows[0].len();
+    let mut columns: Vec<Vec<char>> = vec![];
+    for column in 0..row_codeword_length {
+        let bits: String = rows.iter().map(|row| row[column]).collect();
+        let (ok, encoded_column) = encode_correction(&code.column, &bits);
+        if !ok {
+            return (false, encoded_column);
+        }
+        columns.push(encoded_column.chars().collect());
+    }
+
+    // Send the matrix row by row
+    let mut output = String::new();
+    for row in 0..columns[0].len() {
+        for column in &columns {
+            output.push(column[row]);
+        }
+    }
+
+    (true, output)
+}
+
+pub fn decode_product(
+    code: &ProductCode,
+    encoded_string: &str,
+) -> ((bool, bool, String, String), ProductStats) {
+    let mut stats = ProductStats::default();
+    let failed = (true, false, "".to_string(), "".to_string());
+
+    let row_codeword_length = encoded_length(&code.row, code.row_length).unwrap_or(0);
+    if row_codeword_length == 0 || !encoded_string.len().is_multiple_of(row_codeword_length) {
+        println!("Error: Product code message is not a whole number of rows");
+        return (failed, stats);
+    }
+
+    let mut matrix: Vec<Vec<char>> = encoded_string
+        .as_bytes()
+        .chunks(row_codeword_length)
+        .map(|chunk| chunk.iter().map(|&byte| byte as char).collect())
+        .collect();
+    let num_rows = matrix.len();
+
+    let is_column_codeword_length =
+        (1..=num_rows).any(|k| encoded_length(&code.column, k) == Some(num_rows));
+    if !is_column_codeword_length {
+        println!("Error: Product code message has the wrong number of rows");
+        return (failed, stats);
+    }
+
+    // Peel the column code off first, then the row code, to get the data rows back
+    let extract_data = |matrix: &Vec<Vec<char>>, corrected: bool| -> String {
+        let mut data_columns: Vec<Vec<char>> = vec![];
+        for column in 0..row_codeword_length {
+            let bits: String = matrix.iter().map(|row| row[column]).collect();
+            let values = decode_stage(&code.column, &bits);
+            let data = if corrected { values.3 } else { values.2 };
+            data_columns.push(data.chars().collect());
+        }
+
+        let mut data = String::new();
+        for row in 0..data_columns[0].len() {
+            let bits: String = data_columns.iter().map(|column| column[row]).collect();
+            let values = decode_stage(&code.row, &bits);
+            data.push_str(if corrected { &values.3 } else { &values.2 });
+        }
+
+        // Strip the 10* padding
+        match data.rfind('1') {
+            Some(index) => data[..index].to_string(),
+            None => "".to_string(),
+        }
+    };
+
+    let original_message = extract_data(&matrix, false);
+    let mut has_error = false;
+    let mut is_clean = false;
+
+    while stats.iterations < PRODUCT_MAX_ITERATIONS {
+        stats.iterations += 1;
+        let mut changed = false;
+
+        let mut bad_rows = vec![];
+        for (row, row_bits) in matrix.iter_mut().enumerate() {
+            let bits: String = row_bits.iter().collect();
+            let values = decode_stage(&code.row, &bits);
+            if values.0 && values.1 {
+                let fixed = encode_correction(&code.row, &values.3).1;
+                stats.corrected_bits += count_differences(&bits, &fixed);
+                *row_bits = fixed.chars().collect();
+                changed = true;
+            } else if values.0 {
+                bad_rows.push(row);
+            }
+        }
+
+        let mut bad_columns = vec![];
+        for column in 0..row_codeword_length {
+            let bits: String = matrix.iter().map(|row| row[column]).collect();
+            let values = decode_stage(&code.column, &bits);
+            if values.0 && values.1 {
+                let fixed = encode_correction(&code.column, &values.3).1;
+                stats.corrected_bits += count_differences(&bits, &fixed);
+                for (row, bit) in fixed.chars().enumerate() {
+                    matrix[row][column] = bit;
+                }
+                changed = true;
+            } else if values.0 {
+                bad_columns.push(column);
+            }
+        }
+
+        if changed || !bad_rows.is_empty() || !bad_columns.is_empty() {
+            has_error = true;
+        }
+
+        if !changed && bad_rows.is_empty() && bad_columns.is_empty() {
+            is_clean = true;
+            break;
+        }
+
+        if !changed {
+            // Neither direction could fix things alone. If the failing rows and columns
+            // only cross in one line, the errors have to be where they cross.
+            let unambiguous = bad_rows.len() == 1 || bad_columns.len() == 1;
+            if bad_rows.is_empty() || bad_columns.is_empty() || !unambiguous {
+                break;
+            }
+            for &row in &bad_rows {
+                for &column in &bad_columns {
+                    matrix[row][column] = flip_char(matrix[row][column]);
+                    stats.corrected_bits += 1;
+                }
+            }
+        }
+    }
+
+    let corrected_message = extract_data(&matrix, true);
+
+    (
+        (
+            has_error,
+            has_error && is_clean,
+            original_message,
+            corrected_message,
+        ),
+        stats,
+    )
+}
+
+pub fn encode_correction(correction_type: &CorrectionType, encoded_string: &str) -> (bool, String) {
     match correction_type {
         CorrectionType::Parity => encode_parity_bit(encoded_string),
         CorrectionType::Triple => encode_triple(encoded_string),
         CorrectionType::Hamming => encode_hamming(encoded_string, false),
+        CorrectionType::Crc => encode_crc(encoded_string),
+        CorrectionType::Vt => encode_vt(encoded_string),
+        CorrectionType::Convolutional => encode_convolutional(encoded_string),
+        CorrectionType::Concatenated(code) => encode_concatenated(code, encoded_string),
+        CorrectionType::Product(code) => encode_product(code, encoded_string),
     }
 }
 
 pub fn decode_correction(
     correction_type: CorrectionType,
-    encoded_string: &String,
+    encoded_string: &str,
     is_extended: bool,
 ) -> (bool, bool, String, String) {
     match correction_type {
-        CorrectionType::Parity => decode_parity_bit(&encoded_string),
-        CorrectionType::Triple => decode_triple(&encoded_string),
-        CorrectionType::Hamming => decode_hamming(&encoded_string, is_extended),
+        CorrectionType::Parity => decode_parity_bit(encoded_string),
+        CorrectionType::Triple => decode_triple(encoded_string),
+        CorrectionType::Hamming => decode_hamming(encoded_string, is_extended),
+        CorrectionType::Crc => decode_crc(encoded_string),
+        CorrectionType::Vt => decode_vt(encoded_string),
+        CorrectionType::Convolutional => decode_convolutional(encoded_string),
+        CorrectionType::Concatenated(code) => decode_concatenated(&code, encoded_string).0,
+        CorrectionType::Product(code) => decode_product(&code, encoded_string).0,
     }
 }
 
@@ -324,6 +1033,16 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_decode_triple_bad_header() {
+        let failed = (true, false, String::new(), String::new());
+        assert_eq!(decode_stage(&CorrectionType::Triple, "0101"), failed);
+        assert_eq!(
+            decode_stage(&CorrectionType::Triple, "00000?110110110"),
+            failed
+        );
+    }
+
     // Hamming Code Tests
     #[test]
     fn test_encode_hamming_one() {
@@ -435,4 +1154,232 @@ mod tests {
             expected
         );
     }
+
+    // CRC Tests
+    #[test]
+    fn test_encode_crc() {
+        let input = String::from("1101");
+        let expected = (true, String::from("110100100011"));
+        assert_eq!(encode_correction(&CorrectionType::Crc, &input), expected);
+    }
+
+    #[test]
+    fn test_decode_crc_no_error() {
+        let input = String::from("110100100011");
+        let expected = (false, false, String::from("1101"), String::from("1101"));
+        assert_eq!(
+            decode_correction(CorrectionType::Crc, &input, false),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_decode_crc_with_double_error() {
+        let input = String::from("100000100011"); // Two flips, parity would miss this
+        let expected = (true, false, String::from("1000"), String::from("1000"));
+        assert_eq!(
+            decode_correction(CorrectionType::Crc, &input, false),
+            expected
+        );
+    }
+
+    // Interleaver and Concatenated Code Tests
+    #[test]
+    fn test_interleave_round_trip() {
+        let input = String::from("1101001110");
+        let interleaved = interleave(&input, 3);
+        assert_eq!(interleaved, "1011000111");
+        assert_eq!(deinterleave(&interleaved, 3), input);
+    }
+
+    #[test]
+    fn test_concatenated_round_trip() {
+        let input = String::from("1110100110");
+        let code = CorrectionType::Concatenated(Box::default());
+        let encoded = encode_correction(&code, &input);
+        assert!(encoded.0);
+
+        let expected = (false, false, input.clone(), input.clone());
+        assert_eq!(decode_correction(code, &encoded.1, false), expected);
+    }
+
+    #[test]
+    fn test_concatenated_outer_fixes_what_inner_misses() {
+        let input = String::from("1110100110");
+        let code = ConcatenatedCode::new(CorrectionType::Hamming, Some(4), CorrectionType::Parity);
+        let encoded = encode_concatenated(&code, &input).1;
+
+        // Parity inner can only detect, so the outer Hamming has to do the work
+        let mut noisy: Vec<char> = encoded.chars().collect();
+        noisy[5] = if noisy[5] == '0' { '1' } else { '0' };
+        let noisy: String = noisy.into_iter().collect();
+
+        let (result, stats) = decode_concatenated(&code, &noisy);
+        assert_eq!((result.0, result.1), (true, true));
+        assert_eq!(result.3, input);
+        assert!(stats.inner.found_errors);
+        assert_eq!(stats.inner.corrected_bits, 0);
+        assert!(stats.outer.found_errors);
+    }
+
+    #[test]
+    fn test_concatenated_inner_corrects_burst() {
+        let input = String::from("111010011011");
+        let code = ConcatenatedCode::default();
+        let encoded = encode_concatenated(&code, &input).1;
+
+        // Flip a burst in the first copy of the TPC inner code
+        let mut noisy: Vec<char> = encoded.chars().collect();
+        for bit in noisy.iter_mut().skip(12).take(3) {
+            *bit = if *bit == '0' { '1' } else { '0' };
+        }
+        let noisy: String = noisy.into_iter().collect();
+
+        let (result, stats) = decode_concatenated(&code, &noisy);
+        assert_eq!(result.3, input);
+        assert_eq!(stats.inner.corrected_bits, 3);
+        assert!(!stats.outer.found_errors);
+    }
+
+    // Product Code Tests
+    fn flip_at(input: &str, positions: &[usize]) -> String {
+        let mut bits: Vec<char> = input.chars().collect();
+        for &position in positions {
+            bits[position] = if bits[position] == '0' { '1' } else { '0' };
+        }
+        bits.into_iter().collect()
+    }
+
+    #[test]
+    fn test_encode_product_parity() {
+        let input = String::from("110");
+        let code = ProductCode::new(CorrectionType::Parity, CorrectionType::Parity, 2);
+        // Rows "11" and "01" (padded), each gets a row parity bit, then a parity row on top
+        let expected = (true, String::from("110011101"));
+        assert_eq!(encode_product(&code, &input), expected);
+    }
+
+    #[test]
+    fn test_decode_product_parity_single_error() {
+        let input = String::from("1110100110");
+        let code = ProductCode::default();
+        let encoded = encode_product(&code, &input).1;
+        let noisy = flip_at(&encoded, &[13]);
+
+        let (result, stats) = decode_product(&code, &noisy);
+        assert_eq!((result.0, result.1), (true, true));
+        assert_eq!(result.3, input);
+        assert_eq!(stats.corrected_bits, 1);
+    }
+
+    #[test]
+    fn test_decode_product_parity_double_error_detected() {
+        let input = String::from("1110100110");
+        let code = ProductCode::default();
+        let encoded = encode_product(&code, &input).1;
+        // Two errors in different rows and columns: four candidate spots, can't tell which
+        let noisy = flip_at(&encoded, &[10, 22]);
+
+        let (result, _) = decode_product(&code, &noisy);
+        assert_eq!((result.0, result.1), (true, false));
+    }
+
+    #[test]
+    fn test_decode_product_hamming_iterative() {
+        let input = "1100101011".repeat(4);
+        let code = ProductCode::new(CorrectionType::Hamming, CorrectionType::Hamming, 8);
+        let encoded = encode_product(&code, &input).1;
+        // Three errors in one row: the rows miscorrect it, the columns clean it up
+        let noisy = flip_at(&encoded, &[12, 14, 17]);
+
+        let (result, stats) = decode_product(&code, &noisy);
+        assert_eq!((result.0, result.1), (true, true));
+        assert_eq!(result.3, input);
+        assert!(stats.iterations > 1);
+    }
+
+    #[test]
+    fn test_vt_corrects_any_single_deletion_or_insertion() {
+        for input in [
+            "",
+            "1",
+            "0110100111",
+            "1111111111111",
+            "1100101011000111010",
+        ] {
+            let encoded = encode_vt(input).1;
+            assert_eq!(encoded.len() % 3, 0);
+            assert_eq!(
+                decode_vt(&encoded),
+                (false, false, input.into(), input.into())
+            );
+
+            for position in 0..encoded.len() {
+                let mut deleted = encoded.clone();
+                deleted.remove(position);
+                let result = decode_vt(&deleted);
+                assert_eq!((result.0, result.1), (true, true));
+                assert_eq!(result.3, input, "deleted bit {} of {}", position, encoded);
+
+                for bit in ['0', '1'] {
+                    let mut inserted = encoded.clone();
+                    inserted.insert(position, bit);
+                    assert_eq!(decode_vt(&inserted).3, input);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_vt_detects_a_flip() {
+        let encoded = encode_vt("0110100111").1;
+        let result = decode_vt(&flip_at(&encoded, &[4]));
+        assert_eq!((result.0, result.1), (true, false));
+    }
+
+    // Convolutional Code Tests
+    #[test]
+    fn test_encode_convolutional() {
+        let input = String::from("1101");
+        let expected = (true, String::from("110101001011"));
+        assert_eq!(
+            encode_correction(&CorrectionType::Convolutional, &input),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_decode_convolutional_two_spread_flips() {
+        let input = "0110100111";
+        let encoded = encode_convolutional(input).1;
+        assert_eq!(
+            decode_convolutional(&encoded),
+            (false, false, input.into(), input.into())
+        );
+
+        let result = decode_convolutional(&flip_at(&encoded, &[1, 14]));
+        assert_eq!((result.0, result.1), (true, true));
+        assert_ne!(result.2, input);
+        assert_eq!(result.3, input);
+    }
+
+    #[test]
+    fn test_decode_convolutional_skips_erasures() {
+        let input = "1100101";
+        let mut erased: Vec<char> = encode_convolutional(input).1.chars().collect();
+        for position in (3..erased.len()).step_by(4) {
+            erased[position] = '?';
+        }
+        let erased: String = erased.into_iter().collect();
+
+        let result = decode_convolutional(&erased);
+        assert!(!result.0);
+        assert_eq!(result.3, input);
+    }
+
+    #[test]
+    fn test_encode_product_rejects_triple() {
+        let code = ProductCode::new(CorrectionType::Triple, CorrectionType::Parity, 4);
+        assert!(!encode_product(&code, "1010").0);
+    }
 }
@@ -0,0 +1,273 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::time::Duration;
+
+/* Where to listen or connect. On the command line (--listen / --connect) or in
+ * the environment variable each binary falls back to:
+ *
+ *   127.0.0.1:6969        IPv4
+ *   [::1]:6969            IPv6
+ *   localhost:6969        anything else that resolves
+ *   unix:/tmp/ecc.sock    Unix domain socket
+ */
+
+pub const DEFAULT_RECEIVER_ADDRESS: &str = "127.0.0.1:6969";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Endpoint {
+    Ip(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Endpoint {
+    pub fn parse(address: &str) -> Result<Self, String> {
+        if let Some(path) = address.strip_prefix("unix:") {
+            #[cfg(unix)]
+            return Ok(Endpoint::Unix(PathBuf::from(path)));
+            #[cfg(not(unix))]
+            return Err(format!("Unix sockets are not supported here: {}", path));
+        }
+
+        address
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addresses| addresses.next())
+            .map(Endpoint::Ip)
+            .ok_or(format!("Can't use {} as an address", address))
+    }
+
+    // UDP has no Unix socket counterpart here
+    pub fn ip(&self) -> Result<SocketAddr, String> {
+        match self {
+            Endpoint::Ip(address) => Ok(*address),
+            #[cfg(unix)]
+            Endpoint::Unix(_) => Err(format!("{} is not an IP address", self)),
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Endpoint::Ip(address) => write!(f, "{}", address),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// `flag` on the command line, then the `env_var` environment variable, then `default`.
+pub fn endpoint_from_args(
+    args: &[String],
+    flag: &str,
+    env_var: &str,
+    default: &str,
+) -> Result<Endpoint, String> {
+    let address = match args.iter().position(|arg| arg == flag) {
+        Some(index) => args
+            .get(index + 1)
+            .cloned()
+            .ok_or(format!("{} needs an address", flag))?,
+        None => std::env::var(env_var).unwrap_or(default.to_string()),
+    };
+
+    Endpoint::parse(&address)
+}
+
+/// A connected TCP or Unix stream.
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    pub fn connect(endpoint: &Endpoint) -> io::Result<Self> {
+        match endpoint {
+            Endpoint::Ip(address) => TcpStream::connect(address).map(Stream::Tcp),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => UnixStream::connect(path).map(Stream::Unix),
+        }
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Stream::Tcp(stream) => stream.try_clone().map(Stream::Tcp),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.try_clone().map(Stream::Unix),
+        }
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.shutdown(how),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.shutdown(how),
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.read(buffer),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buffer),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => stream.write(buffer),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buffer),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    /// A socket file left behind by an earlier run is removed first, anything else
+    /// at the path is left alone and is an error.
+    pub fn bind(endpoint: &Endpoint) -> io::Result<Self> {
+        match endpoint {
+            Endpoint::Ip(address) => TcpListener::bind(address).map(Listener::Tcp),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                if let Ok(metadata) = std::fs::symlink_metadata(path) {
+                    if !metadata.file_type().is_socket() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AlreadyExists,
+                            format!("{} exists and is not a socket", path.display()),
+                        ));
+                    }
+                    if UnixStream::connect(path).is_err() {
+                        std::fs::remove_file(path)?;
+                    }
+                }
+                UnixListener::bind(path).map(|listener| Listener::Unix(listener, path.clone()))
+            }
+        }
+    }
+
+    pub fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => {
+                listener.accept().map(|(stream, _)| Stream::Unix(stream))
+            }
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.set_nonblocking(nonblocking),
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => listener.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_endpoints() {
+        assert_eq!(
+            Endpoint::parse("127.0.0.1:6969"),
+            Ok(Endpoint::Ip("127.0.0.1:6969".parse().unwrap()))
+        );
+        assert_eq!(
+            Endpoint::parse("[::1]:7070"),
+            Ok(Endpoint::Ip("[::1]:7070".parse().unwrap()))
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            Endpoint::parse("unix:/tmp/ecc.sock"),
+            Ok(Endpoint::Unix(PathBuf::from("/tmp/ecc.sock")))
+        );
+        assert!(Endpoint::parse("no port").is_err());
+    }
+
+    #[test]
+    fn test_flag_beats_default() {
+        let args: Vec<String> = ["receiver", "--listen", "[::1]:7000"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let endpoint = endpoint_from_args(&args, "--listen", "ECC_TEST_UNSET", "127.0.0.1:1");
+        assert_eq!(endpoint.unwrap().to_string(), "[::1]:7000");
+
+        let endpoint = endpoint_from_args(&args[..1], "--listen", "ECC_TEST_UNSET", "127.0.0.1:1");
+        assert_eq!(endpoint.unwrap().to_string(), "127.0.0.1:1");
+        assert!(endpoint_from_args(&args[..2], "--listen", "ECC_TEST_UNSET", "").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_bind_leaves_other_files_alone() {
+        let path = std::env::temp_dir().join(format!("ecc-not-a-socket-{}", std::process::id()));
+        std::fs::write(&path, "keep me").unwrap();
+
+        let error = Listener::bind(&Endpoint::Unix(path.clone())).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "keep me");
+        std::fs::remove_file(&path).unwrap();
+
+        // A stale socket file from an earlier run is fine
+        let path = std::env::temp_dir().join(format!("ecc-stale-{}.sock", std::process::id()));
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(Listener::bind(&Endpoint::Unix(path.clone())).is_ok());
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,193 @@
+use crate::correction::{is_product_component, ConcatenatedCode, CorrectionType, ProductCode};
+use crate::protocol::{decode_code, encode_code, Packet, PacketType, ProtocolError};
+
+/* Before any message the two sides agree on which codes can be used:
+ *
+ *   sender   -> Hello   payload: every code (with its parameters) it can encode
+ *   receiver -> Accept  payload: the ones out of those it can decode
+ *            or Error   payload: why not, and the connection is closed
+ *
+ * Codes are sent the same way as in the packet header, but each one is prefixed
+ * with its length, so a receiver from an older build can skip ids it doesn't know
+ * instead of giving up on the whole list. A message using a code that was not
+ * accepted gets an Error back as well, rather than being decoded as something else.
+ */
+
+/// The codes (and parameters) this build's sender can offer from its menu.
+pub fn supported_codes() -> Vec<CorrectionType> {
+    vec![
+        CorrectionType::Parity,
+        CorrectionType::Triple,
+        CorrectionType::Hamming,
+        CorrectionType::Concatenated(Box::default()),
+        CorrectionType::Product(Box::default()),
+        CorrectionType::Crc,
+        CorrectionType::Vt,
+        CorrectionType::Convolutional,
+    ]
+}
+
+/// Whether this build can decode the code with these parameters.
+pub fn is_supported(correction_type: &CorrectionType) -> bool {
+    match correction_type {
+        CorrectionType::Parity
+        | CorrectionType::Triple
+        | CorrectionType::Hamming
+        | CorrectionType::Crc
+        | CorrectionType::Vt
+        | CorrectionType::Convolutional => true,
+        CorrectionType::Concatenated(code) => {
+            let ConcatenatedCode {
+                outer,
+                interleaver_depth,
+                inner,
+            } = code.as_ref();
+            interleaver_depth.is_none_or(|depth| depth > 0)
+                && is_supported(outer)
+                && is_supported(inner)
+        }
+        CorrectionType::Product(code) => {
+            let ProductCode {
+                row,
+                column,
+                row_length,
+            } = code.as_ref();
+            *row_length > 0 && is_product_component(row) && is_product_component(column)
+        }
+    }
+}
+
+pub fn encode_code_list(codes: &[CorrectionType]) -> Result<Vec<u8>, ProtocolError> {
+    let count = u8::try_from(codes.len()).map_err(|_| ProtocolError::CodeTooLarge)?;
+    let mut bytes = vec![count];
+    for code in codes {
+        let code_bytes = encode_code(code)?;
+        bytes.push(u8::try_from(code_bytes.len()).map_err(|_| ProtocolError::CodeTooLarge)?);
+        bytes.extend(code_bytes);
+    }
+    Ok(bytes)
+}
+
+/// None if the list itself is cut short. Codes we don't know come back as None
+/// entries so the caller can still say how many were skipped.
+pub fn decode_code_list(bytes: &[u8]) -> Option<Vec<Option<CorrectionType>>> {
+    let (&count, mut rest) = bytes.split_first()?;
+    let mut codes = vec![];
+
+    for _ in 0..count {
+        let (&length, after_length) = rest.split_first()?;
+        let code_bytes = after_length.get(..length as usize)?;
+        codes.push(match decode_code(code_bytes) {
+            Some((code, used)) if used == code_bytes.len() => Some(code),
+            _ => None,
+        });
+        rest = &after_length[length as usize..];
+    }
+
+    Some(codes)
+}
+
+pub fn hello_packet() -> Packet {
+    Packet::new(
+        PacketType::Hello,
+        None,
+        encode_code_list(&supported_codes()).expect("the supported codes fit in a Hello"),
+    )
+}
+
+/// Receiver side: the Accept or Error packet to answer a Hello with, and the codes
+/// that can be used from now on.
+pub fn answer_hello(hello: &Packet) -> (Packet, Vec<CorrectionType>) {
+    let Some(offered) = decode_code_list(&hello.payload) else {
+        return (error_packet("malformed code list in Hello"), vec![]);
+    };
+
+    let accepted: Vec<CorrectionType> = offered
+        .iter()
+        .flatten()
+        .filter(|code| is_supported(code))
+        .cloned()
+        .collect();
+
+    if accepted.is_empty() {
+        let reason = format!(
+            "none of the {} offered correction codes are supported",
+            offered.len()
+        );
+        return (error_packet(&reason), vec![]);
+    }
+
+    let skipped = offered.len() - accepted.len();
+    if skipped > 0 {
+        println!("Handshake: skipping {} unsupported code(s)", skipped);
+    }
+
+    match encode_code_list(&accepted) {
+        Ok(bytes) => (Packet::new(PacketType::Accept, None, bytes), accepted),
+        Err(e) => (error_packet(&e.to_string()), vec![]),
+    }
+}
+
+/// Sender side: the codes the receiver accepted, or the reason it gave for refusing.
+pub fn read_accept(answer: &Packet) -> Result<Vec<CorrectionType>, String> {
+    match answer.packet_type {
+        PacketType::Accept => decode_code_list(&answer.payload)
+            .map(|codes| codes.into_iter().flatten().collect())
+            .ok_or("malformed code list in Accept".to_string()),
+        PacketType::Error => Err(answer.payload_text()),
+        other => Err(format!("expected Accept, got {:?}", other)),
+    }
+}
+
+pub fn error_packet(reason: &str) -> Packet {
+    Packet::text(PacketType::Error, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_list_round_trip_skips_unknown_codes() {
+        let codes = supported_codes();
+        let mut bytes = encode_code_list(&codes).unwrap();
+        // A code from a newer build, id 42 with two parameter bytes
+        bytes[0] += 1;
+        bytes.extend([3, 42, 1, 2]);
+
+        let decoded = decode_code_list(&bytes).unwrap();
+        assert_eq!(decoded.len(), codes.len() + 1);
+        assert_eq!(decoded.last(), Some(&None));
+        let known: Vec<CorrectionType> = decoded.into_iter().flatten().collect();
+        assert_eq!(known, codes);
+
+        assert_eq!(decode_code_list(&bytes[..bytes.len() - 1]), None);
+    }
+
+    #[test]
+    fn test_handshake_accepts_supported_codes() {
+        let (answer, accepted) = answer_hello(&hello_packet());
+        assert_eq!(answer.packet_type, PacketType::Accept);
+        assert_eq!(accepted, supported_codes());
+        assert_eq!(read_accept(&answer), Ok(supported_codes()));
+    }
+
+    #[test]
+    fn test_handshake_rejects_unsupported_parameters() {
+        let product_with_triple = CorrectionType::Product(Box::new(ProductCode::new(
+            CorrectionType::Triple,
+            CorrectionType::Parity,
+            8,
+        )));
+        let hello = Packet::new(
+            PacketType::Hello,
+            None,
+            encode_code_list(&[product_with_triple]).unwrap(),
+        );
+
+        let (answer, accepted) = answer_hello(&hello);
+        assert_eq!(answer.packet_type, PacketType::Error);
+        assert!(accepted.is_empty());
+        assert!(read_accept(&answer).is_err());
+    }
+}
@@ -0,0 +1,300 @@
+use crate::correction::{decode_crc, decode_hamming, encode_crc, encode_hamming};
+
+/* Hybrid ARQ: instead of giving up when a message can't be decoded, the receiver
+ * answers with a NACK and the sender transmits again, until decoding works or we hit
+ * MAX_TRANSMISSIONS. Every transmission is built from the same CRC protected
+ * word (message + CRC-8), the CRC tells the receiver when it's done.
+ *
+ * Chase combining: every transmission is the same word again. The receiver tries
+ * every copy on its own first, then votes over all the copies it has.
+ * Incremental redundancy (Type-II): the second transmission is not a copy but the
+ * Hamming check bits for the word, so together with the first one it forms a
+ * Hamming codeword that can correct a single error. After that we send copies.
+ *
+ * Every transmission carries its round, so the receiver knows what it got even
+ * when an earlier one or a NACK went missing, and the message id it belongs to.
+ */
+
+pub const MAX_TRANSMISSIONS: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HarqMode {
+    IncrementalRedundancy,
+    ChaseCombining,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RedundancyVersion {
+    Systematic,
+    HammingChecks,
+}
+
+pub fn redundancy_version(mode: HarqMode, round: usize) -> RedundancyVersion {
+    match (mode, round) {
+        (HarqMode::IncrementalRedundancy, 1) => RedundancyVersion::HammingChecks,
+        _ => RedundancyVersion::Systematic,
+    }
+}
+
+/// What the sender puts on the wire for transmission number `round` (starting at 0).
+pub fn harq_transmission(mode: HarqMode, encoded_string: &str, round: usize) -> String {
+    let (_, systematic) = encode_crc(encoded_string);
+
+    match redundancy_version(mode, round) {
+        RedundancyVersion::Systematic => systematic,
+        RedundancyVersion::HammingChecks => hamming_check_bits(&systematic),
+    }
+}
+
+fn hamming_check_bits(word: &str) -> String {
+    let (_, hamming_code) = encode_hamming(word, false);
+    hamming_code
+        .chars()
+        .enumerate()
+        .filter(|(i, _)| (i + 1).is_power_of_two())
+        .map(|(_, bit)| bit)
+        .collect()
+}
+
+// Put the word and separately received check bits back together into a Hamming codeword
+fn with_check_bits(word: &str, checks: &str) -> String {
+    let mut word_bits = word.chars();
+    let mut check_bits = checks.chars();
+
+    (1..=word.len() + checks.len())
+        .map(|i| {
+            if i.is_power_of_two() {
+                check_bits.next().unwrap_or('0')
+            } else {
+                word_bits.next().unwrap_or('0')
+            }
+        })
+        .collect()
+}
+
+fn passes_crc(word: &str) -> bool {
+    !decode_crc(word).0
+}
+
+// Past this many tied bits there are too many ways to fill them in to try them all
+const MAX_TIES: usize = 3;
+
+/* Bitwise majority over all copies. Tied bits could go either way, so every way of
+ * filling them in is a candidate, and the CRC picks the right one.
+ */
+fn combine_copies(copies: &[String]) -> Vec<String> {
+    let length = copies[0].len();
+    let mut combined: Vec<char> = vec![];
+    let mut ties = vec![];
+
+    for i in 0..length {
+        let count_1 = copies
+            .iter()
+            .filter(|copy| copy.as_bytes()[i] == b'1')
+            .count();
+        let count_0 = copies.len() - count_1;
+
+        if count_1 > count_0 {
+            combined.push('1');
+        } else if count_0 > count_1 {
+            combined.push('0');
+        } else {
+            combined.push('0');
+            ties.push(i);
+        }
+    }
+
+    if ties.len() > MAX_TIES {
+        return vec![];
+    }
+
+    (0..1usize << ties.len())
+        .map(|fill| {
+            for (index, &tie) in ties.iter().enumerate() {
+                combined[tie] = if (fill >> index) & 1 == 1 { '1' } else { '0' };
+            }
+            combined.iter().collect()
+        })
+        .collect()
+}
+
+/// Receiver side buffer for one message, fed one transmission at a time.
+pub struct HarqReceiver {
+    pub mode: HarqMode,
+    pub transmissions: usize,
+    rounds: Vec<usize>,
+    copies: Vec<String>,
+    checks: Vec<String>,
+}
+
+impl HarqReceiver {
+    pub fn new(mode: HarqMode) -> Self {
+        HarqReceiver {
+            mode,
+            transmissions: 0,
+            rounds: vec![],
+            copies: vec![],
+            checks: vec![],
+        }
+    }
+
+    /// Whether transmission `round` already came in, duplicates should be dropped.
+    pub fn has_round(&self, round: usize) -> bool {
+        self.rounds.contains(&round)
+    }
+
+    /// Returns the message once some combination of what we have passes the CRC.
+    pub fn receive(&mut self, round: usize, bits: &str) -> Option<String> {
+        match redundancy_version(self.mode, round) {
+            RedundancyVersion::Systematic => {
+                let expected_length = self.copies.first().map_or(bits.len(), |copy| copy.len());
+                if bits.len() == expected_length {
+                    self.copies.push(bits.to_string());
+                } else {
+                    println!("Error: HARQ retransmission has the wrong length, ignoring it");
+                }
+            }
+            RedundancyVersion::HammingChecks => self.checks.push(bits.to_string()),
+        }
+        self.transmissions += 1;
+        self.rounds.push(round);
+
+        self.candidates()
+            .into_iter()
+            .find(|word| passes_crc(word))
+            .map(|word| decode_crc(&word).3)
+    }
+
+    // The sender sends nothing after its last round, whatever got lost before it
+    pub fn gave_up(&self) -> bool {
+        self.rounds
+            .iter()
+            .any(|&round| round + 1 >= MAX_TRANSMISSIONS)
+    }
+
+    // Best effort once we are out of retries: the newest copy, CRC or not
+    pub fn best_guess(&self) -> String {
+        self.copies
+            .last()
+            .map_or("".to_string(), |word| decode_crc(word).3)
+    }
+
+    // Every copy as it came, newest first, then with the check bits, then voted on
+    fn candidates(&self) -> Vec<String> {
+        let mut candidates: Vec<String> = self.copies.iter().rev().cloned().collect();
+
+        for copy in self.copies.iter().rev() {
+            for checks in &self.checks {
+                let (_, _, _, corrected) = decode_hamming(&with_check_bits(copy, checks), false);
+                candidates.push(corrected);
+            }
+        }
+
+        if self.copies.len() >= 2 {
+            candidates.extend(combine_copies(&self.copies));
+        }
+
+        candidates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flip_at(input: &str, position: usize) -> String {
+        let mut bits: Vec<char> = input.chars().collect();
+        bits[position] = if bits[position] == '0' { '1' } else { '0' };
+        bits.into_iter().collect()
+    }
+
+    #[test]
+    fn test_harq_clean_first_transmission() {
+        let input = String::from("1101001");
+        let mut receiver = HarqReceiver::new(HarqMode::ChaseCombining);
+        let sent = harq_transmission(HarqMode::ChaseCombining, &input, 0);
+        assert_eq!(receiver.receive(0, &sent), Some(input));
+    }
+
+    #[test]
+    fn test_harq_incremental_redundancy_corrects_with_check_bits() {
+        let input = String::from("1101001");
+        let mode = HarqMode::IncrementalRedundancy;
+        let mut receiver = HarqReceiver::new(mode);
+
+        let first = flip_at(&harq_transmission(mode, &input, 0), 3);
+        assert_eq!(receiver.receive(0, &first), None);
+
+        let second = harq_transmission(mode, &input, 1);
+        // Hamming check bits for 7 message bits and 8 CRC bits
+        assert_eq!(second.len(), 5);
+        assert_eq!(receiver.receive(1, &second), Some(input));
+    }
+
+    #[test]
+    fn test_harq_chase_combining_resolves_single_disagreement() {
+        let input = String::from("1101001");
+        let mode = HarqMode::ChaseCombining;
+        let mut receiver = HarqReceiver::new(mode);
+
+        let first = flip_at(&harq_transmission(mode, &input, 0), 5);
+        assert_eq!(receiver.receive(0, &first), None);
+
+        let second = harq_transmission(mode, &input, 1);
+        assert_eq!(receiver.receive(1, &second), Some(input));
+    }
+
+    #[test]
+    fn test_harq_catches_two_flips() {
+        let input = String::from("1101001");
+        let mode = HarqMode::ChaseCombining;
+        let mut receiver = HarqReceiver::new(mode);
+
+        // A parity bit would have taken this for the message
+        let first = flip_at(&flip_at(&harq_transmission(mode, &input, 0), 1), 4);
+        assert_eq!(receiver.receive(0, &first), None);
+    }
+
+    #[test]
+    fn test_harq_chase_combining_fills_in_ties() {
+        let input = String::from("1101001");
+        let mode = HarqMode::ChaseCombining;
+        let mut receiver = HarqReceiver::new(mode);
+
+        // Both copies are wrong, in different places, so two bits are tied
+        let first = flip_at(&harq_transmission(mode, &input, 0), 2);
+        assert_eq!(receiver.receive(0, &first), None);
+        let second = flip_at(&harq_transmission(mode, &input, 1), 9);
+        assert_eq!(receiver.receive(1, &second), Some(input));
+    }
+
+    #[test]
+    fn test_harq_gives_up_after_max_transmissions() {
+        let input = String::from("1101001");
+        let mode = HarqMode::ChaseCombining;
+        let mut receiver = HarqReceiver::new(mode);
+
+        // A stuck bit: every copy has the same error, so voting can't help
+        for round in 0..MAX_TRANSMISSIONS {
+            assert!(!receiver.gave_up());
+            let sent = flip_at(&harq_transmission(mode, &input, round), 2);
+            assert_eq!(receiver.receive(round, &sent), None);
+        }
+        assert!(receiver.gave_up());
+    }
+
+    #[test]
+    fn test_harq_incremental_redundancy_after_lost_first_round() {
+        let input = String::from("1101001");
+        let mode = HarqMode::IncrementalRedundancy;
+        let mut receiver = HarqReceiver::new(mode);
+
+        // Round 0 never arrived, round 1 is only check bits and round 2 a copy
+        let second = harq_transmission(mode, &input, 1);
+        assert_eq!(receiver.receive(1, &second), None);
+        let third = flip_at(&harq_transmission(mode, &input, 2), 3);
+        assert_eq!(receiver.receive(2, &third), Some(input));
+        assert!(receiver.has_round(1));
+        assert!(!receiver.has_round(0));
+    }
+}
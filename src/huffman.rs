@@ -21,7 +21,7 @@ impl HuffmanNode {
             max_size: 0,
         };
         Self::save_encoding_helper(root, curr_code, &mut map);
-        return map;
+        map
     }
 
     fn save_encoding_helper(root: &HuffmanNodeRef, curr_code: String, map: &mut HuffmanEncoding) {
@@ -93,8 +93,8 @@ impl HuffmanEncoding {
         }
 
         let curr_root = Box::new(min_heap.pop().unwrap().0);
-        let map = HuffmanNode::save_encoding(&Some(curr_root), "".to_string());
-        return map;
+
+        HuffmanNode::save_encoding(&Some(curr_root), "".to_string())
     }
 
     pub fn encode_table(hf: &HuffmanEncoding) -> String {
@@ -105,7 +105,7 @@ impl HuffmanEncoding {
          * and the next 5 tell us the huffman code for it
          */
 
-        let mut to_send = String::from(format!("{}1", "0".repeat(hf.max_size)));
+        let mut to_send = format!("{}1", "0".repeat(hf.max_size));
         let bits_req = (hf.max_size.ilog2() + 1) as usize;
 
         for (k, v) in &hf.encoding {
@@ -115,7 +115,7 @@ impl HuffmanEncoding {
             } else if num_alphabet == 35 {
                 num_alphabet = 28;
             } else {
-                num_alphabet = num_alphabet - 97;
+                num_alphabet -= 97;
             }
 
             let binary_string = format!("{:0>width$b}", num_alphabet, width = 5);
@@ -124,10 +124,10 @@ impl HuffmanEncoding {
             let size_of_code = v.len();
             let binary_size_of_code = format!("{:0>width$b}", size_of_code, width = bits_req);
             to_send.push_str(&binary_size_of_code);
-            to_send.push_str(&v);
+            to_send.push_str(v);
         }
 
-        return to_send;
+        to_send
     }
 
     pub fn decode_table(s: &str) -> Option<HuffmanDecoding> {
@@ -183,8 +183,47 @@ impl HuffmanEncoding {
                 break;
             }
         }
-        return Some(hf);
+        Some(hf)
+    }
+}
+
+pub fn encode_message(message: &str, hf: &HuffmanEncoding) -> String {
+    let mut encoded_message: String = "".to_string();
+
+    for char in message.chars() {
+        let bit_string = match hf.encoding.get(&char) {
+            Some(val) => val,
+            None => panic!("Could not find bit string for char {}", char),
+        };
+        encoded_message.push_str(bit_string);
     }
+
+    encoded_message
+}
+
+pub fn decode_message(message: &str, hd: &HuffmanDecoding) -> String {
+    if hd.max_size == 0 {
+        return "".to_string();
+    }
+
+    let mut decoded_string: String = "".to_string();
+
+    let mut left_ptr = 0;
+    let mut right_ptr = 1;
+
+    while right_ptr <= message.len() && left_ptr < message.len() {
+        let string_slice = &message[left_ptr..right_ptr];
+        match hd.decoding.get(string_slice) {
+            Some(value) => {
+                decoded_string.push(*value);
+                left_ptr = right_ptr;
+                right_ptr += 1;
+            }
+            None => right_ptr += 1,
+        }
+    }
+
+    decoded_string
 }
 
 #[cfg(test)]
@@ -202,7 +241,7 @@ mod tests {
         assert!(encoding.encoding.contains_key(&'w'));
         assert!(encoding.encoding.contains_key(&'r'));
         assert!(encoding.encoding.contains_key(&'d'));
-        assert_eq!(encoding.max_size > 0, true);
+        assert!(encoding.max_size > 0);
     }
 
     #[test]
@@ -221,6 +260,19 @@ mod tests {
         assert!(encoding.encoding.contains_key(&'o'));
     }
 
+    #[test]
+    fn test_huffman_message_round_trip() {
+        let words = vec!["hello", " ", "how", "are", "you"];
+        let encoding = HuffmanEncoding::new(&words);
+        let decoding = HuffmanEncoding::decode_table(&HuffmanEncoding::encode_table(&encoding));
+
+        let bits = encode_message("hello how are you", &encoding);
+        assert_eq!(
+            decode_message(&bits, &decoding.unwrap()),
+            "hello how are you"
+        );
+    }
+
     #[test]
     fn test_huffman_complex_words() {
         let words = vec!["rustacean", "hello", "world"];
@@ -0,0 +1,109 @@
+use std::process::ExitCode;
+
+use async_link::{send_messages, serve};
+use correction::CorrectionType;
+use noise::seed_from_args;
+use script::EXIT_USAGE;
+use stats::ReceiverStats;
+use tokio::io::duplex;
+
+pub mod arq;
+pub mod async_link;
+pub mod correction;
+pub mod handshake;
+pub mod harq;
+pub mod huffman;
+pub mod modulation;
+pub mod noise;
+pub mod protocol;
+pub mod rate_matching;
+pub mod report;
+pub mod script;
+pub mod session;
+pub mod stats;
+
+/* Runs many sender/receiver pairs in this one process, each pair talking over an
+ * in-memory pipe instead of a socket. Every link does the full handshake and sends
+ * its messages with row/column parity and the usual noise.
+ */
+
+const DEFAULT_LINKS: usize = 100;
+// Bytes either end can write before it waits for the other one to read
+const LINK_BUFFER_SIZE: usize = 4096;
+
+const VALID_WORDS: [&str; 5] = ["hello", "how", "are", "you", " "];
+const MESSAGES: [&str; 2] = ["hello how are you", "how are you"];
+
+fn links_from_args(args: &[String]) -> Result<usize, String> {
+    match args.iter().position(|arg| arg == "--links") {
+        None => Ok(DEFAULT_LINKS),
+        Some(index) => match args.get(index + 1).map(|value| value.parse::<usize>()) {
+            Some(Ok(links)) if links > 0 => Ok(links),
+            _ => Err("--links needs a number of at least 1".to_string()),
+        },
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    let links = match links_from_args(&args) {
+        Ok(links) => links,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    let seed = match seed_from_args(&args) {
+        Ok(seed) => seed.unwrap_or_else(rand::random),
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+    println!("Noise seed: {}", seed);
+
+    let mut tasks = vec![];
+    for link in 0..links {
+        // Every link gets its own noise, and the same again with the same --seed
+        let link_seed = seed.wrapping_add(link as u64);
+        let (sender_end, receiver_end) = duplex(LINK_BUFFER_SIZE);
+        let receiver = tokio::spawn(serve(receiver_end, None));
+        let sender = tokio::spawn(async move {
+            let code = CorrectionType::Product(Box::default());
+            send_messages(sender_end, &VALID_WORDS, &MESSAGES, &code, link_seed).await
+        });
+        tasks.push((sender, receiver));
+    }
+
+    let mut total_stats = ReceiverStats::default();
+    let mut failed_links = 0;
+    for (sender, receiver) in tasks {
+        // Every message has to come back as it was sent
+        let delivered = match sender.await {
+            Ok(Ok(reports)) => reports
+                .iter()
+                .zip(MESSAGES)
+                .all(|(report, message)| report.matches(message)),
+            _ => false,
+        };
+        if !delivered {
+            failed_links += 1;
+        }
+        match receiver.await {
+            Ok(Ok(stats)) => total_stats.add(&stats),
+            _ => failed_links += 1,
+        }
+    }
+
+    println!("Ran {} links, {} failed", links, failed_links);
+    println!("Statistics for all links:");
+    println!("{}", total_stats);
+
+    if failed_links == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::stats::{CodeStats, ReceiverStats};
+
+/* Statistics of every connection, the ones still open included, so a long soak
+ * run can be watched while it goes. With --metrics ADDRESS the receiver serves them
+ * in the Prometheus text format on http://ADDRESS/metrics.
+ */
+
+/* Scrapes are answered one at a time on a single thread, so a client gets
+ * REQUEST_TIMEOUT in total to send its request, not per read, and lines and headers
+ * are capped, otherwise one slow or endless request would stall every scrape after it.
+ */
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_LINE_LENGTH: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+
+/// `--metrics`, None when it isn't given.
+pub fn metrics_from_args(args: &[String]) -> Result<Option<SocketAddr>, String> {
+    match args.iter().position(|arg| arg == "--metrics") {
+        None => Ok(None),
+        Some(index) => match args.get(index + 1).map(|value| value.parse::<SocketAddr>()) {
+            Some(Ok(address)) => Ok(Some(address)),
+            _ => Err("--metrics needs an address like 127.0.0.1:9898".to_string()),
+        },
+    }
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    closed: Mutex<ReceiverStats>,
+    // Latest statistics of the connections still open, by the id open() gave them
+    open: Mutex<HashMap<usize, ReceiverStats>>,
+    next_id: AtomicUsize,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    pub fn open(&self) -> usize {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.open
+            .lock()
+            .unwrap()
+            .insert(id, ReceiverStats::default());
+        id
+    }
+
+    pub fn update(&self, id: usize, stats: &ReceiverStats) {
+        self.open.lock().unwrap().insert(id, stats.clone());
+    }
+
+    pub fn close(&self, id: usize, stats: &ReceiverStats) {
+        self.open.lock().unwrap().remove(&id);
+        self.closed.lock().unwrap().add(stats);
+    }
+
+    // For what happens outside any connection, like turning one away
+    pub fn add(&self, stats: &ReceiverStats) {
+        self.closed.lock().unwrap().add(stats);
+    }
+
+    /// Everything from the connections that are done.
+    pub fn closed(&self) -> ReceiverStats {
+        self.closed.lock().unwrap().clone()
+    }
+
+    /// The closed connections plus where the open ones are now.
+    pub fn snapshot(&self) -> (ReceiverStats, usize) {
+        let mut total = self.closed();
+        let open = self.open.lock().unwrap();
+        for stats in open.values() {
+            total.add(stats);
+        }
+        (total, open.len())
+    }
+
+    pub fn render(&self) -> String {
+        let (stats, open) = self.snapshot();
+        render_prometheus(&stats, open)
+    }
+}
+
+fn write_metric(output: &mut String, name: &str, kind: &str, help: &str, value: usize) {
+    let _ = writeln!(output, "# HELP {} {}", name, help);
+    let _ = writeln!(output, "# TYPE {} {}", name, kind);
+    let _ = writeln!(output, "{} {}", name, value);
+}
+
+fn write_code_metric(
+    output: &mut String,
+    stats: &ReceiverStats,
+    name: &str,
+    help: &str,
+    value: fn(&CodeStats) -> usize,
+) {
+    let _ = writeln!(output, "# HELP {} {}", name, help);
+    let _ = writeln!(output, "# TYPE {} counter", name);
+    for (label, code) in &stats.codes {
+        let _ = writeln!(output, "{}{{code=\"{}\"}} {}", name, label, value(code));
+    }
+}
+
+pub fn render_prometheus(stats: &ReceiverStats, open_connections: usize) -> String {
+    let mut output = String::new();
+
+    write_metric(
+        &mut output,
+        "ecc_connections_total",
+        "counter",
+        "Connections served.",
+        stats.connections,
+    );
+    write_metric(
+        &mut output,
+        "ecc_open_connections",
+        "gauge",
+        "Connections open right now.",
+        open_connections,
+    );
+    write_metric(
+        &mut output,
+        "ecc_harq_nacks_sent_total",
+        "counter",
+        "HARQ NACKs sent.",
+        stats.harq_nacks_sent,
+    );
+    write_metric(
+        &mut output,
+        "ecc_error_packets_sent_total",
+        "counter",
+        "Error packets sent.",
+        stats.errors_sent,
+    );
+    write_metric(
+        &mut output,
+        "ecc_datagrams_received_total",
+        "counter",
+        "UDP datagrams received.",
+        stats.datagrams_received,
+    );
+    write_metric(
+        &mut output,
+        "ecc_datagrams_lost_total",
+        "counter",
+        "UDP datagrams that never arrived.",
+        stats.datagrams_lost,
+    );
+
+    write_code_metric(
+        &mut output,
+        stats,
+        "ecc_messages_total",
+        "Messages decoded.",
+        |code| code.messages,
+    );
+    write_code_metric(
+        &mut output,
+        stats,
+        "ecc_bits_received_total",
+        "Coded bits received.",
+        |code| code.bits_received,
+    );
+    write_code_metric(
+        &mut output,
+        stats,
+        "ecc_errors_detected_total",
+        "Messages the code found errors in.",
+        |code| code.errors_detected,
+    );
+    write_code_metric(
+        &mut output,
+        stats,
+        "ecc_messages_corrected_total",
+        "Messages the code corrected.",
+        |code| code.corrected,
+    );
+    write_code_metric(
+        &mut output,
+        stats,
+        "ecc_corrected_bits_total",
+        "Bits the code corrected.",
+        |code| code.corrected_bits,
+    );
+    write_code_metric(
+        &mut output,
+        stats,
+        "ecc_uncorrectable_total",
+        "Messages with errors the code could not correct.",
+        |code| code.uncorrectable,
+    );
+    write_code_metric(
+        &mut output,
+        stats,
+        "ecc_ground_truth_messages_total",
+        "Messages the sender sent the clean bits for.",
+        |code| code.truth_messages,
+    );
+    write_code_metric(
+        &mut output,
+        stats,
+        "ecc_residual_bit_errors_total",
+        "Bit errors left after decoding, against the ground truth.",
+        |code| code.residual_bit_errors,
+    );
+    write_code_metric(
+        &mut output,
+        stats,
+        "ecc_residual_message_errors_total",
+        "Messages decoded wrong, against the ground truth.",
+        |code| code.residual_message_errors,
+    );
+
+    output
+}
+
+// One line of the request, an error if it doesn't end in time or within MAX_LINE_LENGTH
+fn read_request_line(reader: &mut BufReader<TcpStream>, deadline: Instant) -> io::Result<String> {
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    if remaining.is_zero() {
+        return Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "request took too long",
+        ));
+    }
+    reader.get_ref().set_read_timeout(Some(remaining))?;
+
+    let mut line = String::new();
+    reader.by_ref().take(MAX_LINE_LENGTH).read_line(&mut line)?;
+    if !line.ends_with('\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "request line too long or cut off",
+        ));
+    }
+    Ok(line)
+}
+
+fn answer_request(stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    let deadline = Instant::now() + REQUEST_TIMEOUT;
+    let mut reader = BufReader::new(stream);
+    let request_line = read_request_line(&mut reader, deadline)?;
+    // Skip the headers up to the empty line, nothing in them matters here
+    let mut headers = 0;
+    while !read_request_line(&mut reader, deadline)?
+        .trim_end()
+        .is_empty()
+    {
+        headers += 1;
+        if headers > MAX_HEADERS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "too many headers",
+            ));
+        }
+    }
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or("");
+    let (status, body) = if request_line.starts_with("GET ") && path == "/metrics" {
+        ("200 OK", metrics.render())
+    } else {
+        ("404 Not Found", "Only /metrics is here\n".to_string())
+    };
+
+    let mut stream = reader.into_inner();
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+/// Answers scrapes on its own thread for as long as the receiver runs.
+pub fn serve_metrics(address: SocketAddr, metrics: Arc<Metrics>) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(address)?;
+    let address = listener.local_addr()?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = answer_request(stream, &metrics) {
+                println!("Metrics request failed: {}", e);
+            }
+        }
+    });
+
+    Ok(address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::DecodeOutcome;
+
+    #[test]
+    fn test_snapshot_includes_open_connections() {
+        let metrics = Metrics::new();
+        let first = metrics.open();
+        let second = metrics.open();
+
+        let mut stats = ReceiverStats {
+            connections: 1,
+            ..Default::default()
+        };
+        stats.record("hamming", DecodeOutcome::Corrected, 1);
+        metrics.update(first, &stats);
+        metrics.close(second, &stats);
+
+        let (total, open) = metrics.snapshot();
+        assert_eq!((total.connections, open), (2, 1));
+        assert_eq!(total.codes["hamming"].corrected_bits, 2);
+        assert_eq!(metrics.closed().messages, 1);
+
+        let text = metrics.render();
+        assert!(text.contains("ecc_open_connections 1\n"));
+        assert!(text.contains("ecc_corrected_bits_total{code=\"hamming\"} 2\n"));
+    }
+
+    #[test]
+    fn test_serves_metrics_over_http() {
+        let metrics = Arc::new(Metrics::new());
+        let address = serve_metrics("127.0.0.1:0".parse().unwrap(), metrics).unwrap();
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("ecc_connections_total 0"));
+    }
+
+    #[test]
+    fn test_endless_request_line_is_dropped() {
+        let metrics = Arc::new(Metrics::new());
+        let address = serve_metrics("127.0.0.1:0".parse().unwrap(), metrics).unwrap();
+
+        // No newline ever comes, the server gives up after MAX_LINE_LENGTH bytes
+        let mut stream = TcpStream::connect(address).unwrap();
+        let _ = stream.write_all(&vec![b'A'; MAX_LINE_LENGTH as usize + 1]);
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+        assert!(response.is_empty());
+
+        // and is free for the next scrape
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+    }
+}
@@ -0,0 +1,267 @@
+use std::ops::{Add, Mul, Sub};
+
+/* Turns bits into the complex symbols that go over a radio link and back. Every
+ * constellation is Gray mapped, so the nearest wrong symbol costs one bit, and has
+ * an average energy of 1 per symbol.
+ *
+ *   BPSK     1 bit per symbol    0 -> +1, 1 -> -1
+ *   QPSK     2 bits per symbol   one BPSK on each axis, scaled by 1/sqrt(2)
+ *   16-QAM   4 bits per symbol   two bits per axis, 00 01 11 10 -> -3 -1 +1 +3,
+ *                                scaled by 1/sqrt(10)
+ *
+ * The first bits of a symbol go on the real axis.
+ */
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Symbol {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Symbol {
+    pub fn new(re: f64, im: f64) -> Self {
+        Symbol { re, im }
+    }
+
+    pub fn norm_sqr(&self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    pub fn scale(&self, factor: f64) -> Self {
+        Symbol::new(self.re * factor, self.im * factor)
+    }
+}
+
+impl Add for Symbol {
+    type Output = Symbol;
+
+    fn add(self, other: Symbol) -> Symbol {
+        Symbol::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Symbol {
+    type Output = Symbol;
+
+    fn sub(self, other: Symbol) -> Symbol {
+        Symbol::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Symbol {
+    type Output = Symbol;
+
+    fn mul(self, other: Symbol) -> Symbol {
+        Symbol::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Modulation {
+    Bpsk,
+    Qpsk,
+    Qam16,
+}
+
+// Gray coded levels of one axis, for the bits of that axis read as a number
+fn axis_level(bits: usize, bits_per_axis: usize) -> f64 {
+    match (bits_per_axis, bits) {
+        (1, 0) => 1.0,
+        (1, _) => -1.0,
+        (_, 0b00) => -3.0,
+        (_, 0b01) => -1.0,
+        (_, 0b11) => 1.0,
+        (_, _) => 3.0,
+    }
+}
+
+impl Modulation {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "bpsk" => Ok(Modulation::Bpsk),
+            "qpsk" => Ok(Modulation::Qpsk),
+            "16qam" => Ok(Modulation::Qam16),
+            _ => Err(format!(
+                "Unknown modulation: {}, use bpsk, qpsk or 16qam",
+                name
+            )),
+        }
+    }
+
+    pub fn bits_per_symbol(&self) -> usize {
+        match self {
+            Modulation::Bpsk => 1,
+            Modulation::Qpsk => 2,
+            Modulation::Qam16 => 4,
+        }
+    }
+
+    /// The symbol for the bits of `label`, first bit most significant.
+    pub fn point(&self, label: usize) -> Symbol {
+        match self {
+            Modulation::Bpsk => Symbol::new(axis_level(label, 1), 0.0),
+            Modulation::Qpsk => Symbol::new(axis_level(label >> 1, 1), axis_level(label & 1, 1))
+                .scale(1.0 / 2f64.sqrt()),
+            Modulation::Qam16 => Symbol::new(axis_level(label >> 2, 2), axis_level(label & 3, 2))
+                .scale(1.0 / 10f64.sqrt()),
+        }
+    }
+
+    /// Every symbol, indexed by its label.
+    pub fn constellation(&self) -> Vec<Symbol> {
+        (0..1 << self.bits_per_symbol())
+            .map(|label| self.point(label))
+            .collect()
+    }
+
+    /// The last symbol is filled up with 0s, cut them off after demapping.
+    pub fn modulate(&self, bits: &str) -> Vec<Symbol> {
+        let bits: Vec<char> = bits.chars().collect();
+        bits.chunks(self.bits_per_symbol())
+            .map(|chunk| {
+                let label = (0..self.bits_per_symbol()).fold(0, |label, i| {
+                    (label << 1) | (chunk.get(i) == Some(&'1')) as usize
+                });
+                self.point(label)
+            })
+            .collect()
+    }
+
+    /// The bits of the nearest symbol, after each one went through its channel gain.
+    pub fn demap_hard(&self, received: &[Symbol], gains: &[Symbol]) -> String {
+        let constellation = self.constellation();
+        let mut bits = String::with_capacity(received.len() * self.bits_per_symbol());
+
+        for (symbol, gain) in received.iter().zip(gains) {
+            let (label, _) = constellation
+                .iter()
+                .enumerate()
+                .map(|(label, point)| (label, (*symbol - *gain * *point).norm_sqr()))
+                .fold((0, f64::INFINITY), |best, candidate| {
+                    if candidate.1 < best.1 {
+                        candidate
+                    } else {
+                        best
+                    }
+                });
+            bits.push_str(&format!(
+                "{:0width$b}",
+                label,
+                width = self.bits_per_symbol()
+            ));
+        }
+
+        bits
+    }
+
+    /* Log likelihood ratios, log P(bit = 0) / P(bit = 1), for soft decoders. Positive
+     * means 0, and the further from 0 the surer. Max-log approximation: only the
+     * nearest symbol with each value of the bit counts.
+     * `noise_density` is N0, the complex noise variance per symbol.
+     */
+    pub fn demap_llr(&self, received: &[Symbol], gains: &[Symbol], noise_density: f64) -> Vec<f64> {
+        let constellation = self.constellation();
+        let bits_per_symbol = self.bits_per_symbol();
+        let mut llrs = Vec::with_capacity(received.len() * bits_per_symbol);
+
+        for (symbol, gain) in received.iter().zip(gains) {
+            let distances: Vec<f64> = constellation
+                .iter()
+                .map(|point| (*symbol - *gain * *point).norm_sqr())
+                .collect();
+
+            for bit in (0..bits_per_symbol).rev() {
+                let (mut nearest_zero, mut nearest_one) = (f64::INFINITY, f64::INFINITY);
+                for (label, distance) in distances.iter().enumerate() {
+                    if (label >> bit) & 1 == 0 {
+                        nearest_zero = nearest_zero.min(*distance);
+                    } else {
+                        nearest_one = nearest_one.min(*distance);
+                    }
+                }
+                llrs.push((nearest_one - nearest_zero) / noise_density);
+            }
+        }
+
+        llrs
+    }
+}
+
+/// Hard decisions from LLRs, 0 when there's nothing to go on.
+pub fn llrs_to_bits(llrs: &[f64]) -> String {
+    llrs.iter()
+        .map(|llr| if *llr >= 0.0 { '0' } else { '1' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODULATIONS: [Modulation; 3] = [Modulation::Bpsk, Modulation::Qpsk, Modulation::Qam16];
+
+    #[test]
+    fn test_constellations_are_gray_mapped_with_unit_energy() {
+        for modulation in MODULATIONS {
+            let constellation = modulation.constellation();
+            let energy: f64 = constellation.iter().map(Symbol::norm_sqr).sum::<f64>()
+                / constellation.len() as f64;
+            assert!((energy - 1.0).abs() < 1e-9, "{:?}", modulation);
+
+            // The nearest other symbols differ in exactly one bit
+            for (label, point) in constellation.iter().enumerate() {
+                let distances: Vec<f64> = constellation
+                    .iter()
+                    .map(|other| (*other - *point).norm_sqr())
+                    .collect();
+                let nearest = distances
+                    .iter()
+                    .filter(|distance| **distance > 1e-9)
+                    .fold(f64::INFINITY, |a, b| a.min(*b));
+                for (other, distance) in distances.iter().enumerate() {
+                    if (distance - nearest).abs() < 1e-9 {
+                        assert_eq!((label ^ other).count_ones(), 1, "{:?}", modulation);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_demapping_without_noise() {
+        let bits = "1100101001110";
+        for modulation in MODULATIONS {
+            let symbols = modulation.modulate(bits);
+            assert_eq!(
+                symbols.len(),
+                bits.len().div_ceil(modulation.bits_per_symbol())
+            );
+
+            // A gain turns and shrinks every symbol, the demappers undo it
+            let gains = vec![Symbol::new(0.3, -0.4); symbols.len()];
+            let received: Vec<Symbol> = symbols
+                .iter()
+                .zip(&gains)
+                .map(|(symbol, gain)| *gain * *symbol)
+                .collect();
+
+            let hard = modulation.demap_hard(&received, &gains);
+            assert_eq!(&hard[..bits.len()], bits);
+            let llrs = modulation.demap_llr(&received, &gains, 0.1);
+            assert_eq!(&llrs_to_bits(&llrs)[..bits.len()], bits);
+        }
+    }
+
+    #[test]
+    fn test_llr_gets_less_sure_near_the_boundary() {
+        let gain = [Symbol::new(1.0, 0.0)];
+        let far = Modulation::Bpsk.demap_llr(&[Symbol::new(-1.0, 0.0)], &gain, 1.0);
+        let near = Modulation::Bpsk.demap_llr(&[Symbol::new(-0.1, 0.0)], &gain, 1.0);
+        // |y + 1|^2 - |y - 1|^2 = 4y for BPSK
+        assert!((far[0] + 4.0).abs() < 1e-9);
+        assert!((near[0] + 0.4).abs() < 1e-9);
+    }
+}
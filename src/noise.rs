@@ -1,14 +1,462 @@
-use rand::Rng;
+use rand::seq::index::sample;
+use rand::{Rng, RngCore};
+
+use std::f64::consts::PI;
 
 use crate::correction::CorrectionType;
+use crate::modulation::{Modulation, Symbol};
+
+/* Channels take bits and give back what arrives at the other end. Unlike add_noise
+ * they know nothing about the code, so any code can be tried against any channel.
+ * The randomness comes from the caller, seed it and the same noise comes out again.
+ */
+pub trait Channel {
+    fn transmit(&mut self, bits: &str, rng: &mut dyn RngCore) -> String;
+
+    /// What arrives, and which bits got flipped on the way.
+    fn transmit_reported(&mut self, bits: &str, rng: &mut dyn RngCore) -> (String, NoiseReport) {
+        let received = self.transmit(bits, rng);
+        let report = NoiseReport::between(bits, &received);
+        (received, report)
+    }
+}
+
+/// What the noise did, counting from the start of the transmission.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NoiseReport {
+    pub flipped_positions: Vec<usize>,
+    // Where the added bits are in what arrived
+    pub inserted_positions: Vec<usize>,
+    // Where the lost bits were in what was sent
+    pub deleted_positions: Vec<usize>,
+}
+
+impl NoiseReport {
+    /// Lengths one apart are taken as a single bit lost or added, at the first
+    /// difference. Channels that can do more than that report it themselves.
+    pub fn between(sent: &str, received: &str) -> Self {
+        let mut report = NoiseReport::default();
+        let first_difference = sent
+            .chars()
+            .zip(received.chars())
+            .take_while(|(sent, received)| sent == received)
+            .count();
+
+        if sent.len() == received.len() {
+            report.flipped_positions = sent
+                .chars()
+                .zip(received.chars())
+                .enumerate()
+                .filter(|(_, (sent, received))| sent != received)
+                .map(|(position, _)| position)
+                .collect();
+        } else if sent.len() == received.len() + 1 {
+            report.deleted_positions.push(first_difference);
+        } else if sent.len() + 1 == received.len() {
+            report.inserted_positions.push(first_difference);
+        }
+
+        report
+    }
+}
+
+/// Everything arrives as it was sent.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NoiselessChannel;
+
+impl Channel for NoiselessChannel {
+    fn transmit(&mut self, bits: &str, _rng: &mut dyn RngCore) -> String {
+        bits.to_string()
+    }
+}
+
+/// Flips every bit on its own with probability `crossover`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BinarySymmetricChannel {
+    crossover: f64,
+}
+
+impl BinarySymmetricChannel {
+    /// Err when `crossover` is not a probability.
+    pub fn new(crossover: f64) -> Result<Self, String> {
+        if (0.0..=1.0).contains(&crossover) {
+            Ok(BinarySymmetricChannel { crossover })
+        } else {
+            Err(format!(
+                "{} is not a probability between 0 and 1",
+                crossover
+            ))
+        }
+    }
+}
+
+impl Channel for BinarySymmetricChannel {
+    fn transmit(&mut self, bits: &str, rng: &mut dyn RngCore) -> String {
+        bits.chars()
+            .map(|bit| {
+                if rng.gen_bool(self.crossover) {
+                    flipped(bit)
+                } else {
+                    bit
+                }
+            })
+            .collect()
+    }
+}
+
+/// Flips exactly `flips` distinct bits, or all of them in shorter transmissions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FixedFlipsChannel {
+    pub flips: usize,
+}
+
+impl Channel for FixedFlipsChannel {
+    fn transmit(&mut self, bits: &str, rng: &mut dyn RngCore) -> String {
+        let mut output: Vec<char> = bits.chars().collect();
+        for position in sample(rng, output.len(), self.flips.min(output.len())) {
+            output[position] = flipped(output[position]);
+        }
+        output.into_iter().collect()
+    }
+}
+
+/* Gilbert-Elliott: a two state Markov chain that decides, bit by bit, whether the
+ * channel is in its good or its bad state, and each state flips bits with its own
+ * probability. A small bad_to_good keeps the channel bad for long stretches, so the
+ * errors come in bursts instead of spread out like on the binary symmetric channel.
+ * The state carries over from one transmission to the next.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GilbertElliottChannel {
+    pub good_error_rate: f64,
+    pub bad_error_rate: f64,
+    pub good_to_bad: f64,
+    pub bad_to_good: f64,
+    pub bad: bool,
+}
+
+impl GilbertElliottChannel {
+    /// Starts in the good state.
+    pub fn new(
+        good_error_rate: f64,
+        bad_error_rate: f64,
+        good_to_bad: f64,
+        bad_to_good: f64,
+    ) -> Self {
+        GilbertElliottChannel {
+            good_error_rate,
+            bad_error_rate,
+            good_to_bad,
+            bad_to_good,
+            bad: false,
+        }
+    }
+
+    /// How much of the time the channel spends in the bad state, in the long run.
+    pub fn bad_fraction(&self) -> f64 {
+        let switches = self.good_to_bad + self.bad_to_good;
+        if switches == 0.0 {
+            return if self.bad { 1.0 } else { 0.0 };
+        }
+        self.good_to_bad / switches
+    }
+}
+
+impl Channel for GilbertElliottChannel {
+    fn transmit(&mut self, bits: &str, rng: &mut dyn RngCore) -> String {
+        bits.chars()
+            .map(|bit| {
+                let error_rate = if self.bad {
+                    self.bad_error_rate
+                } else {
+                    self.good_error_rate
+                };
+                let output = if rng.gen_bool(error_rate) {
+                    flipped(bit)
+                } else {
+                    bit
+                };
+                let switch = if self.bad {
+                    self.bad_to_good
+                } else {
+                    self.good_to_bad
+                };
+                if rng.gen_bool(switch) {
+                    self.bad = !self.bad;
+                }
+                output
+            })
+            .collect()
+    }
+}
+
+/// Flips `length` bits in a row at a random place, all of them in shorter transmissions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BurstChannel {
+    pub length: usize,
+}
+
+impl Channel for BurstChannel {
+    fn transmit(&mut self, bits: &str, rng: &mut dyn RngCore) -> String {
+        let mut output: Vec<char> = bits.chars().collect();
+        let length = self.length.min(output.len());
+        let start = rng.gen_range(0..=output.len() - length);
+        for bit in &mut output[start..start + length] {
+            *bit = flipped(*bit);
+        }
+        output.into_iter().collect()
+    }
+}
+
+/// Loses every bit with probability `deletion` and puts a random bit in front of
+/// every bit with probability `insertion`. Everything after that shifts along.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InsertionDeletionChannel {
+    pub insertion: f64,
+    pub deletion: f64,
+}
+
+impl Channel for InsertionDeletionChannel {
+    fn transmit(&mut self, bits: &str, rng: &mut dyn RngCore) -> String {
+        self.transmit_reported(bits, rng).0
+    }
+
+    fn transmit_reported(&mut self, bits: &str, rng: &mut dyn RngCore) -> (String, NoiseReport) {
+        let mut output = String::with_capacity(bits.len());
+        let mut report = NoiseReport::default();
+
+        for (position, bit) in bits.chars().enumerate() {
+            if rng.gen_bool(self.insertion) {
+                report.inserted_positions.push(output.len());
+                output.push(if rng.gen_bool(0.5) { '1' } else { '0' });
+            }
+            if rng.gen_bool(self.deletion) {
+                report.deleted_positions.push(position);
+            } else {
+                output.push(bit);
+            }
+        }
+
+        (output, report)
+    }
+}
+
+/// A standard normal sample, by Box-Muller.
+pub fn gaussian(rng: &mut dyn RngCore) -> f64 {
+    // 1 - [0, 1) keeps the logarithm finite
+    let radius = (-2.0 * (1.0 - rng.gen::<f64>()).ln()).sqrt();
+    radius * (2.0 * PI * rng.gen::<f64>()).cos()
+}
+
+// Circularly symmetric complex normal with the given variance
+fn complex_gaussian(variance: f64, rng: &mut dyn RngCore) -> Symbol {
+    let deviation = (variance / 2.0).sqrt();
+    Symbol::new(gaussian(rng) * deviation, gaussian(rng) * deviation)
+}
+
+/// Flat fading, drawn anew for every symbol. The receiver knows each gain.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fading {
+    None,
+    Rayleigh,
+    // K factor: power in the direct path over the power in the scattered ones
+    Rician(f64),
+}
+
+impl Fading {
+    // Average power 1, so the SNR is the same on average as without fading
+    fn gain(&self, rng: &mut dyn RngCore) -> Symbol {
+        match self {
+            Fading::None => Symbol::new(1.0, 0.0),
+            Fading::Rayleigh => complex_gaussian(1.0, rng),
+            Fading::Rician(k) => {
+                let direct = (k / (k + 1.0)).sqrt();
+                Symbol::new(direct, 0.0) + complex_gaussian(1.0 / (k + 1.0), rng)
+            }
+        }
+    }
+}
+
+/* The bits are modulated, every symbol is multiplied by its fading gain and gets
+ * white Gaussian noise added, and the receiver demaps them back with the gains in
+ * mind. `ebn0_db` is the energy per bit that goes over the channel over the noise
+ * density, in dB. A code of rate R spends 1/R of those bits on every data bit.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ModulatedChannel {
+    pub modulation: Modulation,
+    pub ebn0_db: f64,
+    pub fading: Fading,
+}
+
+impl ModulatedChannel {
+    /// N0 for symbols with energy 1.
+    pub fn noise_density(&self) -> f64 {
+        let bit_energy = 1.0 / self.modulation.bits_per_symbol() as f64;
+        bit_energy / 10f64.powf(self.ebn0_db / 10.0)
+    }
+
+    /// What arrives and the gain each symbol went through.
+    pub fn transmit_symbols(
+        &self,
+        bits: &str,
+        rng: &mut dyn RngCore,
+    ) -> (Vec<Symbol>, Vec<Symbol>) {
+        let noise_density = self.noise_density();
+        self.modulation
+            .modulate(bits)
+            .into_iter()
+            .map(|symbol| {
+                let gain = self.fading.gain(rng);
+                (gain * symbol + complex_gaussian(noise_density, rng), gain)
+            })
+            .unzip()
+    }
+
+    /// One log likelihood ratio per bit, positive for 0, for decoders that can use them.
+    pub fn transmit_llrs(&self, bits: &str, rng: &mut dyn RngCore) -> Vec<f64> {
+        let (received, gains) = self.transmit_symbols(bits, rng);
+        let mut llrs = self
+            .modulation
+            .demap_llr(&received, &gains, self.noise_density());
+        llrs.truncate(bits.len());
+        llrs
+    }
+}
+
+impl Channel for ModulatedChannel {
+    fn transmit(&mut self, bits: &str, rng: &mut dyn RngCore) -> String {
+        let (received, gains) = self.transmit_symbols(bits, rng);
+        let mut output = self.modulation.demap_hard(&received, &gains);
+        output.truncate(bits.len());
+        output
+    }
+}
+
+/// What the sender does to its own packets when there's no channel proxy in between.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoiseMode {
+    // The noise each code is meant to handle, what the sender has always done
+    Code,
+    None,
+    Flip(usize),
+    BinarySymmetric(BinarySymmetricChannel),
+    Burst(usize),
+    GilbertElliott(GilbertElliottChannel),
+    InsertionDeletion(InsertionDeletionChannel),
+    Modulated(ModulatedChannel),
+}
+
+fn parse_probability(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(p) if (0.0..=1.0).contains(&p) => Ok(p),
+        _ => Err(format!("{} is not a probability between 0 and 1", value)),
+    }
+}
+
+const NOISE_NAMES: &str = "code, none, flip:N, bsc:P, burst:LEN, ge:PG:PB:G2B:B2G, indel:PI:PD, \
+    awgn:MOD:EBN0, rayleigh:MOD:EBN0 or rician:MOD:EBN0:K";
 
-pub fn add_noise(message: String, correction_type: &CorrectionType) -> String {
+fn modulated(modulation: &str, ebn0_db: &str, fading: Fading) -> Result<NoiseMode, String> {
+    Ok(NoiseMode::Modulated(ModulatedChannel {
+        modulation: Modulation::parse(modulation)?,
+        ebn0_db: ebn0_db
+            .parse::<f64>()
+            .map_err(|_| format!("{} is not an Eb/N0 in dB", ebn0_db))?,
+        fading,
+    }))
+}
+
+impl NoiseMode {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = spec.split(':').collect();
+
+        match parts.as_slice() {
+            ["none"] => Ok(NoiseMode::None),
+            ["code"] => Ok(NoiseMode::Code),
+            ["flip", bits] => bits
+                .parse::<usize>()
+                .map(NoiseMode::Flip)
+                .map_err(|_| format!("{} is not a number of bits", bits)),
+            ["bsc", p] => Ok(NoiseMode::BinarySymmetric(BinarySymmetricChannel::new(
+                parse_probability(p)?,
+            )?)),
+            ["burst", length] => match length.parse::<usize>() {
+                Ok(length) if length > 0 => Ok(NoiseMode::Burst(length)),
+                _ => Err(format!("burst length {} has to be at least 1", length)),
+            },
+            // The good state's error rate, the bad state's, then the two transitions
+            ["ge", good, bad, good_to_bad, bad_to_good] => {
+                Ok(NoiseMode::GilbertElliott(GilbertElliottChannel::new(
+                    parse_probability(good)?,
+                    parse_probability(bad)?,
+                    parse_probability(good_to_bad)?,
+                    parse_probability(bad_to_good)?,
+                )))
+            }
+            ["indel", insertion, deletion] => {
+                Ok(NoiseMode::InsertionDeletion(InsertionDeletionChannel {
+                    insertion: parse_probability(insertion)?,
+                    deletion: parse_probability(deletion)?,
+                }))
+            }
+            ["awgn", modulation, ebn0_db] => modulated(modulation, ebn0_db, Fading::None),
+            ["rayleigh", modulation, ebn0_db] => modulated(modulation, ebn0_db, Fading::Rayleigh),
+            ["rician", modulation, ebn0_db, k] => match k.parse::<f64>() {
+                Ok(k) if k >= 0.0 => modulated(modulation, ebn0_db, Fading::Rician(k)),
+                _ => Err(format!("K factor {} has to be 0 or more", k)),
+            },
+            _ => Err(format!("Unknown noise: {}, use {}", spec, NOISE_NAMES)),
+        }
+    }
+
+    /// None for Code, which picks the noise from the code with add_noise.
+    pub fn channel(&self) -> Option<Box<dyn Channel>> {
+        match self {
+            NoiseMode::Code => None,
+            NoiseMode::None => Some(Box::new(NoiselessChannel)),
+            NoiseMode::Flip(flips) => Some(Box::new(FixedFlipsChannel { flips: *flips })),
+            NoiseMode::BinarySymmetric(channel) => Some(Box::new(*channel)),
+            NoiseMode::Burst(length) => Some(Box::new(BurstChannel { length: *length })),
+            NoiseMode::GilbertElliott(channel) => Some(Box::new(*channel)),
+            NoiseMode::InsertionDeletion(channel) => Some(Box::new(*channel)),
+            NoiseMode::Modulated(channel) => Some(Box::new(*channel)),
+        }
+    }
+}
+
+/// `--noise`, Code when it isn't given.
+pub fn noise_from_args(args: &[String]) -> Result<NoiseMode, String> {
+    match args.iter().position(|arg| arg == "--noise") {
+        None => Ok(NoiseMode::Code),
+        Some(index) => match args.get(index + 1) {
+            Some(spec) => NoiseMode::parse(spec),
+            None => Err(format!("--noise needs {}", NOISE_NAMES)),
+        },
+    }
+}
+
+/// `--seed`, None when it isn't given and a random one should be used.
+pub fn seed_from_args(args: &[String]) -> Result<Option<u64>, String> {
+    match args.iter().position(|arg| arg == "--seed") {
+        None => Ok(None),
+        Some(index) => match args.get(index + 1).map(|seed| seed.parse::<u64>()) {
+            Some(Ok(seed)) => Ok(Some(seed)),
+            _ => Err("--seed needs a number".to_string()),
+        },
+    }
+}
+
+pub fn add_noise(
+    message: String,
+    correction_type: &CorrectionType,
+    rng: &mut dyn RngCore,
+) -> String {
     let len_message = message.len();
-    let mut rng = rand::thread_rng();
     let mut output = message.clone();
 
     match correction_type {
-        CorrectionType::Parity => {
+        CorrectionType::Parity | CorrectionType::Crc | CorrectionType::Product(_) => {
             let where_to_flip = rng.gen_range(10..len_message - 1);
             flip_bit(&mut output, where_to_flip);
         }
@@ -43,14 +491,283 @@ pub fn add_noise(message: String, correction_type: &CorrectionType) -> String {
                 }
             }
         }
+        CorrectionType::Convolutional => {
+            // One flip in the first third and one in the last, far enough apart to fix
+            flip_bit(&mut output, rng.gen_range(0..len_message / 3));
+            flip_bit(&mut output, rng.gen_range(len_message * 2 / 3..len_message));
+        }
+        CorrectionType::Vt => {
+            // Lose a bit or add one
+            let position = rng.gen_range(0..=len_message);
+            if position < len_message && rng.gen_bool(0.5) {
+                output.remove(position);
+            } else {
+                output.insert(position, if rng.gen_bool(0.5) { '1' } else { '0' });
+            }
+        }
+        CorrectionType::Concatenated(code) => {
+            // The inner code is what goes on the wire, so it decides what noise is fair
+            output = add_noise(output, &code.inner, rng);
+        }
+    }
+
+    output
+}
+
+// Flip `num_bits_to_flip` distinct random bits anywhere in the message
+pub fn flip_random_bits(message: String, num_bits_to_flip: usize, rng: &mut dyn RngCore) -> String {
+    let mut output = message;
+    let mut flipped_positions = vec![];
+
+    while flipped_positions.len() < num_bits_to_flip.min(output.len()) {
+        let pos = rng.gen_range(0..output.len());
+        if !flipped_positions.contains(&pos) {
+            flip_bit(&mut output, pos);
+            flipped_positions.push(pos);
+        }
     }
 
     output
 }
 
+fn flipped(bit: char) -> char {
+    if bit == '1' {
+        '0'
+    } else {
+        '1'
+    }
+}
+
 fn flip_bit(message: &mut String, index: usize) {
     if let Some(bit) = message.chars().nth(index) {
         let flipped_bit = if bit == '1' { '0' } else { '1' };
         message.replace_range(index..index + 1, &flipped_bit.to_string());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn differences(a: &str, b: &str) -> usize {
+        a.chars().zip(b.chars()).filter(|(a, b)| a != b).count()
+    }
+
+    #[test]
+    fn test_noise_modes() {
+        assert_eq!(NoiseMode::parse("none"), Ok(NoiseMode::None));
+        assert_eq!(NoiseMode::parse("flip:3"), Ok(NoiseMode::Flip(3)));
+        assert_eq!(
+            NoiseMode::parse("bsc:0.1"),
+            Ok(NoiseMode::BinarySymmetric(
+                BinarySymmetricChannel::new(0.1).unwrap()
+            ))
+        );
+        assert!(NoiseMode::parse("flip:x").is_err());
+        assert!(NoiseMode::parse("bsc:2").is_err());
+        assert!(NoiseMode::parse("gaussian").is_err());
+        assert!(NoiseMode::Code.channel().is_none());
+        assert_eq!(NoiseMode::parse("burst:8"), Ok(NoiseMode::Burst(8)));
+        assert!(NoiseMode::parse("burst:0").is_err());
+        assert_eq!(
+            NoiseMode::parse("ge:0:0.5:0.01:0.1"),
+            Ok(NoiseMode::GilbertElliott(GilbertElliottChannel::new(
+                0.0, 0.5, 0.01, 0.1
+            )))
+        );
+        assert!(NoiseMode::parse("ge:0:0.5:0.01").is_err());
+    }
+
+    #[test]
+    fn test_seeded_noise_repeats() {
+        let bits = "1011001110001111011100101";
+        let noisy = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let code_noise = add_noise(bits.to_string(), &CorrectionType::Hamming, &mut rng);
+            let flips = flip_random_bits(bits.to_string(), 3, &mut rng);
+            let (received, report) = BinarySymmetricChannel::new(0.3)
+                .unwrap()
+                .transmit_reported(bits, &mut rng);
+            (code_noise, flips, received, report)
+        };
+        assert_eq!(noisy(42), noisy(42));
+
+        let (_, flips, received, report) = noisy(42);
+        assert_eq!(
+            NoiseReport::between(bits, &flips).flipped_positions.len(),
+            3
+        );
+        for (position, (sent, got)) in bits.chars().zip(received.chars()).enumerate() {
+            assert_eq!(report.flipped_positions.contains(&position), sent != got);
+        }
+
+        let args: Vec<String> = ["sender", "--seed", "7"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        assert_eq!(seed_from_args(&args), Ok(Some(7)));
+        assert_eq!(seed_from_args(&args[..1]), Ok(None));
+        assert!(seed_from_args(&args[..2]).is_err());
+    }
+
+    #[test]
+    fn test_insertion_deletion_channel() {
+        let mut rng = StdRng::seed_from_u64(9);
+        let bits = "0".repeat(200);
+
+        let mut lossy = InsertionDeletionChannel {
+            insertion: 0.0,
+            deletion: 0.1,
+        };
+        let (received, report) = lossy.transmit_reported(&bits, &mut rng);
+        assert!(!report.deleted_positions.is_empty());
+        assert_eq!(received.len(), bits.len() - report.deleted_positions.len());
+
+        let mut noisy = InsertionDeletionChannel {
+            insertion: 0.1,
+            deletion: 0.0,
+        };
+        let (received, report) = noisy.transmit_reported(&bits, &mut rng);
+        assert_eq!(received.len(), bits.len() + report.inserted_positions.len());
+        let mut without: Vec<char> = received.chars().collect();
+        for position in report.inserted_positions.iter().rev() {
+            without.remove(*position);
+        }
+        assert_eq!(without.into_iter().collect::<String>(), bits);
+
+        assert_eq!(
+            NoiseReport::between("1011", "111").deleted_positions,
+            vec![1]
+        );
+        assert_eq!(
+            NoiseReport::between("1001", "10011").inserted_positions,
+            vec![4]
+        );
+    }
+
+    fn bit_error_rate(channel: &mut ModulatedChannel, rng: &mut StdRng) -> f64 {
+        let bits: String = (0..100_000)
+            .map(|_| if rng.gen_bool(0.5) { '1' } else { '0' })
+            .collect();
+        let received = channel.transmit(&bits, rng);
+        assert_eq!(received.len(), bits.len());
+        differences(&bits, &received) as f64 / bits.len() as f64
+    }
+
+    #[test]
+    fn test_modulated_channels_match_theory() {
+        let mut rng = StdRng::seed_from_u64(13);
+        let mut channel = ModulatedChannel {
+            modulation: Modulation::Bpsk,
+            ebn0_db: 4.0,
+            fading: Fading::None,
+        };
+        // Q(sqrt(2 Eb/N0)) at 4 dB
+        let ber = bit_error_rate(&mut channel, &mut rng);
+        assert!((ber - 0.0125).abs() < 0.002, "{}", ber);
+
+        // Gray mapped QPSK is two BPSKs, same error rate per bit
+        channel.modulation = Modulation::Qpsk;
+        let ber = bit_error_rate(&mut channel, &mut rng);
+        assert!((ber - 0.0125).abs() < 0.002, "{}", ber);
+
+        // Rayleigh: (1 - sqrt(g / (1 + g))) / 2 with g = Eb/N0 = 10 at 10 dB
+        channel.modulation = Modulation::Bpsk;
+        channel.ebn0_db = 10.0;
+        channel.fading = Fading::Rayleigh;
+        let ber = bit_error_rate(&mut channel, &mut rng);
+        assert!((ber - 0.0233).abs() < 0.003, "{}", ber);
+
+        // A strong direct path brings it close to no fading at all
+        channel.fading = Fading::Rician(100.0);
+        let rician = bit_error_rate(&mut channel, &mut rng);
+        assert!(rician < 0.001, "{}", rician);
+
+        // 16-QAM needs more energy per bit than QPSK for the same error rate
+        channel.modulation = Modulation::Qam16;
+        channel.fading = Fading::None;
+        channel.ebn0_db = 4.0;
+        assert!(bit_error_rate(&mut channel, &mut rng) > 0.03);
+
+        assert_eq!(
+            NoiseMode::parse("rician:16qam:8:3"),
+            Ok(NoiseMode::Modulated(ModulatedChannel {
+                modulation: Modulation::Qam16,
+                ebn0_db: 8.0,
+                fading: Fading::Rician(3.0),
+            }))
+        );
+        assert!(NoiseMode::parse("awgn:8psk:3").is_err());
+    }
+
+    #[test]
+    fn test_llrs_agree_with_hard_decisions() {
+        let mut rng = StdRng::seed_from_u64(17);
+        let channel = ModulatedChannel {
+            modulation: Modulation::Qam16,
+            ebn0_db: 30.0,
+            fading: Fading::Rayleigh,
+        };
+        let bits = "10110010011101";
+        let llrs = channel.transmit_llrs(bits, &mut rng);
+        assert_eq!(llrs.len(), bits.len());
+        assert_eq!(crate::modulation::llrs_to_bits(&llrs), bits);
+    }
+
+    #[test]
+    fn test_burst_channel_flips_bits_in_a_row() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let noisy = BurstChannel { length: 4 }.transmit(&"0".repeat(30), &mut rng);
+        let start = noisy.find('1').unwrap();
+        assert_eq!(&noisy[start..start + 4], "1111");
+        assert_eq!(noisy.matches('1').count(), 4);
+        assert_eq!(BurstChannel { length: 9 }.transmit("000", &mut rng), "111");
+    }
+
+    #[test]
+    fn test_gilbert_elliott_errors_come_in_bursts() {
+        let mut rng = StdRng::seed_from_u64(11);
+        // Never wrong when good, always wrong when bad
+        let mut channel = GilbertElliottChannel::new(0.0, 1.0, 0.01, 0.1);
+        let noisy = channel.transmit(&"0".repeat(100_000), &mut rng);
+
+        let errors = noisy.matches('1').count();
+        let bursts = noisy.split('0').filter(|run| !run.is_empty()).count();
+        // A bad stretch lasts 1 / bad_to_good = 10 bits on average
+        let mean_burst = errors as f64 / bursts as f64;
+        assert!((8.0..12.0).contains(&mean_burst), "{}", mean_burst);
+        let fraction = errors as f64 / noisy.len() as f64;
+        assert!(
+            (fraction - channel.bad_fraction()).abs() < 0.02,
+            "{}",
+            fraction
+        );
+
+        // Always bad, and it stays bad into the next transmission
+        let mut stuck = GilbertElliottChannel::new(0.0, 1.0, 1.0, 0.0);
+        assert_eq!(stuck.transmit("0000", &mut rng), "0111");
+        assert_eq!(stuck.transmit("0000", &mut rng), "1111");
+    }
+
+    #[test]
+    fn test_channels_do_not_care_about_the_code() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let bits = "1011001110001111";
+
+        assert_eq!(NoiselessChannel.transmit(bits, &mut rng), bits);
+        let noisy = FixedFlipsChannel { flips: 2 }.transmit(bits, &mut rng);
+        assert_eq!(differences(bits, &noisy), 2);
+        let noisy = FixedFlipsChannel { flips: 20 }.transmit(bits, &mut rng);
+        assert_eq!(differences(bits, &noisy), bits.len());
+
+        let mut always = BinarySymmetricChannel::new(1.0).unwrap();
+        assert_eq!(always.transmit("1100", &mut rng), "0011");
+        let mut never = BinarySymmetricChannel::new(0.0).unwrap();
+        assert_eq!(never.transmit("1100", &mut rng), "1100");
+        assert!(BinarySymmetricChannel::new(1.5).is_err());
+        assert!(BinarySymmetricChannel::new(f64::NAN).is_err());
+    }
+}
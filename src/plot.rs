@@ -0,0 +1,546 @@
+use std::fmt::Write;
+
+/* Error rate curves with nothing to install: in the terminal with Braille dots, or
+ * plain ASCII where those don't show, and as an SVG file.
+ *
+ *   1e-1 ┤⠑⠢⢄
+ *        │   ⠈⠑⠢⡀
+ *   1e-2 ┤      ⠈⠢⡀
+ *        └──────────
+ *
+ * The y axis is always logarithmic, in whole decades, the x axis is when it holds a
+ * probability. A rate of 0 has no place on a log scale, those points are left out.
+ * In the terminal every series gets a color, or a marker of its own in ASCII. Where
+ * two curves cross, the one drawn later wins the cell.
+ */
+
+// In characters
+pub const PLOT_WIDTH: usize = 64;
+pub const PLOT_HEIGHT: usize = 16;
+const SVG_WIDTH: f64 = 720.0;
+const SVG_HEIGHT: f64 = 440.0;
+// Left, right (with the legend), top and bottom
+const SVG_MARGINS: (f64, f64, f64, f64) = (70.0, 150.0, 20.0, 50.0);
+
+const MARKERS: [char; 8] = ['*', 'o', '+', 'x', '#', '@', '%', '&'];
+const ANSI_COLORS: [u8; 6] = [31, 32, 33, 34, 35, 36];
+const SVG_COLORS: [&str; 8] = [
+    "#d62728", "#1f77b4", "#2ca02c", "#ff7f0e", "#9467bd", "#8c564b", "#e377c2", "#17becf",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Series {
+    pub name: String,
+    pub points: Vec<(f64, f64)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Axes {
+    pub x_label: String,
+    pub y_label: String,
+    pub log_x: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlotStyle {
+    Braille,
+    Ascii,
+}
+
+impl PlotStyle {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "braille" => Ok(PlotStyle::Braille),
+            "ascii" => Ok(PlotStyle::Ascii),
+            _ => Err(format!("Unknown plot {}, use braille or ascii", name)),
+        }
+    }
+}
+
+// What the axes span, in log10 where they are logarithmic
+struct Scale {
+    log_x: bool,
+    x: (f64, f64),
+    y: (f64, f64),
+}
+
+impl Scale {
+    fn new(series: &[Series], log_x: bool) -> Option<Self> {
+        let points: Vec<(f64, f64)> = series
+            .iter()
+            .flat_map(|series| series.points.iter())
+            .filter(|(x, y)| *y > 0.0 && (!log_x || *x > 0.0))
+            .map(|(x, y)| (if log_x { x.log10() } else { *x }, y.log10()))
+            .collect();
+        if points.is_empty() {
+            return None;
+        }
+
+        let range = |values: Vec<f64>, decades: bool| {
+            let (low, high) = values
+                .iter()
+                .fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), value| {
+                    (low.min(*value), high.max(*value))
+                });
+            let (low, high) = if decades {
+                (low.floor(), high.ceil())
+            } else {
+                (low, high)
+            };
+            if high - low < 1e-9 {
+                (low - 1.0, high + if decades { 0.0 } else { 1.0 })
+            } else {
+                (low, high)
+            }
+        };
+
+        Some(Scale {
+            log_x,
+            x: range(points.iter().map(|point| point.0).collect(), log_x),
+            y: range(points.iter().map(|point| point.1).collect(), true),
+        })
+    }
+
+    fn visible(&self, (x, y): (f64, f64)) -> bool {
+        y > 0.0 && (!self.log_x || x > 0.0)
+    }
+
+    // Both from 0 to 1, y going up
+    fn position(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        let x = if self.log_x { x.log10() } else { x };
+        (
+            (x - self.x.0) / (self.x.1 - self.x.0),
+            (y.log10() - self.y.0) / (self.y.1 - self.y.0),
+        )
+    }
+
+    // Where the labels go along x, with their values
+    fn x_ticks(&self) -> Vec<(f64, f64)> {
+        if self.log_x {
+            (self.x.0 as i32..=self.x.1 as i32)
+                .map(|decade| {
+                    let value = 10f64.powi(decade);
+                    (self.position((value, 1.0)).0, value)
+                })
+                .collect()
+        } else {
+            (0..=4)
+                .map(|step| {
+                    let position = step as f64 / 4.0;
+                    (position, self.x.0 + position * (self.x.1 - self.x.0))
+                })
+                .collect()
+        }
+    }
+
+    fn decades(&self) -> Vec<i32> {
+        (self.y.0 as i32..=self.y.1 as i32).rev().collect()
+    }
+}
+
+fn tick_label(value: f64) -> String {
+    if value != 0.0 && value.abs() < 0.01 {
+        format!("{:.0e}", value)
+    } else {
+        let label = format!("{:.2}", value);
+        label
+            .trim_end_matches('0')
+            .trim_end_matches('.')
+            .to_string()
+    }
+}
+
+// Every cell a line from one dot to the next goes through
+fn line((x0, y0): (i64, i64), (x1, y1): (i64, i64)) -> Vec<(i64, i64)> {
+    let steps = (x1 - x0).abs().max((y1 - y0).abs()).max(1);
+    (0..=steps)
+        .map(|step| {
+            let t = step as f64 / steps as f64;
+            (
+                (x0 as f64 + t * (x1 - x0) as f64).round() as i64,
+                (y0 as f64 + t * (y1 - y0) as f64).round() as i64,
+            )
+        })
+        .collect()
+}
+
+// The dot of a Braille character for a column of 2 and a row of 4
+fn braille_bit(column: usize, row: usize) -> u8 {
+    match (column, row) {
+        (0, 3) => 0x40,
+        (1, 3) => 0x80,
+        (0, row) => 1 << row,
+        (_, row) => 1 << (row + 3),
+    }
+}
+
+/// The chart as lines of text. `color` adds ANSI colors to the Braille dots.
+pub fn terminal_plot(series: &[Series], axes: &Axes, style: PlotStyle, color: bool) -> String {
+    let Some(scale) = Scale::new(series, axes.log_x) else {
+        return "Nothing to plot, every error rate is 0".to_string();
+    };
+    let (dots_across, dots_down) = match style {
+        PlotStyle::Braille => (2, 4),
+        PlotStyle::Ascii => (1, 1),
+    };
+    let (width, height) = (PLOT_WIDTH * dots_across, PLOT_HEIGHT * dots_down);
+
+    // The dots set in every cell, and the series that set one last
+    let mut cells = vec![vec![(0u8, None); PLOT_WIDTH]; PLOT_HEIGHT];
+    for (index, series) in series.iter().enumerate() {
+        let dots: Vec<(i64, i64)> = series
+            .points
+            .iter()
+            .filter(|point| scale.visible(**point))
+            .map(|point| {
+                let (x, y) = scale.position(*point);
+                (
+                    (x * (width - 1) as f64).round() as i64,
+                    ((1.0 - y) * (height - 1) as f64).round() as i64,
+                )
+            })
+            .collect();
+        let mut path = dots.clone();
+        for pair in dots.windows(2) {
+            path.extend(line(pair[0], pair[1]));
+        }
+        for (x, y) in path {
+            let (x, y) = (x as usize, y as usize);
+            let cell = &mut cells[y / dots_down][x / dots_across];
+            cell.0 |= braille_bit(x % dots_across, y % dots_down);
+            cell.1 = Some(index);
+        }
+    }
+
+    let (vertical, tick, corner, horizontal) = match style {
+        PlotStyle::Braille => ('│', '┤', '└', '─'),
+        PlotStyle::Ascii => ('|', '+', '+', '-'),
+    };
+    let decade_rows: Vec<(usize, i32)> = scale
+        .decades()
+        .into_iter()
+        .map(|decade| {
+            let y = (decade as f64 - scale.y.0) / (scale.y.1 - scale.y.0);
+            (
+                ((1.0 - y) * (PLOT_HEIGHT - 1) as f64).round() as usize,
+                decade,
+            )
+        })
+        .collect();
+
+    let mut output = format!("{:>6}\n", axes.y_label);
+    for (row, cells) in cells.iter().enumerate() {
+        match decade_rows
+            .iter()
+            .find(|(decade_row, _)| *decade_row == row)
+        {
+            Some((_, decade)) => {
+                let _ = write!(output, "{:>6} {}", format!("1e{}", decade), tick);
+            }
+            None => {
+                let _ = write!(output, "{:>6} {}", "", vertical);
+            }
+        }
+        for (dots, index) in cells {
+            let Some(index) = index else {
+                output.push(' ');
+                continue;
+            };
+            let character = match style {
+                PlotStyle::Braille => char::from_u32(0x2800 + *dots as u32).unwrap_or(' '),
+                PlotStyle::Ascii => MARKERS[index % MARKERS.len()],
+            };
+            if color && style == PlotStyle::Braille {
+                let _ = write!(
+                    output,
+                    "\x1b[{}m{}\x1b[0m",
+                    ANSI_COLORS[index % ANSI_COLORS.len()],
+                    character
+                );
+            } else {
+                output.push(character);
+            }
+        }
+        output.push('\n');
+    }
+
+    let _ = writeln!(
+        output,
+        "{:>6} {}{}",
+        "",
+        corner,
+        horizontal.to_string().repeat(PLOT_WIDTH)
+    );
+    // Tick labels, as long as they don't run into each other
+    let mut labels = vec![' '; PLOT_WIDTH + 8];
+    let mut free_from = 0;
+    for (position, value) in scale.x_ticks() {
+        let label: Vec<char> = tick_label(value).chars().collect();
+        let center = 8 + (position * (PLOT_WIDTH - 1) as f64).round() as usize;
+        let start = center
+            .saturating_sub(label.len() / 2)
+            .min(labels.len() - label.len());
+        if start >= free_from {
+            labels[start..start + label.len()].copy_from_slice(&label);
+            free_from = start + label.len() + 1;
+        }
+    }
+    let labels: String = labels.into_iter().collect();
+    let _ = writeln!(output, "{}", labels.trim_end());
+    let _ = writeln!(
+        output,
+        "{:>width$}",
+        axes.x_label,
+        width = 8 + (PLOT_WIDTH + axes.x_label.len()) / 2
+    );
+
+    for (index, series) in series.iter().enumerate() {
+        match style {
+            PlotStyle::Braille if color => {
+                let _ = writeln!(
+                    output,
+                    "  \x1b[{}m⣿\x1b[0m {}",
+                    ANSI_COLORS[index % ANSI_COLORS.len()],
+                    series.name
+                );
+            }
+            PlotStyle::Braille => {
+                let _ = writeln!(output, "  {} {}", index + 1, series.name);
+            }
+            PlotStyle::Ascii => {
+                let _ = writeln!(
+                    output,
+                    "  {} {}",
+                    MARKERS[index % MARKERS.len()],
+                    series.name
+                );
+            }
+        }
+    }
+    // Without colors the Braille curves can't be told apart, so say which is which
+    if style == PlotStyle::Braille && !color && series.len() > 1 {
+        output.push_str("  (use --plot ascii to tell the curves apart without colors)\n");
+    }
+
+    output
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The chart as an SVG document, with a grid line at every decade.
+pub fn svg_plot(series: &[Series], axes: &Axes) -> String {
+    let (left, right, top, bottom) = SVG_MARGINS;
+    let (plot_width, plot_height) = (SVG_WIDTH - left - right, SVG_HEIGHT - top - bottom);
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-family=\"sans-serif\" font-size=\"12\">",
+        SVG_WIDTH, SVG_HEIGHT
+    );
+    let _ = writeln!(
+        svg,
+        "<rect width=\"{}\" height=\"{}\" fill=\"white\"/>",
+        SVG_WIDTH, SVG_HEIGHT
+    );
+
+    let Some(scale) = Scale::new(series, axes.log_x) else {
+        let _ = writeln!(
+            svg,
+            "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">Nothing to plot, every error rate is 0</text>",
+            SVG_WIDTH / 2.0,
+            SVG_HEIGHT / 2.0
+        );
+        svg.push_str("</svg>\n");
+        return svg;
+    };
+    let to_svg = |(x, y): (f64, f64)| (left + x * plot_width, top + (1.0 - y) * plot_height);
+
+    for decade in scale.decades() {
+        let y = (decade as f64 - scale.y.0) / (scale.y.1 - scale.y.0);
+        let (_, y) = to_svg((0.0, y));
+        let _ = writeln!(
+            svg,
+            "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"#ddd\"/>",
+            left,
+            y,
+            left + plot_width,
+            y
+        );
+        let _ = writeln!(
+            svg,
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">1e{}</text>",
+            left - 6.0,
+            y + 4.0,
+            decade
+        );
+    }
+    for (position, value) in scale.x_ticks() {
+        let (x, _) = to_svg((position, 0.0));
+        let _ = writeln!(
+            svg,
+            "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"#ddd\"/>",
+            x,
+            top,
+            x,
+            top + plot_height
+        );
+        let _ = writeln!(
+            svg,
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>",
+            x,
+            top + plot_height + 18.0,
+            tick_label(value)
+        );
+    }
+    let _ = writeln!(
+        svg,
+        "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"black\"/>",
+        left, top, plot_width, plot_height
+    );
+    let _ = writeln!(
+        svg,
+        "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>",
+        left + plot_width / 2.0,
+        SVG_HEIGHT - 10.0,
+        xml_escape(&axes.x_label)
+    );
+    let _ = writeln!(
+        svg,
+        "<text x=\"16\" y=\"{:.1}\" text-anchor=\"middle\" transform=\"rotate(-90 16 {:.1})\">{}</text>",
+        top + plot_height / 2.0,
+        top + plot_height / 2.0,
+        xml_escape(&axes.y_label)
+    );
+
+    for (index, series) in series.iter().enumerate() {
+        let color = SVG_COLORS[index % SVG_COLORS.len()];
+        let points: Vec<(f64, f64)> = series
+            .points
+            .iter()
+            .filter(|point| scale.visible(**point))
+            .map(|point| to_svg(scale.position(*point)))
+            .collect();
+        let path: Vec<String> = points
+            .iter()
+            .map(|(x, y)| format!("{:.1},{:.1}", x, y))
+            .collect();
+        let _ = writeln!(
+            svg,
+            "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"2\"/>",
+            path.join(" "),
+            color
+        );
+        for (x, y) in &points {
+            let _ = writeln!(
+                svg,
+                "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"3\" fill=\"{}\"/>",
+                x, y, color
+            );
+        }
+
+        let legend_y = top + 10.0 + index as f64 * 18.0;
+        let legend_x = left + plot_width + 16.0;
+        let _ = writeln!(
+            svg,
+            "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"{}\" stroke-width=\"2\"/>",
+            legend_x,
+            legend_y,
+            legend_x + 20.0,
+            legend_y,
+            color
+        );
+        let _ = writeln!(
+            svg,
+            "<text x=\"{:.1}\" y=\"{:.1}\">{}</text>",
+            legend_x + 26.0,
+            legend_y + 4.0,
+            xml_escape(&series.name)
+        );
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn falling() -> Vec<Series> {
+        vec![
+            Series {
+                name: "hamming".to_string(),
+                points: vec![(0.001, 2e-4), (0.01, 2e-2), (0.1, 0.0)],
+            },
+            Series {
+                name: "triple".to_string(),
+                points: vec![(0.001, 3e-6), (0.01, 3e-4), (0.1, 3e-2)],
+            },
+        ]
+    }
+
+    fn bsc_axes() -> Axes {
+        Axes {
+            x_label: "p".to_string(),
+            y_label: "BER".to_string(),
+            log_x: true,
+        }
+    }
+
+    #[test]
+    fn test_terminal_plot() {
+        let plot = terminal_plot(&falling(), &bsc_axes(), PlotStyle::Ascii, false);
+        let lines: Vec<&str> = plot.lines().collect();
+        // BER label, 16 rows, the axis, the ticks, the x label and 2 legend lines
+        assert_eq!(lines.len(), 1 + PLOT_HEIGHT + 3 + 2);
+        assert_eq!(lines[0].trim(), "BER");
+        // From 1e-6 to 1e-1, the top row is the highest decade
+        assert!(lines[1].starts_with("  1e-1 +"));
+        assert!(lines[PLOT_HEIGHT].starts_with("  1e-6 +"));
+        assert!(lines[PLOT_HEIGHT + 2].contains("1e-3"));
+        assert!(lines.contains(&"  o triple"));
+
+        // Triple reaches the right edge, the lowest p is at the left edge
+        assert!(lines[1..=PLOT_HEIGHT]
+            .iter()
+            .any(|line| line.ends_with('o')));
+        assert!(lines.iter().any(|line| line.chars().nth(8) == Some('*')));
+
+        let braille = terminal_plot(&falling(), &bsc_axes(), PlotStyle::Braille, true);
+        assert!(braille.contains('\u{1b}'));
+        assert!(braille
+            .chars()
+            .any(|character| ('\u{2801}'..='\u{28ff}').contains(&character)));
+
+        let nothing = vec![Series {
+            name: "vt".to_string(),
+            points: vec![(0.001, 0.0)],
+        }];
+        assert!(
+            terminal_plot(&nothing, &bsc_axes(), PlotStyle::Ascii, false).starts_with("Nothing")
+        );
+    }
+
+    #[test]
+    fn test_svg_plot() {
+        let svg = svg_plot(&falling(), &bsc_axes());
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        assert_eq!(svg.matches("<polyline").count(), 2);
+        // The 0 is left out
+        assert_eq!(svg.matches("<circle").count(), 5);
+        assert!(svg.contains(">hamming</text>"));
+
+        let axes = Axes {
+            x_label: "Eb/N0 <dB>".to_string(),
+            y_label: "FER".to_string(),
+            log_x: false,
+        };
+        assert!(svg_plot(&falling(), &axes).contains("Eb/N0 &lt;dB&gt;"));
+    }
+}
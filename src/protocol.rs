@@ -0,0 +1,644 @@
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
+
+use crate::correction::{crc8, ConcatenatedCode, CorrectionType, ProductCode};
+use crate::rate_matching::ERASURE;
+
+/* Everything between the sender and the receiver goes in packets like this:
+ *
+ *   magic      2 bytes  "EC"
+ *   version    1 byte
+ *   type       1 byte   PacketType
+ *   code       1 byte length, then the code id and its parameters (see encode_code)
+ *   length     4 bytes  payload length in bytes, big endian
+ *   checksum   1 byte   CRC-8 over everything above
+ *   payload    `length` bytes
+ *
+ * Bit string payloads are packed 8 bits to a byte, with one leading byte saying
+ * how many bits of the last byte are padding. When some bits are erasures, the top
+ * bit of that byte is set and a second bitmap of the same size follows, with a 1
+ * for every erased bit. TCP is a byte stream, so the
+ * receiving side feeds whatever it reads into a PacketDecoder and takes out
+ * complete packets, no matter how they were split up or glued together.
+ *
+ * A header that fails its checksum is noise, the decoder drops a byte and looks for
+ * the next magic. A header with another version, or one that checks out but names a
+ * code we don't know, means the other side runs a different build. Those are passed
+ * up so they can be reported, see ProtocolError::is_fatal.
+ */
+
+pub const MAGIC: [u8; 2] = *b"EC";
+// 2: Response packets carry a DecodeReport instead of plain text
+// 3: Truth packets (type 11) carry the clean bits for --ground-truth
+// 4: HARQ packets start with their message id and round
+// 5: packed bits can carry erasures
+// 6: Data packets start with a message id, and Responses carry it back
+pub const VERSION: u8 = 6;
+const ERASURES_FOLLOW: u8 = 0x80;
+const MAX_PAYLOAD_LENGTH: usize = 1 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PacketType {
+    Table,
+    Data,
+    Response,
+    HarqIncrementalRedundancy,
+    HarqChaseCombining,
+    Nack,
+    Arq,
+    Hello,
+    Accept,
+    Error,
+    // The clean data bits of the next message, for counting residual errors
+    Truth,
+}
+
+impl PacketType {
+    fn id(&self) -> u8 {
+        match self {
+            PacketType::Table => 1,
+            PacketType::Data => 2,
+            PacketType::Response => 3,
+            PacketType::HarqIncrementalRedundancy => 4,
+            PacketType::HarqChaseCombining => 5,
+            PacketType::Nack => 6,
+            PacketType::Arq => 7,
+            PacketType::Hello => 8,
+            PacketType::Accept => 9,
+            PacketType::Error => 10,
+            PacketType::Truth => 11,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(PacketType::Table),
+            2 => Some(PacketType::Data),
+            3 => Some(PacketType::Response),
+            4 => Some(PacketType::HarqIncrementalRedundancy),
+            5 => Some(PacketType::HarqChaseCombining),
+            6 => Some(PacketType::Nack),
+            7 => Some(PacketType::Arq),
+            8 => Some(PacketType::Hello),
+            9 => Some(PacketType::Accept),
+            10 => Some(PacketType::Error),
+            11 => Some(PacketType::Truth),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ProtocolError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    BadChecksum,
+    UnknownPacketType(u8),
+    UnknownCode,
+    CodeTooLarge,
+    PayloadTooLarge(usize),
+    Truncated,
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProtocolError::BadMagic => write!(f, "packet does not start with the magic bytes"),
+            ProtocolError::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported protocol version {}, this side speaks version {}",
+                version, VERSION
+            ),
+            ProtocolError::BadChecksum => write!(f, "packet header checksum does not match"),
+            ProtocolError::UnknownPacketType(id) => write!(f, "unknown packet type {}", id),
+            ProtocolError::UnknownCode => write!(f, "unknown correction code in packet header"),
+            ProtocolError::CodeTooLarge => {
+                write!(
+                    f,
+                    "correction code parameters do not fit in the packet header"
+                )
+            }
+            ProtocolError::PayloadTooLarge(length) => {
+                write!(f, "payload of {} bytes is too large", length)
+            }
+            ProtocolError::Truncated => write!(f, "packet is cut short"),
+        }
+    }
+}
+
+impl ProtocolError {
+    /// Whether the peer runs a different build, so skipping ahead won't help.
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            ProtocolError::UnsupportedVersion(_) | ProtocolError::UnknownCode
+        )
+    }
+}
+
+impl From<ProtocolError> for io::Error {
+    fn from(error: ProtocolError) -> Self {
+        io::Error::new(ErrorKind::InvalidData, error.to_string())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Packet {
+    pub packet_type: PacketType,
+    pub code: Option<CorrectionType>,
+    pub payload: Vec<u8>,
+}
+
+impl Packet {
+    pub fn new(packet_type: PacketType, code: Option<CorrectionType>, payload: Vec<u8>) -> Self {
+        Packet {
+            packet_type,
+            code,
+            payload,
+        }
+    }
+
+    pub fn bits(packet_type: PacketType, code: Option<CorrectionType>, bits: &str) -> Self {
+        Packet::new(packet_type, code, pack_bits(bits))
+    }
+
+    // Data payload: message id (1 byte), then the bits
+    pub fn data(code: CorrectionType, message_id: u8, bits: &str) -> Self {
+        let mut payload = vec![message_id];
+        payload.extend(pack_bits(bits));
+        Packet::new(PacketType::Data, Some(code), payload)
+    }
+
+    // HARQ payload: message id (1 byte), round (1 byte), then the bits
+    pub fn harq(packet_type: PacketType, message_id: u8, round: u8, bits: &str) -> Self {
+        let mut payload = vec![message_id, round];
+        payload.extend(pack_bits(bits));
+        Packet::new(packet_type, None, payload)
+    }
+
+    pub fn text(packet_type: PacketType, text: &str) -> Self {
+        Packet::new(packet_type, None, text.as_bytes().to_vec())
+    }
+
+    pub fn payload_bits(&self) -> String {
+        unpack_bits(&self.payload)
+    }
+
+    /// Message id and bits of a Data packet, None when it is cut short.
+    pub fn data_fields(&self) -> Option<(u8, String)> {
+        match self.payload.as_slice() {
+            [message_id, bits @ ..] => Some((*message_id, unpack_bits(bits))),
+            _ => None,
+        }
+    }
+
+    /// Message id, round and bits of a HARQ packet, None when it is cut short.
+    pub fn harq_fields(&self) -> Option<(u8, u8, String)> {
+        match self.payload.as_slice() {
+            [message_id, round, bits @ ..] => Some((*message_id, *round, unpack_bits(bits))),
+            _ => None,
+        }
+    }
+
+    pub fn payload_text(&self) -> String {
+        String::from_utf8_lossy(&self.payload).to_string()
+    }
+}
+
+// One bit for every char, set where it is `set`
+fn bitmap(bits: &str, set: char) -> Vec<u8> {
+    bits.as_bytes()
+        .chunks(8)
+        .map(|chunk| {
+            let mut byte = 0u8;
+            for (i, &bit) in chunk.iter().enumerate() {
+                if bit as char == set {
+                    byte |= 1 << (7 - i);
+                }
+            }
+            byte
+        })
+        .collect()
+}
+
+pub fn pack_bits(bits: &str) -> Vec<u8> {
+    let padding = (8 - bits.len() % 8) % 8;
+    let has_erasures = bits.contains(ERASURE);
+    let mut bytes = vec![padding as u8 | if has_erasures { ERASURES_FOLLOW } else { 0 }];
+
+    bytes.extend(bitmap(bits, '1'));
+    if has_erasures {
+        bytes.extend(bitmap(bits, ERASURE));
+    }
+
+    bytes
+}
+
+pub fn unpack_bits(bytes: &[u8]) -> String {
+    let Some((&first, packed)) = bytes.split_first() else {
+        return "".to_string();
+    };
+    let padding = (first & !ERASURES_FOLLOW) as usize;
+    let (values, erasures) = if first & ERASURES_FOLLOW != 0 {
+        packed.split_at(packed.len() / 2)
+    } else {
+        (packed, &[][..])
+    };
+
+    let mut bits: String = values
+        .iter()
+        .enumerate()
+        .flat_map(|(index, value)| {
+            let erased = erasures.get(index).copied().unwrap_or(0);
+            (0..8).rev().map(move |i| {
+                if (erased >> i) & 1 == 1 {
+                    ERASURE
+                } else if (value >> i) & 1 == 1 {
+                    '1'
+                } else {
+                    '0'
+                }
+            })
+        })
+        .collect();
+    bits.truncate(bits.len().saturating_sub(padding));
+    bits
+}
+
+/* Code ids and their parameters. Nested codes just write their components after
+ * their own parameters.
+ *   1 Parity, 2 Triple, 3 Hamming, 4 CRC, 7 VT, 8 Convolutional
+ *   5 Concatenated: interleaver depth (1 byte, 0 = none), outer code, inner code
+ *   6 Product: row length (2 bytes), row code, column code
+ */
+pub fn encode_code(correction_type: &CorrectionType) -> Result<Vec<u8>, ProtocolError> {
+    match correction_type {
+        CorrectionType::Parity => Ok(vec![1]),
+        CorrectionType::Triple => Ok(vec![2]),
+        CorrectionType::Hamming => Ok(vec![3]),
+        CorrectionType::Crc => Ok(vec![4]),
+        CorrectionType::Vt => Ok(vec![7]),
+        CorrectionType::Convolutional => Ok(vec![8]),
+        CorrectionType::Concatenated(code) => {
+            let depth = u8::try_from(code.interleaver_depth.unwrap_or(0))
+                .map_err(|_| ProtocolError::CodeTooLarge)?;
+            let mut bytes = vec![5, depth];
+            bytes.extend(encode_code(&code.outer)?);
+            bytes.extend(encode_code(&code.inner)?);
+            Ok(bytes)
+        }
+        CorrectionType::Product(code) => {
+            let row_length =
+                u16::try_from(code.row_length).map_err(|_| ProtocolError::CodeTooLarge)?;
+            let mut bytes = vec![6];
+            bytes.extend(row_length.to_be_bytes());
+            bytes.extend(encode_code(&code.row)?);
+            bytes.extend(encode_code(&code.column)?);
+            Ok(bytes)
+        }
+    }
+}
+
+// Returns the code and how many bytes it took up
+pub fn decode_code(bytes: &[u8]) -> Option<(CorrectionType, usize)> {
+    match bytes.first()? {
+        1 => Some((CorrectionType::Parity, 1)),
+        2 => Some((CorrectionType::Triple, 1)),
+        3 => Some((CorrectionType::Hamming, 1)),
+        4 => Some((CorrectionType::Crc, 1)),
+        7 => Some((CorrectionType::Vt, 1)),
+        8 => Some((CorrectionType::Convolutional, 1)),
+        5 => {
+            let depth = *bytes.get(1)? as usize;
+            let (outer, outer_length) = decode_code(&bytes[2..])?;
+            let (inner, inner_length) = decode_code(&bytes[2 + outer_length..])?;
+            let interleaver_depth = if depth == 0 { None } else { Some(depth) };
+            Some((
+                CorrectionType::Concatenated(Box::new(ConcatenatedCode::new(
+                    outer,
+                    interleaver_depth,
+                    inner,
+                ))),
+                2 + outer_length + inner_length,
+            ))
+        }
+        6 => {
+            let row_length = u16::from_be_bytes([*bytes.get(1)?, *bytes.get(2)?]) as usize;
+            let (row, row_code_length) = decode_code(&bytes[3..])?;
+            let (column, column_code_length) = decode_code(&bytes[3 + row_code_length..])?;
+            Some((
+                CorrectionType::Product(Box::new(ProductCode::new(row, column, row_length))),
+                3 + row_code_length + column_code_length,
+            ))
+        }
+        _ => None,
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    let bits: String = bytes.iter().map(|byte| format!("{:08b}", byte)).collect();
+    crc8(&bits)
+}
+
+// Fails for what the other side would not be able to read back
+pub fn encode_packet(packet: &Packet) -> Result<Vec<u8>, ProtocolError> {
+    let code = packet.code.as_ref().map_or(Ok(vec![]), encode_code)?;
+    let code_length = u8::try_from(code.len()).map_err(|_| ProtocolError::CodeTooLarge)?;
+    if packet.payload.len() > MAX_PAYLOAD_LENGTH {
+        return Err(ProtocolError::PayloadTooLarge(packet.payload.len()));
+    }
+
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+    bytes.push(packet.packet_type.id());
+    bytes.push(code_length);
+    bytes.extend(&code);
+    bytes.extend((packet.payload.len() as u32).to_be_bytes());
+    bytes.push(checksum(&bytes));
+    bytes.extend(&packet.payload);
+    Ok(bytes)
+}
+
+/// Collects bytes from the stream and hands out whole packets.
+#[derive(Default)]
+pub struct PacketDecoder {
+    buffer: Vec<u8>,
+}
+
+impl PacketDecoder {
+    pub fn new() -> Self {
+        PacketDecoder::default()
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Ok(None) means we need more bytes. On a broken header the first byte is
+    /// dropped, so calling again looks for the next packet. A packet with an
+    /// unknown code is dropped as a whole.
+    pub fn next_packet(&mut self) -> Result<Option<Packet>, ProtocolError> {
+        match parse_packet(&self.buffer) {
+            Ok(Some((packet, length))) => {
+                self.buffer.drain(..length);
+                Ok(Some(packet))
+            }
+            Ok(None) => Ok(None),
+            Err((error, length)) => {
+                self.buffer.drain(..length);
+                Err(error)
+            }
+        }
+    }
+}
+
+/// One packet off the front of `buffer` and its length in bytes. On error also
+/// returns how many bytes to throw away before trying again.
+pub fn parse_packet(buffer: &[u8]) -> Result<Option<(Packet, usize)>, (ProtocolError, usize)> {
+    if buffer.len() < 5 {
+        return Ok(None);
+    }
+    if buffer[..2] != MAGIC {
+        return Err((ProtocolError::BadMagic, 1));
+    }
+    if buffer[2] != VERSION {
+        return Err((ProtocolError::UnsupportedVersion(buffer[2]), 1));
+    }
+
+    let code_length = buffer[4] as usize;
+    let header_length = 5 + code_length + 4 + 1;
+    if buffer.len() < header_length {
+        return Ok(None);
+    }
+    if checksum(&buffer[..header_length - 1]) != buffer[header_length - 1] {
+        return Err((ProtocolError::BadChecksum, 1));
+    }
+
+    let packet_type =
+        PacketType::from_id(buffer[3]).ok_or((ProtocolError::UnknownPacketType(buffer[3]), 1))?;
+
+    let length_bytes = &buffer[5 + code_length..9 + code_length];
+    let payload_length = u32::from_be_bytes(length_bytes.try_into().unwrap()) as usize;
+    if payload_length > MAX_PAYLOAD_LENGTH {
+        return Err((ProtocolError::PayloadTooLarge(payload_length), 1));
+    }
+    if buffer.len() < header_length + payload_length {
+        return Ok(None);
+    }
+
+    let code = if code_length == 0 {
+        None
+    } else {
+        match decode_code(&buffer[5..5 + code_length]) {
+            Some((code, length)) if length == code_length => Some(code),
+            _ => return Err((ProtocolError::UnknownCode, header_length + payload_length)),
+        }
+    };
+
+    let payload = buffer[header_length..header_length + payload_length].to_vec();
+    Ok(Some((
+        Packet::new(packet_type, code, payload),
+        header_length + payload_length,
+    )))
+}
+
+pub fn write_packet(stream: &mut impl Write, packet: &Packet) -> io::Result<()> {
+    stream.write_all(&encode_packet(packet)?)
+}
+
+/// Blocks until a whole packet is in. Ok(None) when the other side hung up.
+/// If the stream has a read timeout the error is passed on, and whatever part of
+/// the packet already arrived stays in the decoder for the next call. A packet from
+/// another version or with an unknown code is an InvalidData error, the caller
+/// decides what to tell the peer.
+pub fn read_packet(
+    stream: &mut impl Read,
+    decoder: &mut PacketDecoder,
+) -> io::Result<Option<Packet>> {
+    let mut buffer = [0; 512];
+
+    loop {
+        match decoder.next_packet() {
+            Ok(Some(packet)) => return Ok(Some(packet)),
+            Ok(None) => {}
+            Err(error) if error.is_fatal() => return Err(error.into()),
+            Err(error) => {
+                println!("Skipping bad data: {}", error);
+                continue;
+            }
+        }
+
+        let bytes_read = stream.read(&mut buffer)?;
+        if bytes_read == 0 {
+            return Ok(None);
+        }
+        decoder.push(&buffer[..bytes_read]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_bits_round_trip() {
+        let bits = "1011001110";
+        let packed = pack_bits(bits);
+        assert_eq!(packed, vec![6, 0b10110011, 0b10000000]);
+        assert_eq!(unpack_bits(&packed), bits);
+        assert_eq!(unpack_bits(&pack_bits("")), "");
+    }
+
+    #[test]
+    fn test_pack_bits_keeps_erasures() {
+        let bits = "10?1001110?";
+        let packed = pack_bits(bits);
+        assert_eq!(
+            packed,
+            vec![0x85, 0b10010011, 0b10000000, 0b00100000, 0b00100000]
+        );
+        assert_eq!(unpack_bits(&packed), bits);
+    }
+
+    #[test]
+    fn test_message_fields_round_trip() {
+        let packet = Packet::harq(PacketType::HarqChaseCombining, 7, 2, "10110");
+        assert_eq!(packet.harq_fields(), Some((7, 2, "10110".to_string())));
+
+        let cut_short = Packet::new(PacketType::HarqChaseCombining, None, vec![7]);
+        assert_eq!(cut_short.harq_fields(), None);
+
+        let packet = Packet::data(CorrectionType::Hamming, 9, "1?0");
+        assert_eq!(packet.data_fields(), Some((9, "1?0".to_string())));
+        assert_eq!(Packet::new(PacketType::Data, None, vec![]).data_fields(), None);
+    }
+
+    #[test]
+    fn test_code_round_trip() {
+        let codes = [
+            CorrectionType::Hamming,
+            CorrectionType::Concatenated(Box::default()),
+            CorrectionType::Product(Box::new(ProductCode::new(
+                CorrectionType::Hamming,
+                CorrectionType::Parity,
+                300,
+            ))),
+        ];
+        for code in codes {
+            let bytes = encode_code(&code).unwrap();
+            assert_eq!(decode_code(&bytes), Some((code, bytes.len())));
+        }
+    }
+
+    #[test]
+    fn test_code_parameters_that_do_not_fit_are_refused() {
+        let deep = CorrectionType::Concatenated(Box::new(ConcatenatedCode::new(
+            CorrectionType::Hamming,
+            Some(256),
+            CorrectionType::Triple,
+        )));
+        assert_eq!(encode_code(&deep), Err(ProtocolError::CodeTooLarge));
+
+        let wide = CorrectionType::Product(Box::new(ProductCode::new(
+            CorrectionType::Parity,
+            CorrectionType::Parity,
+            70_000,
+        )));
+        let packet = Packet::bits(PacketType::Data, Some(wide), "1011");
+        assert_eq!(encode_packet(&packet), Err(ProtocolError::CodeTooLarge));
+
+        let large = Packet::new(PacketType::Data, None, vec![0; MAX_PAYLOAD_LENGTH + 1]);
+        assert_eq!(
+            encode_packet(&large),
+            Err(ProtocolError::PayloadTooLarge(MAX_PAYLOAD_LENGTH + 1))
+        );
+    }
+
+    #[test]
+    fn test_decoder_handles_split_and_coalesced_packets() {
+        let first = Packet::bits(
+            PacketType::Data,
+            Some(CorrectionType::Triple),
+            &"10".repeat(400),
+        );
+        let second = Packet::text(PacketType::Response, "hello how are you");
+        let mut bytes = encode_packet(&first).unwrap();
+        bytes.extend(encode_packet(&second).unwrap());
+
+        let mut decoder = PacketDecoder::new();
+        decoder.push(&bytes[..7]);
+        assert_eq!(decoder.next_packet(), Ok(None));
+        decoder.push(&bytes[7..]);
+        assert_eq!(decoder.next_packet(), Ok(Some(first)));
+        assert_eq!(decoder.next_packet(), Ok(Some(second)));
+        assert_eq!(decoder.next_packet(), Ok(None));
+    }
+
+    #[test]
+    fn test_decoder_rejects_bad_header_and_resyncs() {
+        let packet = Packet::text(PacketType::Nack, "");
+        let mut corrupted = encode_packet(&packet).unwrap();
+        corrupted[3] = 2;
+
+        let mut decoder = PacketDecoder::new();
+        decoder.push(&corrupted);
+        decoder.push(&encode_packet(&packet).unwrap());
+        assert_eq!(decoder.next_packet(), Err(ProtocolError::BadChecksum));
+
+        let mut result = decoder.next_packet();
+        while result.is_err() {
+            result = decoder.next_packet();
+        }
+        assert_eq!(result, Ok(Some(packet)));
+    }
+
+    #[test]
+    fn test_decoder_drops_whole_packet_with_unknown_code() {
+        let packet = Packet::bits(PacketType::Data, Some(CorrectionType::Hamming), "1011");
+        let mut unknown = encode_packet(&packet).unwrap();
+        // Code id 42, then fix up the header checksum so only the code is wrong
+        unknown[5] = 42;
+        let header_length = unknown.len() - packet.payload.len();
+        unknown[header_length - 1] = checksum(&unknown[..header_length - 1]);
+
+        let mut decoder = PacketDecoder::new();
+        decoder.push(&unknown);
+        decoder.push(&encode_packet(&packet).unwrap());
+        assert_eq!(decoder.next_packet(), Err(ProtocolError::UnknownCode));
+        assert_eq!(decoder.next_packet(), Ok(Some(packet)));
+    }
+
+    #[test]
+    fn test_other_version_is_not_skipped() {
+        let mut old = encode_packet(&Packet::text(PacketType::Hello, "")).unwrap();
+        old[2] = 1;
+        let header_length = old.len();
+        old[header_length - 1] = checksum(&old[..header_length - 1]);
+
+        let mut decoder = PacketDecoder::new();
+        let mut stream: &[u8] = &old;
+        let error = read_packet(&mut stream, &mut decoder).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "unsupported protocol version 1, this side speaks version {}",
+                VERSION
+            )
+        );
+    }
+
+    #[test]
+    fn test_read_packet_from_stream() {
+        let packet = Packet::bits(PacketType::Table, None, &"01".repeat(600));
+        let bytes = encode_packet(&packet).unwrap();
+        let mut decoder = PacketDecoder::new();
+
+        let mut stream: &[u8] = &bytes;
+        assert_eq!(
+            read_packet(&mut stream, &mut decoder).unwrap(),
+            Some(packet)
+        );
+        assert_eq!(read_packet(&mut stream, &mut decoder).unwrap(), None);
+    }
+}
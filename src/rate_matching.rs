@@ -0,0 +1,330 @@
+use crate::correction::{decode_stage, encode_correction, encoded_length, CorrectionType};
+
+/// Marks a bit the receiver never got (punctured away). Triple decoding skips these
+/// in the vote, the other decoders need them filled in with `fill_erasures` first.
+pub const ERASURE: char = '?';
+
+pub fn fill_erasures(bits: &str) -> String {
+    bits.replace(ERASURE, "0")
+}
+
+/// Whether the decoder takes erasures as they are, without `fill_erasures`.
+pub fn decodes_erasures(correction_type: &CorrectionType) -> bool {
+    matches!(
+        correction_type,
+        CorrectionType::Triple | CorrectionType::Convolutional
+    )
+}
+
+// Where the message bits end up in the codeword, for the codes that can be shortened
+pub fn data_positions(
+    correction_type: &CorrectionType,
+    message_length: usize,
+) -> Option<Vec<usize>> {
+    match correction_type {
+        CorrectionType::Parity => Some((1..=message_length).collect()),
+        CorrectionType::Hamming => {
+            let total_length = encoded_length(correction_type, message_length)?;
+            Some(
+                (1..=total_length)
+                    .filter(|i| !i.is_power_of_two())
+                    .map(|i| i - 1)
+                    .collect(),
+            )
+        }
+        _ => None,
+    }
+}
+
+/* Shortening: pretend the message is `block_length` bits long by putting zeros in
+ * front of it, encode that, then leave the zeros out of what we send. The receiver
+ * knows they were zeros and puts them back before decoding.
+ * This fixes the code to the (n, k) of the full block size instead of whatever
+ * fits the message exactly.
+ */
+pub fn encode_shortened(
+    correction_type: &CorrectionType,
+    encoded_string: &str,
+    block_length: usize,
+) -> (bool, String) {
+    if encoded_string.len() > block_length {
+        return (false, "Message is longer than the block length".to_string());
+    }
+    let num_shortened = block_length - encoded_string.len();
+    let padded = format!("{}{}", "0".repeat(num_shortened), encoded_string);
+    let (ok, codeword) = encode_correction(correction_type, &padded);
+    if !ok {
+        return (false, codeword);
+    }
+    let positions = match data_positions(correction_type, block_length) {
+        Some(positions) => positions,
+        None => return (false, "This code cannot be shortened".to_string()),
+    };
+
+    let skipped = &positions[..num_shortened];
+    let shortened = codeword
+        .chars()
+        .enumerate()
+        .filter(|(i, _)| !skipped.contains(i))
+        .map(|(_, bit)| bit)
+        .collect();
+
+    (true, shortened)
+}
+
+pub fn decode_shortened(
+    correction_type: &CorrectionType,
+    received: &str,
+    block_length: usize,
+) -> (bool, bool, String, String) {
+    let failed = (true, false, "".to_string(), "".to_string());
+    let positions = match data_positions(correction_type, block_length) {
+        Some(positions) => positions,
+        None => return failed,
+    };
+
+    let full_length = match encoded_length(correction_type, block_length) {
+        Some(full_length) => full_length,
+        None => return failed,
+    };
+    if received.len() > full_length || full_length - received.len() > block_length {
+        println!(
+            "Error: Shortened codeword length ({}) does not fit block length ({})",
+            received.len(),
+            block_length
+        );
+        return failed;
+    }
+    let num_shortened = full_length - received.len();
+
+    let skipped = &positions[..num_shortened];
+    let mut received_bits = received.chars();
+    let codeword: String = (0..full_length)
+        .map(|i| {
+            if skipped.contains(&i) {
+                '0'
+            } else {
+                received_bits.next().unwrap()
+            }
+        })
+        .collect();
+
+    let (has_error, corrected, original, decoded) = decode_stage(correction_type, &codeword);
+    (
+        has_error,
+        corrected,
+        original.get(num_shortened..).unwrap_or("").to_string(),
+        decoded.get(num_shortened..).unwrap_or("").to_string(),
+    )
+}
+
+/// Which bits survive puncturing, repeated over the whole codeword.
+/// "110" keeps two out of every three bits.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PuncturePattern {
+    pub keep: Vec<bool>,
+}
+
+impl PuncturePattern {
+    pub fn new(pattern: &str) -> Self {
+        PuncturePattern {
+            keep: pattern.chars().map(|c| c == '1').collect(),
+        }
+    }
+
+    fn keeps(&self, index: usize) -> bool {
+        self.keep.is_empty() || self.keep[index % self.keep.len()]
+    }
+}
+
+pub fn puncture(bits: &str, pattern: &PuncturePattern) -> String {
+    bits.chars()
+        .enumerate()
+        .filter(|(i, _)| pattern.keeps(*i))
+        .map(|(_, bit)| bit)
+        .collect()
+}
+
+pub fn depuncture(bits: &str, pattern: &PuncturePattern, original_length: usize) -> String {
+    let mut received_bits = bits.chars();
+    (0..original_length)
+        .map(|i| {
+            if pattern.keeps(i) {
+                received_bits.next().unwrap_or(ERASURE)
+            } else {
+                ERASURE
+            }
+        })
+        .collect()
+}
+
+// Spread `target_length` kept bits as evenly as possible over `length` bits
+fn rate_match_keeps(index: usize, length: usize, target_length: usize) -> bool {
+    (index + 1) * target_length / length > index * target_length / length
+}
+
+/* Rate matching: stretch or squeeze a codeword to exactly `target_length` bits.
+ * Too long: puncture evenly spread bits. Too short: keep sending the codeword
+ * from the start again (circular buffer) until it fits.
+ */
+pub fn rate_match(bits: &str, target_length: usize) -> String {
+    let chars: Vec<char> = bits.chars().collect();
+    let length = chars.len();
+
+    if length == 0 {
+        return "0".repeat(target_length);
+    }
+
+    if length >= target_length {
+        chars
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| rate_match_keeps(*i, length, target_length))
+            .map(|(_, bit)| bit)
+            .collect()
+    } else {
+        (0..target_length).map(|i| chars[i % length]).collect()
+    }
+}
+
+// Undo rate_match: punctured bits come back as erasures, repeated bits are voted on
+pub fn rate_recover(bits: &str, encoded_length: usize) -> String {
+    let chars: Vec<char> = bits.chars().collect();
+    let target_length = chars.len();
+
+    if encoded_length == 0 {
+        return "".to_string();
+    }
+
+    if encoded_length >= target_length {
+        let mut received_bits = chars.into_iter();
+        return (0..encoded_length)
+            .map(|i| {
+                if rate_match_keeps(i, encoded_length, target_length) {
+                    received_bits.next().unwrap_or(ERASURE)
+                } else {
+                    ERASURE
+                }
+            })
+            .collect();
+    }
+
+    (0..encoded_length)
+        .map(|i| {
+            let copies = chars.iter().skip(i).step_by(encoded_length);
+            let (count_0, count_1) = copies.fold((0, 0), |(zeros, ones), &bit| match bit {
+                '0' => (zeros + 1, ones),
+                '1' => (zeros, ones + 1),
+                _ => (zeros, ones),
+            });
+            if count_1 > count_0 {
+                '1'
+            } else if count_0 > count_1 {
+                '0'
+            } else {
+                ERASURE
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::correction::encode_hamming;
+
+    #[test]
+    fn test_shortened_hamming_uses_block_parity_bits() {
+        let input = String::from("1011");
+        let (ok, shortened) = encode_shortened(&CorrectionType::Hamming, &input, 11);
+        assert!(ok);
+        // Hamming(15, 11) shortened by 7 keeps all 4 parity bits, plain Hamming would use 3
+        assert_eq!(shortened.len(), 8);
+        assert_eq!(encode_hamming(&input, false).1.len(), 7);
+
+        let expected = (false, false, input.clone(), input.clone());
+        assert_eq!(
+            decode_shortened(&CorrectionType::Hamming, &shortened, 11),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_shortened_hamming_corrects_error() {
+        let input = String::from("110100");
+        let shortened = encode_shortened(&CorrectionType::Hamming, &input, 11).1;
+        let mut noisy: Vec<char> = shortened.chars().collect();
+        noisy[4] = if noisy[4] == '0' { '1' } else { '0' };
+        let noisy: String = noisy.into_iter().collect();
+
+        let result = decode_shortened(&CorrectionType::Hamming, &noisy, 11);
+        assert_eq!((result.0, result.1), (true, true));
+        assert_eq!(result.3, input);
+    }
+
+    #[test]
+    fn test_block_too_long_for_hamming_is_an_error() {
+        // Hamming encodes at most 501 bits, so there is no codeword to shorten
+        let (ok, _) = encode_shortened(&CorrectionType::Hamming, "1011", 600);
+        assert!(!ok);
+        assert_eq!(data_positions(&CorrectionType::Hamming, 600), None);
+
+        let result = decode_shortened(&CorrectionType::Hamming, "10110100", 600);
+        assert_eq!(result, (true, false, "".to_string(), "".to_string()));
+    }
+
+    #[test]
+    fn test_puncture_round_trip() {
+        let pattern = PuncturePattern::new("110");
+        let punctured = puncture("10110111", &pattern);
+        assert_eq!(punctured, "101011");
+        assert_eq!(depuncture(&punctured, &pattern, 8), "10?10?11");
+    }
+
+    #[test]
+    fn test_punctured_triple_still_decodes() {
+        let input = String::from("1101");
+        let encoded = encode_correction(&CorrectionType::Triple, &input).1;
+        // Keep the length header, drop every third copy bit
+        let pattern = PuncturePattern::new(&format!("{}{}", "1".repeat(9), "110".repeat(4)));
+        let received = depuncture(&puncture(&encoded, &pattern), &pattern, encoded.len());
+
+        let result = decode_stage(&CorrectionType::Triple, &received);
+        assert!(!result.0);
+        assert_eq!(result.3, input);
+    }
+
+    #[test]
+    fn test_punctured_convolutional_rate_two_thirds() {
+        let input = "1011001110";
+        let encoded = encode_correction(&CorrectionType::Convolutional, input).1;
+        // Drop every fourth bit: 3 sent for every 2 data bits
+        let pattern = PuncturePattern::new("1110");
+        let punctured = puncture(&encoded, &pattern);
+        assert_eq!(punctured.len(), encoded.len() * 3 / 4);
+
+        let received = depuncture(&punctured, &pattern, encoded.len());
+        assert_eq!(
+            decode_stage(&CorrectionType::Convolutional, &received).3,
+            input
+        );
+    }
+
+    #[test]
+    fn test_rate_match_exact_length() {
+        let encoded = encode_correction(&CorrectionType::Hamming, "1100101").1;
+
+        let squeezed = rate_match(&encoded, 9);
+        assert_eq!(squeezed.len(), 9);
+        assert_eq!(
+            rate_recover(&squeezed, encoded.len())
+                .matches(ERASURE)
+                .count(),
+            2
+        );
+
+        let stretched = rate_match(&encoded, 25);
+        assert_eq!(stretched.len(), 25);
+        assert_eq!(rate_recover(&stretched, encoded.len()), encoded);
+    }
+}
@@ -1,118 +1,362 @@
-use correction::{decode_correction, CorrectionType};
 use std::collections::HashMap;
-use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
+use std::io::{self, ErrorKind};
+use std::net::{Shutdown, SocketAddr, UdpSocket};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-use huffman::{HuffmanDecoding, HuffmanEncoding};
+use arq::{arq_scheme_from_args, ArqScheme};
+use endpoint::{endpoint_from_args, Endpoint, Listener, Stream, DEFAULT_RECEIVER_ADDRESS};
+use handshake::error_packet;
+use metrics::{metrics_from_args, serve_metrics, Metrics};
+use protocol::{read_packet, write_packet, Packet, PacketDecoder};
+use script::EXIT_USAGE;
+use session::ReceiverSession;
+use stats::ReceiverStats;
+use transport::{
+    decode_datagram, encode_datagram, is_timeout, udp_from_args, SequenceTracker, MAX_DATAGRAM_SIZE,
+};
 
+pub mod arq;
 pub mod correction;
+pub mod endpoint;
+pub mod handshake;
+pub mod harq;
 pub mod huffman;
+pub mod metrics;
+pub mod modulation;
+pub mod noise;
+pub mod protocol;
+pub mod rate_matching;
+pub mod report;
+pub mod script;
+pub mod session;
+pub mod stats;
+pub mod transport;
 
-fn decode_message(message: &str, hd: &HuffmanDecoding) -> String {
-    if hd.max_size == 0 {
-        return "".to_string();
+fn handle_client(
+    mut stream: Stream,
+    arq_scheme: Option<ArqScheme>,
+    metrics: &Metrics,
+) -> ReceiverStats {
+    let mut decoder = PacketDecoder::new();
+    let mut session = ReceiverSession::new(arq_scheme);
+    let metrics_id = metrics.open();
+
+    while !session.is_closed() {
+        let packet = match read_packet(&mut stream, &mut decoder) {
+            Ok(Some(packet)) => packet,
+            Ok(None) => break,
+            Err(e) => {
+                println!("Closing connection: {}", e);
+                if e.kind() == ErrorKind::InvalidData {
+                    let _ = write_packet(&mut stream, &session.reject(&e.to_string()));
+                }
+                break;
+            }
+        };
+
+        let replies = session.handle_packet(packet);
+        metrics.update(metrics_id, &session.stats);
+        // The sender may hang up with a reply on the way, the session still has to finish
+        let sent = replies
+            .iter()
+            .try_for_each(|reply| write_packet(&mut stream, reply));
+        if let Err(e) = sent {
+            println!("Failed to send response: {}", e);
+            break;
+        }
     }
 
-    let mut decoded_string: String = "".to_string();
+    let stats = session.finish();
+    metrics.close(metrics_id, &stats);
+    stats
+}
 
-    let mut left_ptr = 0;
-    let mut right_ptr = 1;
+const DEFAULT_MAX_CONNECTIONS: usize = 8;
+// How often the accept loop looks at the Ctrl-C flag
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const UDP_PEER_TIMEOUT: Duration = Duration::from_secs(60);
 
-    while right_ptr < message.len() && left_ptr < message.len() {
-        let string_slice = &message[left_ptr..right_ptr];
-        match hd.decoding.get(string_slice) {
-            Some(value) => {
-                decoded_string.push(*value);
-                left_ptr = right_ptr;
-                right_ptr += 1;
-            }
-            None => right_ptr += 1,
-        }
+fn max_connections_from_args(args: &[String]) -> Result<usize, String> {
+    match args.iter().position(|arg| arg == "--max-connections") {
+        None => Ok(DEFAULT_MAX_CONNECTIONS),
+        Some(index) => match args.get(index + 1).map(|value| value.parse::<usize>()) {
+            Some(Ok(limit)) if limit > 0 => Ok(limit),
+            _ => Err("--max-connections needs a number of at least 1".to_string()),
+        },
     }
+}
 
-    return decoded_string;
+struct Connection {
+    // Kept to unblock the handler's read on shutdown
+    stream: Stream,
+    handle: JoinHandle<()>,
 }
-fn handle_client(mut stream: TcpStream) {
-    let mut buffer = [0; 512];
-    let mut message_count = 0;
 
-    let mut decoding_table: HuffmanDecoding = HuffmanDecoding {
-        decoding: HashMap::new(),
-        max_size: 0,
-    };
+fn serve_stream(
+    endpoint: &Endpoint,
+    arq_scheme: Option<ArqScheme>,
+    max_connections: usize,
+    metrics: &Arc<Metrics>,
+    shutdown: &AtomicBool,
+) -> io::Result<ReceiverStats> {
+    let listener = Listener::bind(endpoint)?;
+    // Non-blocking, so the loop notices Ctrl-C even when nobody connects
+    listener.set_nonblocking(true)?;
+    println!(
+        "Receiver is listening on {} (up to {} connections)",
+        endpoint, max_connections
+    );
 
-    while let Ok(bytes_read) = stream.read(&mut buffer) {
-        if bytes_read == 0 {
-            break;
-        }
-        let received_message = String::from_utf8_lossy(&buffer[..bytes_read]);
-        println!("Received: {}", received_message);
-        let last_char: char = received_message.chars().last().unwrap();
-        let correction_type = match last_char {
-            'P' => CorrectionType::Parity,
-            'T' => CorrectionType::Triple,
-            _ => CorrectionType::Hamming,
+    let mut connections: Vec<Connection> = vec![];
+
+    while !shutdown.load(Ordering::Relaxed) {
+        connections.retain(|connection| !connection.handle.is_finished());
+
+        let mut stream = match listener.accept() {
+            Ok(stream) => stream,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+                continue;
+            }
+            Err(e) => {
+                println!("Connection failed: {}", e);
+                continue;
+            }
         };
+        stream.set_nonblocking(false)?;
 
-        if message_count == 0 {
-            decoding_table = HuffmanEncoding::decode_table(&received_message).unwrap();
-            message_count += 1;
+        if connections.len() >= max_connections {
+            println!(
+                "Turning away a connection, already serving {}",
+                max_connections
+            );
+            let busy = format!("receiver is busy, {} connections open", max_connections);
+            let _ = write_packet(&mut stream, &error_packet(&busy));
+            metrics.add(&ReceiverStats {
+                errors_sent: 1,
+                ..Default::default()
+            });
             continue;
         }
 
-        let error_decoded_message_values =
-            decode_correction(correction_type, &received_message.to_string(), false);
+        println!("New connection established!");
+        let metrics = Arc::clone(metrics);
+        let handle = match stream.try_clone() {
+            Ok(handler_stream) => thread::spawn(move || {
+                handle_client(handler_stream, arq_scheme, &metrics);
+            }),
+            Err(e) => {
+                println!("Connection failed: {}", e);
+                continue;
+            }
+        };
+        connections.push(Connection { stream, handle });
+    }
 
-        if error_decoded_message_values.0 && !error_decoded_message_values.1 {
-            println!("Found errors in message but cannot correct them!");
-            println!(
-                "Decoding with errors: {}",
-                decode_message(&error_decoded_message_values.3, &decoding_table)
-            );
-        } else if error_decoded_message_values.0 {
-            println!("Found errors in message and corrected them!");
-            println!(
-                "Message if there was no correction: {}",
-                decode_message(&error_decoded_message_values.2, &decoding_table)
-            );
-            println!(
-                "Message after applying error correction: {}",
-                decode_message(&error_decoded_message_values.3, &decoding_table)
-            );
-        } else {
-            println!("Found NO errors in message :)");
-            println!(
-                "Decoding message: {}",
-                decode_message(&error_decoded_message_values.3, &decoding_table)
-            );
+    println!("Shutting down, closing {} connection(s)", connections.len());
+    for connection in connections {
+        let _ = connection.stream.shutdown(Shutdown::Both);
+        let _ = connection.handle.join();
+    }
+
+    Ok(metrics.closed())
+}
+
+// A sender we know over UDP, by its address
+struct UdpPeer {
+    session: ReceiverSession,
+    metrics_id: usize,
+    tracker: SequenceTracker,
+    next_sequence: u32,
+    last_seen: Instant,
+}
+
+impl UdpPeer {
+    fn new(arq_scheme: Option<ArqScheme>, metrics: &Metrics) -> Self {
+        UdpPeer {
+            session: ReceiverSession::new(arq_scheme),
+            metrics_id: metrics.open(),
+            tracker: SequenceTracker::new(),
+            next_sequence: 0,
+            last_seen: Instant::now(),
         }
-        let mut decoded_message_to_send =
-            decode_message(&error_decoded_message_values.3, &decoding_table);
+    }
 
-        if decoded_message_to_send == "" {
-            decoded_message_to_send = "Encoding Table not sent :(".to_string();
+    fn send(&mut self, socket: &UdpSocket, address: SocketAddr, packet: &Packet) {
+        let sent = encode_datagram(self.next_sequence, packet)
+            .map_err(io::Error::from)
+            .and_then(|datagram| socket.send_to(&datagram, address));
+        if let Err(e) = sent {
+            println!("Failed to send response: {}", e);
         }
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+    }
 
-        let response = decoded_message_to_send.as_bytes();
-        stream.write_all(response).expect("Failed to send response");
+    fn finish(self, metrics: &Metrics) {
+        println!("{}", self.tracker);
+        let mut stats = self.session.finish();
+        stats.datagrams_received += self.tracker.received;
+        stats.datagrams_lost += self.tracker.lost();
+        stats.datagrams_reordered += self.tracker.reordered;
+        stats.datagram_duplicates += self.tracker.duplicates;
+        metrics.close(self.metrics_id, &stats);
     }
 }
 
-fn main() -> std::io::Result<()> {
-    let listener = TcpListener::bind("127.0.0.1:6969").unwrap();
-    println!("Receiver is listening on 127.0.0.1:6969");
+/* There are no connections over UDP, every datagram is matched to a session by the
+ * address it came from. A sender that goes quiet for UDP_PEER_TIMEOUT is forgotten,
+ * since nothing tells us when it quits.
+ */
+fn serve_udp(
+    address: SocketAddr,
+    arq_scheme: Option<ArqScheme>,
+    max_peers: usize,
+    metrics: &Metrics,
+    shutdown: &AtomicBool,
+) -> io::Result<ReceiverStats> {
+    let socket = UdpSocket::bind(address)?;
+    socket.set_read_timeout(Some(ACCEPT_POLL_INTERVAL))?;
+    println!(
+        "Receiver is listening for UDP on {} (up to {} senders)",
+        address, max_peers
+    );
 
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                println!("New connection established!");
-                handle_client(stream);
-            }
+    let mut peers: HashMap<SocketAddr, UdpPeer> = HashMap::new();
+    let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+
+    while !shutdown.load(Ordering::Relaxed) {
+        let idle: Vec<SocketAddr> = peers
+            .iter()
+            .filter(|(_, peer)| peer.last_seen.elapsed() > UDP_PEER_TIMEOUT)
+            .map(|(address, _)| *address)
+            .collect();
+        for address in idle {
+            println!("Sender at {} went quiet", address);
+            peers.remove(&address).unwrap().finish(metrics);
+        }
+
+        let (length, address) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) if is_timeout(&e) => continue,
             Err(e) => {
-                println!("Connection failed: {}", e);
+                println!("Failed to receive: {}", e);
+                continue;
+            }
+        };
+
+        if !peers.contains_key(&address) {
+            if peers.len() >= max_peers {
+                println!("Turning away {}, already serving {}", address, max_peers);
+                let busy = format!("receiver is busy, {} senders known", max_peers);
+                if let Ok(datagram) = encode_datagram(0, &error_packet(&busy)) {
+                    let _ = socket.send_to(&datagram, address);
+                }
+                metrics.add(&ReceiverStats {
+                    errors_sent: 1,
+                    ..Default::default()
+                });
+                continue;
+            }
+            println!("New sender at {}", address);
+            peers.insert(address, UdpPeer::new(arq_scheme, metrics));
+        }
+
+        let peer = peers.get_mut(&address).unwrap();
+        peer.last_seen = Instant::now();
+        match decode_datagram(&buffer[..length]) {
+            Ok((sequence, packet)) => {
+                if peer.tracker.observe(sequence) {
+                    for reply in peer.session.handle_packet(packet) {
+                        peer.send(&socket, address, &reply);
+                    }
+                }
+            }
+            Err(error) if error.is_fatal() => {
+                let reply = peer.session.reject(&error.to_string());
+                peer.send(&socket, address, &reply);
             }
+            Err(error) => println!("Skipping bad datagram from {}: {}", address, error),
+        }
+        metrics.update(peer.metrics_id, &peer.session.stats);
+
+        if peer.session.is_closed() {
+            peers.remove(&address).unwrap().finish(metrics);
+        }
+    }
+
+    println!("Shutting down, forgetting {} sender(s)", peers.len());
+    for (_, peer) in peers.drain() {
+        peer.finish(metrics);
+    }
+
+    Ok(metrics.closed())
+}
+
+fn main() -> io::Result<ExitCode> {
+    let args: Vec<String> = std::env::args().collect();
+    let arq_scheme = match arq_scheme_from_args(&args) {
+        Ok(arq_scheme) => arq_scheme,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Ok(ExitCode::from(EXIT_USAGE));
+        }
+    };
+    let max_connections = match max_connections_from_args(&args) {
+        Ok(max_connections) => max_connections,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Ok(ExitCode::from(EXIT_USAGE));
+        }
+    };
+
+    let endpoint = match endpoint_from_args(
+        &args,
+        "--listen",
+        "RECEIVER_LISTEN",
+        DEFAULT_RECEIVER_ADDRESS,
+    ) {
+        Ok(endpoint) => endpoint,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Ok(ExitCode::from(EXIT_USAGE));
+        }
+    };
+
+    let metrics = Arc::new(Metrics::new());
+    match metrics_from_args(&args) {
+        Ok(Some(address)) => {
+            let address = serve_metrics(address, Arc::clone(&metrics))?;
+            println!("Serving metrics on http://{}/metrics", address);
+        }
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("{}", e);
+            return Ok(ExitCode::from(EXIT_USAGE));
         }
     }
 
-    Ok(())
+    let shutdown = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&shutdown))?;
+
+    let total_stats = if udp_from_args(&args) {
+        let address = match endpoint.ip() {
+            Ok(address) => address,
+            Err(e) => {
+                eprintln!("UDP needs an IP address: {}", e);
+                return Ok(ExitCode::from(EXIT_USAGE));
+            }
+        };
+        serve_udp(address, arq_scheme, max_connections, &metrics, &shutdown)?
+    } else {
+        serve_stream(&endpoint, arq_scheme, max_connections, &metrics, &shutdown)?
+    };
+
+    println!("Statistics for all connections:");
+    println!("{}", total_stats);
+
+    Ok(ExitCode::SUCCESS)
 }
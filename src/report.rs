@@ -0,0 +1,230 @@
+use std::fmt;
+
+use crate::correction::CorrectionType;
+use crate::protocol::{Packet, PacketType};
+use crate::stats::DecodeOutcome;
+
+/* What the receiver answers every message with, as the payload of a Response
+ * packet. The code it decoded with goes in the packet header like everywhere else:
+ *
+ *   status      1 byte   0 clean, 1 corrected, 2 errors left
+ *   message id  1 byte   of the Data or HARQ packets it answers
+ *   hash        4 bytes  FNV-1a of the decoded text, big endian
+ *   corrected   4 bytes  number of corrected bits, then 4 bytes per position
+ *   text        the rest, the decoded message
+ *
+ * Positions count data bits, after the error correction and before the Huffman
+ * decoding, so they line up with the sender's Huffman coded message. HARQ corrects
+ * by sending more instead, mixing CRC and Hamming check bits, so its reports have
+ * neither a code nor positions.
+ */
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodeReport {
+    pub message_id: u8,
+    pub outcome: DecodeOutcome,
+    pub code: Option<CorrectionType>,
+    pub corrected_positions: Vec<usize>,
+    pub hash: u32,
+    pub message: String,
+}
+
+/// 32 bit FNV-1a, enough to tell whether two messages are the same.
+pub fn payload_hash(text: &str) -> u32 {
+    text.bytes().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+// Where the error correction changed a bit, comparing data bits before and after it
+pub fn corrected_positions(original: &str, corrected: &str) -> Vec<usize> {
+    if original.len() != corrected.len() {
+        return vec![];
+    }
+
+    original
+        .chars()
+        .zip(corrected.chars())
+        .enumerate()
+        .filter(|(_, (before, after))| before != after)
+        .map(|(position, _)| position)
+        .collect()
+}
+
+fn outcome_id(outcome: DecodeOutcome) -> u8 {
+    match outcome {
+        DecodeOutcome::Clean => 0,
+        DecodeOutcome::Corrected => 1,
+        DecodeOutcome::Uncorrectable => 2,
+    }
+}
+
+fn outcome_from_id(id: u8) -> Option<DecodeOutcome> {
+    match id {
+        0 => Some(DecodeOutcome::Clean),
+        1 => Some(DecodeOutcome::Corrected),
+        2 => Some(DecodeOutcome::Uncorrectable),
+        _ => None,
+    }
+}
+
+impl DecodeReport {
+    pub fn new(
+        message_id: u8,
+        outcome: DecodeOutcome,
+        code: Option<CorrectionType>,
+        corrected_positions: Vec<usize>,
+        message: String,
+    ) -> Self {
+        DecodeReport {
+            message_id,
+            outcome,
+            code,
+            corrected_positions,
+            hash: payload_hash(&message),
+            message,
+        }
+    }
+
+    pub fn to_packet(&self) -> Packet {
+        let mut payload = vec![outcome_id(self.outcome), self.message_id];
+        payload.extend(self.hash.to_be_bytes());
+        payload.extend((self.corrected_positions.len() as u32).to_be_bytes());
+        for position in &self.corrected_positions {
+            payload.extend((*position as u32).to_be_bytes());
+        }
+        payload.extend(self.message.as_bytes());
+
+        Packet::new(PacketType::Response, self.code.clone(), payload)
+    }
+
+    /// None when the packet isn't a well formed report.
+    pub fn from_packet(packet: &Packet) -> Option<Self> {
+        if packet.packet_type != PacketType::Response {
+            return None;
+        }
+
+        let (&status, rest) = packet.payload.split_first()?;
+        let (&message_id, rest) = rest.split_first()?;
+        let (hash, rest) = rest.split_first_chunk::<4>()?;
+        let (count, mut rest) = rest.split_first_chunk::<4>()?;
+
+        let mut corrected_positions = vec![];
+        for _ in 0..u32::from_be_bytes(*count) {
+            let (position, remaining) = rest.split_first_chunk::<4>()?;
+            corrected_positions.push(u32::from_be_bytes(*position) as usize);
+            rest = remaining;
+        }
+
+        Some(DecodeReport {
+            message_id,
+            outcome: outcome_from_id(status)?,
+            code: packet.code.clone(),
+            corrected_positions,
+            hash: u32::from_be_bytes(*hash),
+            message: String::from_utf8_lossy(rest).to_string(),
+        })
+    }
+
+    /// Whether this is what was sent, going by the hash.
+    pub fn matches(&self, sent: &str) -> bool {
+        self.hash == payload_hash(sent)
+    }
+}
+
+impl fmt::Display for DecodeReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = match self.outcome {
+            DecodeOutcome::Clean => "clean",
+            DecodeOutcome::Corrected => "corrected",
+            DecodeOutcome::Uncorrectable => "errors left",
+        };
+        write!(f, "{} ({}", self.message, status)?;
+        if !self.corrected_positions.is_empty() {
+            write!(
+                f,
+                ", {} bit(s) corrected at {:?}",
+                self.corrected_positions.len(),
+                self.corrected_positions
+            )?;
+        }
+        if let Some(code) = &self.code {
+            write!(f, ", {:?}", code)?;
+        }
+        write!(f, ", hash {:08x})", self.hash)
+    }
+}
+
+/// Reports added up over a session on the sender's side.
+#[derive(Clone, Debug, Default)]
+pub struct ReportStats {
+    pub messages: usize,
+    pub clean: usize,
+    pub corrected: usize,
+    pub uncorrectable: usize,
+    pub corrected_bits: usize,
+    // Reports whose hash doesn't match the message we sent
+    pub mismatches: usize,
+}
+
+impl ReportStats {
+    pub fn record(&mut self, report: &DecodeReport, sent: &str) {
+        self.messages += 1;
+        match report.outcome {
+            DecodeOutcome::Clean => self.clean += 1,
+            DecodeOutcome::Corrected => self.corrected += 1,
+            DecodeOutcome::Uncorrectable => self.uncorrectable += 1,
+        }
+        self.corrected_bits += report.corrected_positions.len();
+        if !report.matches(sent) {
+            self.mismatches += 1;
+        }
+    }
+}
+
+impl fmt::Display for ReportStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Messages reported: {} ({} clean, {} corrected, {} with errors left)",
+            self.messages, self.clean, self.corrected, self.uncorrectable
+        )?;
+        write!(
+            f,
+            "Bits corrected: {}, messages that came back different: {}",
+            self.corrected_bits, self.mismatches
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_round_trip() {
+        let report = DecodeReport::new(
+            5,
+            DecodeOutcome::Corrected,
+            Some(CorrectionType::Hamming),
+            vec![3, 17],
+            "hello how are you".to_string(),
+        );
+        let packet = report.to_packet();
+        assert_eq!(packet.code, Some(CorrectionType::Hamming));
+        assert_eq!(DecodeReport::from_packet(&packet), Some(report.clone()));
+        assert!(report.matches("hello how are you"));
+        assert!(!report.matches("hello how are yo"));
+
+        let mut truncated = packet.clone();
+        truncated.payload.truncate(10);
+        assert_eq!(DecodeReport::from_packet(&truncated), None);
+    }
+
+    #[test]
+    fn test_corrected_positions() {
+        assert_eq!(corrected_positions("10110", "10011"), vec![2, 4]);
+        assert_eq!(corrected_positions("1011", "1011"), Vec::<usize>::new());
+        assert_eq!(corrected_positions("", "1011"), Vec::<usize>::new());
+    }
+}
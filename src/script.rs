@@ -0,0 +1,320 @@
+use std::fmt::{self, Write};
+use std::fs;
+
+use crate::correction::CorrectionType;
+use crate::harq::HarqMode;
+use crate::noise::NoiseReport;
+use crate::report::DecodeReport;
+use crate::stats::DecodeOutcome;
+
+/* Running the sender from a script instead of answering its prompts:
+ *
+ *   sender --code hamming --message "hello how are you" [--repeat N] [--json]
+ *   sender --code crc --input-file messages.txt
+ *
+ * Every message goes out with the same code, the input file has one message per
+ * line. The exit code tells how it went, see the EXIT_ constants.
+ */
+
+// Every message came back from the receiver exactly as it was sent
+pub const EXIT_DECODED: u8 = 0;
+pub const EXIT_NOT_DECODED: u8 = 1;
+// Bad flags or input, nothing was sent
+pub const EXIT_USAGE: u8 = 2;
+// Couldn't reach the receiver, or it hung up or refused us
+pub const EXIT_LINK: u8 = 3;
+
+/// The names --code takes, in the order of the interactive menu.
+pub const CODE_NAMES: [&str; 10] = [
+    "parity",
+    "triple",
+    "hamming",
+    "concatenated",
+    "product",
+    "harq-ir",
+    "harq-chase",
+    "crc",
+    "vt",
+    "convolutional",
+];
+
+// HARQ sends CRC protected words, like menu choices 6 and 7
+pub fn code_from_name(name: &str) -> Option<(CorrectionType, Option<HarqMode>)> {
+    match name {
+        "parity" => Some((CorrectionType::Parity, None)),
+        "triple" | "tpc" => Some((CorrectionType::Triple, None)),
+        "hamming" => Some((CorrectionType::Hamming, None)),
+        "concatenated" => Some((CorrectionType::Concatenated(Box::default()), None)),
+        "product" => Some((CorrectionType::Product(Box::default()), None)),
+        "harq-ir" => Some((CorrectionType::Crc, Some(HarqMode::IncrementalRedundancy))),
+        "harq-chase" => Some((CorrectionType::Crc, Some(HarqMode::ChaseCombining))),
+        "crc" => Some((CorrectionType::Crc, None)),
+        "vt" => Some((CorrectionType::Vt, None)),
+        "convolutional" => Some((CorrectionType::Convolutional, None)),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScriptOptions {
+    pub code_name: String,
+    pub correction_type: CorrectionType,
+    pub harq_mode: Option<HarqMode>,
+    pub messages: Vec<String>,
+    pub repeat: usize,
+    pub json: bool,
+}
+
+pub fn flag_value<'a>(args: &'a [String], flag: &str) -> Result<Option<&'a String>, String> {
+    match args.iter().position(|arg| arg == flag) {
+        None => Ok(None),
+        Some(index) => args
+            .get(index + 1)
+            .map(Some)
+            .ok_or(format!("{} needs a value", flag)),
+    }
+}
+
+/// None when neither --message nor --input-file is given, the sender asks instead.
+pub fn script_from_args(args: &[String]) -> Result<Option<ScriptOptions>, String> {
+    let mut messages = vec![];
+    if let Some(message) = flag_value(args, "--message")? {
+        messages.push(message.trim().to_string());
+    }
+    if let Some(path) = flag_value(args, "--input-file")? {
+        let contents =
+            fs::read_to_string(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+        messages.extend(
+            contents
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(String::from),
+        );
+    }
+
+    let code_name = flag_value(args, "--code")?;
+    let repeat = flag_value(args, "--repeat")?;
+    let json = args.iter().any(|arg| arg == "--json");
+
+    if messages.is_empty() {
+        if flag_value(args, "--input-file")?.is_some() {
+            return Err("The input file has no messages in it".to_string());
+        }
+        if code_name.is_some() || repeat.is_some() || json {
+            return Err("--code, --repeat and --json need --message or --input-file".to_string());
+        }
+        return Ok(None);
+    }
+
+    let code_name = code_name.ok_or(format!("--code needs one of {:?}", CODE_NAMES))?;
+    let (correction_type, harq_mode) = code_from_name(code_name).ok_or(format!(
+        "Unknown code {}, use one of {:?}",
+        code_name, CODE_NAMES
+    ))?;
+    let repeat = match repeat.map(|value| value.parse::<usize>()) {
+        None => 1,
+        Some(Ok(repeat)) if repeat > 0 => repeat,
+        _ => return Err("--repeat needs a number of at least 1".to_string()),
+    };
+
+    Ok(Some(ScriptOptions {
+        code_name: code_name.clone(),
+        correction_type,
+        harq_mode,
+        messages,
+        repeat,
+        json,
+    }))
+}
+
+/// How one message did.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MessageReport {
+    pub message: String,
+    pub code: String,
+    // Packets, HARQ transmissions or ARQ frames it took
+    pub transmissions: usize,
+    // None when the receiver never answered
+    pub report: Option<DecodeReport>,
+    // What the noise did to each transmission
+    pub noise: Vec<NoiseReport>,
+}
+
+fn json_positions(positions: &[usize]) -> String {
+    let positions: Vec<String> = positions
+        .iter()
+        .map(|position| position.to_string())
+        .collect();
+    format!("[{}]", positions.join(","))
+}
+
+fn status_name(outcome: DecodeOutcome) -> &'static str {
+    match outcome {
+        DecodeOutcome::Clean => "clean",
+        DecodeOutcome::Corrected => "corrected",
+        DecodeOutcome::Uncorrectable => "uncorrectable",
+    }
+}
+
+impl MessageReport {
+    // The receiver's hash of what it decoded matches what we sent
+    pub fn decoded(&self) -> bool {
+        self.report
+            .as_ref()
+            .is_some_and(|report| report.matches(&self.message))
+    }
+
+    pub fn flipped_bits(&self) -> usize {
+        self.noise
+            .iter()
+            .map(|noise| noise.flipped_positions.len())
+            .sum()
+    }
+
+    // One line of JSON, no spaces
+    pub fn to_json(&self) -> String {
+        let report = match &self.report {
+            Some(report) => {
+                format!(
+                    "{{\"status\":\"{}\",\"corrected_positions\":{},\"hash\":\"{:08x}\",\"message\":{}}}",
+                    status_name(report.outcome),
+                    json_positions(&report.corrected_positions),
+                    report.hash,
+                    json_string(&report.message)
+                )
+            }
+            None => "null".to_string(),
+        };
+        let flipped: Vec<String> = self
+            .noise
+            .iter()
+            .map(|noise| json_positions(&noise.flipped_positions))
+            .collect();
+        format!(
+            "{{\"message\":{},\"code\":{},\"transmissions\":{},\"flipped_positions\":[{}],\"report\":{},\"decoded\":{}}}",
+            json_string(&self.message),
+            json_string(&self.code),
+            self.transmissions,
+            flipped.join(","),
+            report,
+            self.decoded()
+        )
+    }
+}
+
+impl fmt::Display for MessageReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let outcome = if self.decoded() {
+            "decoded"
+        } else {
+            "NOT decoded"
+        };
+        write!(
+            f,
+            "{} with {}: {}, {} transmission(s), {} bit(s) flipped",
+            self.message,
+            self.code,
+            outcome,
+            self.transmissions,
+            self.flipped_bits()
+        )?;
+        match &self.report {
+            Some(report) => write!(f, ", receiver got {}", report),
+            None => write!(f, ", no answer"),
+        }
+    }
+}
+
+pub fn json_string(text: &str) -> String {
+    let mut output = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(output, "\\u{:04x}", c as u32);
+            }
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_script_from_args() {
+        let args = to_args(&[
+            "sender",
+            "--code",
+            "harq-ir",
+            "--message",
+            "hello",
+            "--json",
+        ]);
+        let options = script_from_args(&args).unwrap().unwrap();
+        assert_eq!(options.messages, vec!["hello"]);
+        assert_eq!(options.correction_type, CorrectionType::Crc);
+        assert_eq!(options.harq_mode, Some(HarqMode::IncrementalRedundancy));
+        assert_eq!((options.repeat, options.json), (1, true));
+
+        assert_eq!(
+            script_from_args(&to_args(&["sender", "--arq", "go-back-n"])),
+            Ok(None)
+        );
+        assert!(script_from_args(&to_args(&["sender", "--message", "hello"])).is_err());
+        assert!(script_from_args(&to_args(&["sender", "--code", "hamming"])).is_err());
+        assert!(script_from_args(&to_args(&[
+            "sender",
+            "--code",
+            "hamming",
+            "--message",
+            "hello",
+            "--repeat",
+            "0"
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_report_json() {
+        let mut report = MessageReport {
+            message: "say \"hi\"".to_string(),
+            code: "crc".to_string(),
+            transmissions: 1,
+            report: None,
+            noise: vec![NoiseReport {
+                flipped_positions: vec![2, 30],
+                ..Default::default()
+            }],
+        };
+        assert!(!report.decoded());
+        assert_eq!(
+            report.to_json(),
+            r#"{"message":"say \"hi\"","code":"crc","transmissions":1,"flipped_positions":[[2,30]],"report":null,"decoded":false}"#
+        );
+
+        report.report = Some(DecodeReport::new(
+            0,
+            DecodeOutcome::Corrected,
+            None,
+            vec![4, 9],
+            "say \"hi\"".to_string(),
+        ));
+        assert!(report.decoded());
+        assert!(report
+            .to_json()
+            .contains(r#""report":{"status":"corrected","corrected_positions":[4,9],"#));
+    }
+}
@@ -1,14 +1,52 @@
 use std::collections::HashSet;
-use std::io::{self, Read, Write};
-use std::net::TcpStream;
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, UdpSocket};
+use std::process::ExitCode;
+use std::time::Instant;
 
+use arq::{
+    arq_scheme_from_args, decode_frame, encode_frame, ArqScheme, ArqSender, ArqSenderStats,
+    ARQ_TIMEOUT,
+};
+use channel::{channel_from_args, CHANNEL_ADDRESS, CHANNEL_RESPONSE_TIMEOUT};
 use correction::{encode_correction, CorrectionType};
-use huffman::HuffmanEncoding;
-use noise::add_noise;
+use endpoint::{endpoint_from_args, Stream, DEFAULT_RECEIVER_ADDRESS};
+use handshake::{hello_packet, read_accept};
+use harq::{harq_transmission, HarqMode, MAX_TRANSMISSIONS};
+use huffman::{encode_message, HuffmanEncoding};
+use noise::{
+    add_noise, flip_random_bits, noise_from_args, seed_from_args, Channel, NoiseMode, NoiseReport,
+};
+use protocol::{Packet, PacketType};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use report::{DecodeReport, ReportStats};
+use script::{
+    code_from_name, script_from_args, MessageReport, ScriptOptions, CODE_NAMES, EXIT_DECODED,
+    EXIT_LINK, EXIT_NOT_DECODED, EXIT_USAGE,
+};
+use transport::{is_timeout, udp_from_args, StreamTransport, Transport, UdpTransport};
 
+pub mod arq;
+pub mod channel;
 pub mod correction;
+pub mod endpoint;
+pub mod handshake;
+pub mod harq;
 pub mod huffman;
+pub mod modulation;
 pub mod noise;
+pub mod protocol;
+pub mod rate_matching;
+pub mod report;
+pub mod script;
+pub mod stats;
+pub mod transport;
+
+// Chance that the channel flips a bit in an ARQ frame
+const ARQ_FRAME_ERROR_RATE: f64 = 0.2;
+// Over UDP the Hello or its answer can get lost
+const HANDSHAKE_ATTEMPTS: usize = 3;
 
 const VALID_WORDS: [&str; 13] = [
     "hello", "how", "are", "you", " ", "#", "mikail", "saad", "sagar", "is", "sarthak", "so",
@@ -24,118 +62,650 @@ fn uses_valid_vocab(message: &str, vocab: &HashSet<&str>) -> bool {
         }
     }
 
-    return true;
+    true
+}
+
+// The receiver sends an Error and hangs up when it can't handle what we sent
+fn receiver_error(packet: &Packet) -> io::Error {
+    io::Error::other(format!("receiver refused: {}", packet.payload_text()))
+}
+
+// A report on an earlier message, one the channel delivered twice or held back
+fn answers_other_message(packet: &Packet, message_id: u8) -> bool {
+    DecodeReport::from_packet(packet).is_some_and(|report| report.message_id != message_id)
 }
 
-fn encode_message(message: &str, hf: &HuffmanEncoding) -> String {
-    let mut encoded_message: String = "".to_string();
+fn read_report(packet: &Packet) -> io::Result<DecodeReport> {
+    DecodeReport::from_packet(packet).ok_or(io::Error::new(
+        ErrorKind::InvalidData,
+        "bad report from the receiver",
+    ))
+}
+
+/// What came of sending one message.
+struct Delivery {
+    // Packets, HARQ transmissions or ARQ frames
+    transmissions: usize,
+    // None when the receiver never answered
+    report: Option<DecodeReport>,
+    // What the noise did to each transmission
+    noise: Vec<NoiseReport>,
+}
+
+/// One connection to the receiver and everything that stays the same between messages.
+struct SenderSession {
+    link: Box<dyn Transport>,
+    accepted_codes: Vec<CorrectionType>,
+    encoding: HuffmanEncoding,
+    arq_scheme: Option<ArqScheme>,
+    arq_message_id: usize,
+    arq_stats: ArqSenderStats,
+    // Tells the receiver which message a Data packet or HARQ transmission belongs
+    // to, and us which message a report is about
+    message_id: u8,
+    report_stats: ReportStats,
+    // What we do to our own packets, None when the channel proxy does it
+    noise: NoiseMode,
+    // Built from `noise` once, so it can keep its state from packet to packet
+    channel: Option<Box<dyn Channel>>,
+    // All the noise comes from here, so --seed gives the same run again
+    rng: StdRng,
+    // Tell the receiver the clean data bits of every message, so it can count
+    // the errors the code left in
+    ground_truth: bool,
+    // Scripts only want the results
+    verbose: bool,
+}
+
+impl SenderSession {
+    fn say(&self, text: &str) {
+        if self.verbose {
+            println!("{}", text);
+        }
+    }
+
+    // Says Hello until the receiver answers, keeps the codes it accepted
+    fn handshake(&mut self) -> io::Result<()> {
+        for _ in 0..HANDSHAKE_ATTEMPTS {
+            self.link.send_packet(&hello_packet())?;
+            match self.link.recv_packet() {
+                Ok(Some(answer)) => {
+                    self.accepted_codes = read_accept(&answer).map_err(|reason| {
+                        io::Error::other(format!("handshake failed: {}", reason))
+                    })?;
+                    return Ok(());
+                }
+                Ok(None) => return Err(io::Error::from(ErrorKind::UnexpectedEof)),
+                Err(e) if is_timeout(&e) => self.say("No answer to the handshake, trying again"),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(io::Error::new(
+            ErrorKind::TimedOut,
+            "the receiver never answered the handshake",
+        ))
+    }
+
+    fn send_table(&mut self) -> io::Result<()> {
+        let table_encoding = HuffmanEncoding::encode_table(&self.encoding);
+        self.link
+            .send_packet(&Packet::bits(PacketType::Table, None, &table_encoding))
+    }
+
+    // Why a code can't be used on this connection, if it can't
+    fn check_code(
+        &self,
+        correction_type: &CorrectionType,
+        harq_mode: Option<HarqMode>,
+    ) -> Result<(), String> {
+        // HARQ sends CRC protected words
+        if harq_mode.is_some() && *correction_type != CorrectionType::Crc {
+            return Err("HARQ only works with CRC".to_string());
+        }
+        if !self.accepted_codes.contains(correction_type) {
+            return Err("The receiver did not accept this code".to_string());
+        }
+        if harq_mode.is_some() && self.arq_scheme.is_some() {
+            return Err(
+                "HARQ does its own retransmissions, it can't be used with --arq".to_string(),
+            );
+        }
+        Ok(())
+    }
+
+    // Waits for the receiver's report, None if it hung up or never answered
+    fn read_response(&mut self, message_id: u8) -> io::Result<Option<DecodeReport>> {
+        loop {
+            match self.link.recv_packet() {
+                // A late answer to a Hello we sent again
+                Ok(Some(packet)) if packet.packet_type == PacketType::Accept => {}
+                Ok(Some(packet)) if answers_other_message(&packet, message_id) => {
+                    self.say("Skipping a report on an earlier message");
+                }
+                Ok(Some(packet)) if packet.packet_type == PacketType::Error => {
+                    return Err(receiver_error(&packet));
+                }
+                Ok(Some(packet)) => return read_report(&packet).map(Some),
+                Ok(None) => return Ok(None),
+                Err(e) if is_timeout(&e) => {
+                    self.say("No response from the receiver");
+                    return Ok(None);
+                }
+                Err(e) => {
+                    self.say(&format!("Failed to read response: {}", e));
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    // Our own channel, or the noise that suits the code when there is none
+    fn add_noise(
+        &mut self,
+        bits: String,
+        code_noise: impl FnOnce(String, &mut StdRng) -> String,
+    ) -> (String, NoiseReport) {
+        let (received, report) = match self.channel.as_mut() {
+            Some(channel) => channel.transmit_reported(&bits, &mut self.rng),
+            None => {
+                let received = code_noise(bits.clone(), &mut self.rng);
+                let report = NoiseReport::between(&bits, &received);
+                (received, report)
+            }
+        };
+        if !report.flipped_positions.is_empty() {
+            self.say(&format!("Flipped bits: {:?}", report.flipped_positions));
+        }
+        if !report.deleted_positions.is_empty() {
+            self.say(&format!("Lost bits: {:?}", report.deleted_positions));
+        }
+        if !report.inserted_positions.is_empty() {
+            self.say(&format!("Added bits: {:?}", report.inserted_positions));
+        }
+        (received, report)
+    }
+
+    // Goes right before the message it is about
+    fn send_truth(&mut self, encoded_message: &str) -> io::Result<()> {
+        if !self.ground_truth {
+            return Ok(());
+        }
+        self.link
+            .send_packet(&Packet::bits(PacketType::Truth, None, encoded_message))
+    }
+
+    fn send_message(
+        &mut self,
+        message: &str,
+        correction_type: &CorrectionType,
+        harq_mode: Option<HarqMode>,
+    ) -> io::Result<Delivery> {
+        let delivery = self.deliver(message, correction_type, harq_mode)?;
+        if let Some(report) = &delivery.report {
+            self.report_stats.record(report, message);
+        }
+        Ok(delivery)
+    }
+
+    fn deliver(
+        &mut self,
+        message: &str,
+        correction_type: &CorrectionType,
+        harq_mode: Option<HarqMode>,
+    ) -> io::Result<Delivery> {
+        let encoded_message = encode_message(message, &self.encoding);
+        self.say(&format!("Encoded Message: {}", encoded_message));
+
+        if let Some(mode) = harq_mode {
+            self.send_truth(&encoded_message)?;
+            return self.send_with_harq(mode, &encoded_message);
+        }
+
+        let error_encoded_message = encode_correction(correction_type, &encoded_message);
+        if !error_encoded_message.0 {
+            self.say("Error in encoding the message with error correcting codes");
+            return Ok(Delivery {
+                transmissions: 0,
+                report: None,
+                noise: vec![],
+            });
+        }
+
+        self.say(&format!(
+            "Error Resistant Encoded Message: {}",
+            error_encoded_message.1
+        ));
+        self.send_truth(&encoded_message)?;
+
+        if let Some(scheme) = self.arq_scheme {
+            // The channel noise is applied per frame instead
+            let mut arq = ArqSender::new(scheme, self.arq_message_id, &error_encoded_message.1);
+            let delivery = self.send_with_arq(&mut arq, correction_type)?;
+            self.arq_stats.add(&arq.stats);
+            self.arq_message_id = 1 - self.arq_message_id;
+            return Ok(delivery);
+        }
+
+        if self.noise != NoiseMode::None {
+            self.say("Adding noise to the message");
+        }
+        let (add_noise_to_message, noise) = self.add_noise(error_encoded_message.1, |bits, rng| {
+            add_noise(bits, correction_type, rng)
+        });
+        let message_id = self.message_id;
+        self.message_id = self.message_id.wrapping_add(1);
+        let packet = Packet::data(correction_type.clone(), message_id, &add_noise_to_message);
+        self.link.send_packet(&packet)?;
+
+        Ok(Delivery {
+            transmissions: 1,
+            report: self.read_response(message_id)?,
+            noise: vec![noise],
+        })
+    }
 
-    for char in message.chars() {
-        let bit_string = match hf.encoding.get(&char) {
-            Some(val) => val,
-            None => panic!("Could not find bit string for char {}", char),
+    fn send_with_harq(&mut self, mode: HarqMode, encoded_message: &str) -> io::Result<Delivery> {
+        let mut noise = vec![];
+        let packet_type = match mode {
+            HarqMode::IncrementalRedundancy => PacketType::HarqIncrementalRedundancy,
+            HarqMode::ChaseCombining => PacketType::HarqChaseCombining,
         };
-        encoded_message.push_str(&bit_string);
+
+        let message_id = self.message_id;
+        self.message_id = self.message_id.wrapping_add(1);
+
+        for round in 0..MAX_TRANSMISSIONS {
+            let transmission = harq_transmission(mode, encoded_message, round);
+            self.say(&format!("Transmission {}: {}", round + 1, transmission));
+
+            // Each transmission goes through the channel separately
+            let (add_noise_to_message, report) = self.add_noise(transmission, |bits, rng| {
+                let num_bits_to_flip = rng.gen_range(0..=1);
+                flip_random_bits(bits, num_bits_to_flip, rng)
+            });
+            noise.push(report);
+            self.link.send_packet(&Packet::harq(
+                packet_type,
+                message_id,
+                u8::try_from(round).expect("MAX_TRANSMISSIONS fits in a byte"),
+                &add_noise_to_message,
+            ))?;
+
+            let answer = loop {
+                match self.link.recv_packet() {
+                    Ok(Some(packet)) if answers_other_message(&packet, message_id) => {
+                        self.say("Skipping a report on an earlier message");
+                    }
+                    answer => break answer,
+                }
+            };
+            match answer {
+                Ok(Some(response)) if response.packet_type == PacketType::Nack => {
+                    self.say("Receiver could not decode it (NACK), sending more");
+                }
+                Ok(Some(response)) if response.packet_type == PacketType::Error => {
+                    return Err(receiver_error(&response));
+                }
+                Ok(Some(response)) => {
+                    return Ok(Delivery {
+                        transmissions: round + 1,
+                        report: Some(read_report(&response)?),
+                        noise,
+                    });
+                }
+                Ok(None) => return Err(io::Error::from(ErrorKind::UnexpectedEof)),
+                // The transmission or the answer got lost, same as a NACK
+                Err(e) if is_timeout(&e) => self.say("No answer from the receiver, sending more"),
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.say(&format!(
+            "Giving up after {} transmissions",
+            MAX_TRANSMISSIONS
+        ));
+        Ok(Delivery {
+            transmissions: MAX_TRANSMISSIONS,
+            report: None,
+            noise,
+        })
     }
 
-    return encoded_message;
-}
+    // Sends one message as ARQ frames, the code goes in every frame's packet header
+    fn send_with_arq(
+        &mut self,
+        arq: &mut ArqSender,
+        correction_type: &CorrectionType,
+    ) -> io::Result<Delivery> {
+        let mut noise = vec![];
+        let start = Instant::now();
+        self.link.set_read_timeout(Some(ARQ_TIMEOUT))?;
 
-fn main() -> std::io::Result<()> {
-    let mut stream = TcpStream::connect("127.0.0.1:6969")?;
-    println!("Connected to the receiver!");
+        while !arq.is_done() {
+            for frame in arq.frames_to_send() {
+                // Some frames get through clean, or a noisy channel would keep ARQ
+                // going forever
+                let (noisy_frame, report) = if self.rng.gen_bool(ARQ_FRAME_ERROR_RATE) {
+                    self.add_noise(encode_frame(&frame), |bits, rng| {
+                        flip_random_bits(bits, 1, rng)
+                    })
+                } else {
+                    (encode_frame(&frame), NoiseReport::default())
+                };
+                noise.push(report);
+                let packet =
+                    Packet::bits(PacketType::Arq, Some(correction_type.clone()), &noisy_frame);
+                self.link.send_packet(&packet)?;
+            }
 
+            match self.link.recv_packet() {
+                Ok(Some(packet)) if packet.packet_type == PacketType::Error => {
+                    return Err(receiver_error(&packet));
+                }
+                Ok(Some(packet)) => {
+                    if let Some(frame) = decode_frame(&packet.payload_bits()) {
+                        arq.on_response(&frame);
+                    }
+                }
+                Ok(None) => return Err(io::Error::from(ErrorKind::UnexpectedEof)),
+                // Whatever part of the packet we got stays in the decoder
+                Err(e) if is_timeout(&e) => arq.on_timeout(),
+                Err(e) => return Err(e),
+            }
+        }
+        arq.stats.elapsed = start.elapsed();
+
+        // Late ACKs can still be on their way, wait for the decoded message
+        self.link.set_read_timeout(None)?;
+        let mut delivery = Delivery {
+            transmissions: arq.stats.frames_sent,
+            report: None,
+            noise,
+        };
+        loop {
+            match self.link.recv_packet() {
+                Ok(Some(packet)) if packet.packet_type == PacketType::Response => {
+                    delivery.report = Some(read_report(&packet)?);
+                    return Ok(delivery);
+                }
+                Ok(Some(packet)) if packet.packet_type == PacketType::Error => {
+                    return Err(receiver_error(&packet));
+                }
+                Ok(Some(_)) => {}
+                Ok(None) => return Err(io::Error::from(ErrorKind::UnexpectedEof)),
+                Err(e) if is_timeout(&e) => {
+                    self.say("No response from the receiver");
+                    return Ok(delivery);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn print_summary(&self) {
+        if self.report_stats.messages > 0 {
+            println!("Receiver reports for this session:");
+            println!("{}", self.report_stats);
+        }
+        if let Some(scheme) = self.arq_scheme {
+            println!("ARQ ({:?}) statistics for this session:", scheme);
+            println!("{}", self.arq_stats);
+        }
+        if let Some(summary) = self.link.summary() {
+            println!("{}", summary);
+        }
+    }
+}
+
+fn interactive(session: &mut SenderSession) -> io::Result<()> {
     let vocab = HashSet::from(VALID_WORDS);
-    let hf = HuffmanEncoding::new(&VALID_WORDS);
-    let table_encoding = HuffmanEncoding::encode_table(&hf);
-    stream.write_all(table_encoding.as_bytes()).unwrap();
 
     loop {
         println!("Enter a message to send (or type 'exit' to quit):");
         let mut input = String::new();
-        io::stdin()
+        let bytes_read = io::stdin()
             .read_line(&mut input)
             .expect("Failed to read input");
 
         let input = input.trim();
 
-        if !uses_valid_vocab(input, &vocab) {
-            println!("Please only use words in the vocabulary: {:?}", VALID_WORDS);
-            continue;
-        }
-
-        if input.eq_ignore_ascii_case("exit") {
+        if bytes_read == 0 || input.eq_ignore_ascii_case("exit") {
             println!("Exiting...");
             break;
         }
 
-        let correction_type: CorrectionType;
-        let type_to_append: char;
+        if !uses_valid_vocab(input, &vocab) {
+            println!("Please only use words in the vocabulary: {:?}", VALID_WORDS);
+            continue;
+        }
 
-        loop {
+        let (correction_type, harq_mode) = loop {
             println!("Choose error correction method:");
             println!("1. Parity (Detects errors, no correction)");
             println!("2. TPC (Corrects small errors, uses more space)");
             println!("3. Hamming (Detects and corrects single-bit errors)");
+            println!("4. Concatenated (Hamming outer, interleaver, TPC inner)");
+            println!("5. Row/column parity (Corrects single-bit errors)");
+            println!("6. HARQ with incremental redundancy (Sends more until it decodes)");
+            println!("7. HARQ with Chase combining (Resends until it decodes)");
+            println!("8. CRC (Detects errors, no correction)");
+            println!("9. VT (Corrects one lost or extra bit)");
+            println!("10. Convolutional (Corrects flips that are spread out)");
 
             let mut choice = String::new();
             io::stdin()
                 .read_line(&mut choice)
                 .expect("Failed to read input");
 
-            match choice.trim() {
-                "1" => {
-                    correction_type = CorrectionType::Parity;
-                    type_to_append = 'P';
-                    break;
+            // The menu is in the same order as the names --code takes
+            let code = match choice.trim().parse::<usize>() {
+                Ok(number) if (1..=CODE_NAMES.len()).contains(&number) => {
+                    code_from_name(CODE_NAMES[number - 1])
                 }
-                "2" => {
-                    correction_type = CorrectionType::Triple;
-                    type_to_append = 'T';
-                    break;
-                }
-                "3" => {
-                    correction_type = CorrectionType::Hamming;
-                    type_to_append = 'H';
-                    break;
-                }
-                _ => println!("Invalid choice. Please enter 1, 2, or 3."),
+                _ => None,
+            };
+            match code {
+                Some(code) => break code,
+                None => println!("Invalid choice. Please enter 1 to 10."),
             }
+        };
+
+        if let Err(reason) = session.check_code(&correction_type, harq_mode) {
+            println!("{}, pick another one", reason);
+            continue;
         }
 
-        let encoded_message = encode_message(&input, &hf);
-        println!("Encoded Message: {}", encoded_message);
+        let delivery = session.send_message(input, &correction_type, harq_mode)?;
+        if let Some(report) = delivery.report {
+            println!("From Receiver: {}", report);
+        }
+    }
 
-        let error_encoded_message = encode_correction(&correction_type, &encoded_message);
+    session.print_summary();
+    Ok(())
+}
 
-        println!(
-            "Error Resistant Encoded Message: {}",
-            error_encoded_message.1
+// Sends every message with the same code and says whether they all came back right
+fn scripted(session: &mut SenderSession, options: &ScriptOptions) -> io::Result<u8> {
+    if let Err(reason) = session.check_code(&options.correction_type, options.harq_mode) {
+        eprintln!("{}: {}", reason, options.code_name);
+        return Ok(EXIT_LINK);
+    }
+
+    let mut all_decoded = true;
+    for _ in 0..options.repeat {
+        for message in &options.messages {
+            let delivery =
+                session.send_message(message, &options.correction_type, options.harq_mode)?;
+            let report = MessageReport {
+                message: message.clone(),
+                code: options.code_name.clone(),
+                transmissions: delivery.transmissions,
+                report: delivery.report,
+                noise: delivery.noise,
+            };
+            all_decoded &= report.decoded();
+
+            if options.json {
+                println!("{}", report.to_json());
+            } else {
+                println!("{}", report);
+            }
+        }
+    }
+
+    Ok(if all_decoded {
+        EXIT_DECODED
+    } else {
+        EXIT_NOT_DECODED
+    })
+}
+
+fn run(args: &[String]) -> io::Result<u8> {
+    let arq_scheme = match arq_scheme_from_args(args) {
+        Ok(arq_scheme) => arq_scheme,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Ok(EXIT_USAGE);
+        }
+    };
+    let script = match script_from_args(args) {
+        Ok(script) => script,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Ok(EXIT_USAGE);
+        }
+    };
+    // A message we could never send is a usage error, no need to reach the receiver first
+    let vocab = HashSet::from(VALID_WORDS);
+    if let Some(message) = script
+        .iter()
+        .flat_map(|options| &options.messages)
+        .find(|message| !uses_valid_vocab(message, &vocab))
+    {
+        eprintln!(
+            "\"{}\" uses words outside the vocabulary: {:?}",
+            message, VALID_WORDS
         );
+        return Ok(EXIT_USAGE);
+    }
+    let noise = match noise_from_args(args) {
+        Ok(noise) => noise,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Ok(EXIT_USAGE);
+        }
+    };
+    let seed = match seed_from_args(args) {
+        Ok(seed) => seed.unwrap_or_else(rand::random),
+        Err(e) => {
+            eprintln!("{}", e);
+            return Ok(EXIT_USAGE);
+        }
+    };
 
-        let mut add_noise_to_message = add_noise(error_encoded_message.1, &correction_type);
-        add_noise_to_message.push(type_to_append);
-        println!("Adding noise to the message, flipping a random bit");
+    // With the channel proxy in between, the noise is its job
+    let through_channel = channel_from_args(args);
+    if through_channel && udp_from_args(args) {
+        eprintln!("The channel proxy only forwards TCP, --channel can't be used with --udp");
+        return Ok(EXIT_USAGE);
+    }
 
-        if error_encoded_message.0 {
-            stream.write_all(add_noise_to_message.as_bytes()).unwrap();
-        } else {
-            println!("Error in encoding the message with error correcting codes")
+    let default_address = if through_channel {
+        CHANNEL_ADDRESS
+    } else {
+        DEFAULT_RECEIVER_ADDRESS
+    };
+    let endpoint = match endpoint_from_args(args, "--connect", "SENDER_CONNECT", default_address) {
+        Ok(endpoint) => endpoint,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Ok(EXIT_USAGE);
         }
+    };
 
-        let mut buffer = [0; 512];
-        match stream.read(&mut buffer) {
-            Ok(bytes_read) => {
-                if bytes_read > 0 {
-                    let response = String::from_utf8_lossy(&buffer[..bytes_read]);
-                    println!("From Receiver: {}", response);
-                }
-            }
+    let verbose = script.is_none();
+    let link: Box<dyn Transport> = if udp_from_args(args) {
+        let address = match endpoint.ip() {
+            Ok(address) => address,
             Err(e) => {
-                println!("Failed to read response: {}", e);
+                eprintln!("UDP needs an IP address: {}", e);
+                return Ok(EXIT_USAGE);
             }
+        };
+        // Any port, on the same kind of address as the receiver's
+        let local: SocketAddr = if address.is_ipv4() {
+            "0.0.0.0:0".parse().unwrap()
+        } else {
+            "[::]:0".parse().unwrap()
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(address)?;
+        if verbose {
+            println!("Sending to the receiver at {} over UDP!", endpoint);
+        }
+        Box::new(UdpTransport::new(socket)?)
+    } else {
+        let stream = Stream::connect(&endpoint)?;
+        if verbose {
+            println!("Connected to the receiver at {}!", endpoint);
+        }
+        if through_channel {
+            Box::new(StreamTransport::with_response_timeout(
+                stream,
+                CHANNEL_RESPONSE_TIMEOUT,
+            )?)
+        } else {
+            Box::new(StreamTransport::new(stream))
         }
+    };
+
+    let noise = if through_channel {
+        NoiseMode::None
+    } else {
+        noise
+    };
+    let mut session = SenderSession {
+        link,
+        accepted_codes: vec![],
+        encoding: HuffmanEncoding::new(&VALID_WORDS),
+        arq_scheme,
+        arq_message_id: 0,
+        arq_stats: ArqSenderStats::default(),
+        message_id: 0,
+        report_stats: ReportStats::default(),
+        noise,
+        channel: noise.channel(),
+        rng: StdRng::seed_from_u64(seed),
+        ground_truth: args.iter().any(|arg| arg == "--ground-truth"),
+        verbose,
+    };
+    // On stderr in scripts, so it stays out of the JSON
+    if verbose {
+        println!(
+            "Noise seed: {} (--seed {} makes the same noise again)",
+            seed, seed
+        );
+    } else {
+        eprintln!("Noise seed: {}", seed);
     }
+    session.handshake()?;
+    session.send_table()?;
 
-    Ok(())
+    match script {
+        Some(options) => scripted(&mut session, &options),
+        None => interactive(&mut session).map(|_| EXIT_DECODED),
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    match run(&args) {
+        Ok(code) => ExitCode::from(code),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::from(EXIT_LINK)
+        }
+    }
 }
@@ -0,0 +1,510 @@
+use std::collections::HashMap;
+
+use crate::arq::{decode_frame, encode_frame, ArqReceiver, ArqScheme};
+use crate::correction::{decode_concatenated, decode_correction, decode_product, CorrectionType};
+use crate::handshake::{answer_hello, error_packet};
+use crate::harq::{HarqMode, HarqReceiver};
+use crate::huffman::{decode_message, HuffmanDecoding, HuffmanEncoding};
+use crate::protocol::{Packet, PacketType};
+use crate::rate_matching::{decodes_erasures, fill_erasures};
+use crate::report::{corrected_positions, DecodeReport};
+use crate::stats::{code_label, harq_label, DecodeOutcome, ReceiverStats};
+
+/* Everything the receiver remembers about one connection. It only turns packets
+ * into reply packets and never touches a socket, so the blocking receiver and the
+ * async one drive the same logic.
+ */
+
+// Undo the error correction and the Huffman coding, printing what happened on the way.
+// `truth` is the sender's clean data bits, if it sent them
+fn decode_payload(
+    message_id: u8,
+    correction_type: CorrectionType,
+    payload: &str,
+    truth: Option<String>,
+    decoding_table: &HuffmanDecoding,
+    stats: &mut ReceiverStats,
+) -> DecodeReport {
+    // Bits the channel erased, only some decoders know what to do with them
+    let payload = if decodes_erasures(&correction_type) {
+        payload.to_string()
+    } else {
+        fill_erasures(payload)
+    };
+    let error_decoded_message_values = match &correction_type {
+        CorrectionType::Concatenated(code) => {
+            let (values, stats) = decode_concatenated(code, &payload);
+            println!(
+                "Inner code corrected {} bits (found errors: {}), outer code corrected {} bits (found errors: {})",
+                stats.inner.corrected_bits,
+                stats.inner.found_errors,
+                stats.outer.corrected_bits,
+                stats.outer.found_errors
+            );
+            values
+        }
+        CorrectionType::Product(code) => {
+            let (values, stats) = decode_product(code, &payload);
+            println!(
+                "Row/column decoding corrected {} bits in {} iterations",
+                stats.corrected_bits, stats.iterations
+            );
+            values
+        }
+        _ => decode_correction(correction_type.clone(), &payload, false),
+    };
+
+    let positions = corrected_positions(
+        &error_decoded_message_values.2,
+        &error_decoded_message_values.3,
+    );
+    // Erasures the decoder filled in show up as positions without an error
+    let outcome = if error_decoded_message_values.0 && !error_decoded_message_values.1 {
+        DecodeOutcome::Uncorrectable
+    } else if error_decoded_message_values.0 || !positions.is_empty() {
+        DecodeOutcome::Corrected
+    } else {
+        DecodeOutcome::Clean
+    };
+    let label = code_label(&correction_type);
+    stats.record(label, outcome, positions.len());
+    if let Some(truth) = truth {
+        stats.record_truth(label, &error_decoded_message_values.3, &truth);
+    }
+
+    if outcome == DecodeOutcome::Uncorrectable {
+        println!("Found errors in message but cannot correct them!");
+        println!(
+            "Decoding with errors: {}",
+            decode_message(&error_decoded_message_values.3, decoding_table)
+        );
+    } else if outcome == DecodeOutcome::Corrected {
+        println!("Found errors in message and corrected them!");
+        println!(
+            "Message if there was no correction: {}",
+            decode_message(&error_decoded_message_values.2, decoding_table)
+        );
+        println!(
+            "Message after applying error correction: {}",
+            decode_message(&error_decoded_message_values.3, decoding_table)
+        );
+    } else {
+        println!("Found NO errors in message :)");
+        println!(
+            "Decoding message: {}",
+            decode_message(&error_decoded_message_values.3, decoding_table)
+        );
+    }
+    let mut decoded_message_to_send =
+        decode_message(&error_decoded_message_values.3, decoding_table);
+
+    if decoded_message_to_send.is_empty() {
+        decoded_message_to_send = "Encoding Table not sent :(".to_string();
+    }
+
+    DecodeReport::new(
+        message_id,
+        outcome,
+        Some(correction_type),
+        positions,
+        decoded_message_to_send,
+    )
+}
+
+// Returns the report (None to NACK) and the HARQ state to keep if we are still
+// waiting on retransmissions. The truth is only used up once the message is done
+fn handle_harq_transmission(
+    mut harq: HarqReceiver,
+    message_id: u8,
+    round: usize,
+    payload: &str,
+    truth: &mut Option<String>,
+    decoding_table: &HuffmanDecoding,
+    stats: &mut ReceiverStats,
+) -> (Option<DecodeReport>, Option<HarqReceiver>) {
+    let label = harq_label(harq.mode);
+    stats.code(label).bits_received += payload.len();
+
+    match harq.receive(round, payload) {
+        Some(decoded) => {
+            let outcome = if harq.transmissions == 1 {
+                DecodeOutcome::Clean
+            } else {
+                DecodeOutcome::Corrected
+            };
+            stats.record(label, outcome, 0);
+            if let Some(truth) = truth.take() {
+                stats.record_truth(label, &decoded, &truth);
+            }
+            let decoded_message = decode_message(&decoded, decoding_table);
+            println!(
+                "Decoded message after {} transmission(s): {}",
+                harq.transmissions, decoded_message
+            );
+            let report = DecodeReport::new(message_id, outcome, None, vec![], decoded_message);
+            (Some(report), None)
+        }
+        None if harq.gave_up() => {
+            stats.record(label, DecodeOutcome::Uncorrectable, 0);
+            if let Some(truth) = truth.take() {
+                stats.record_truth(label, &harq.best_guess(), &truth);
+            }
+            println!("Found errors in message but cannot correct them!");
+            let decoded_message = decode_message(&harq.best_guess(), decoding_table);
+            println!("Decoding with errors: {}", decoded_message);
+            let report = DecodeReport::new(
+                message_id,
+                DecodeOutcome::Uncorrectable,
+                None,
+                vec![],
+                decoded_message,
+            );
+            (Some(report), None)
+        }
+        None => {
+            stats.harq_nacks_sent += 1;
+            println!(
+                "Could not decode transmission {}, sending NACK",
+                harq.transmissions
+            );
+            (None, Some(harq))
+        }
+    }
+}
+
+// Feeds one ARQ frame to the receiver state, returns the replies to send back
+fn handle_arq_frame(
+    arq: &mut ArqReceiver,
+    packet: &Packet,
+    truth: &mut Option<String>,
+    decoding_table: &HuffmanDecoding,
+    stats: &mut ReceiverStats,
+) -> Vec<Packet> {
+    let mut replies = vec![];
+    if let Some(code) = &packet.code {
+        stats.code(code_label(code)).bits_received += packet.payload_bits().len();
+    }
+
+    if let Some(reply) = arq.on_frame(decode_frame(&fill_erasures(&packet.payload_bits()))) {
+        replies.push(Packet::bits(PacketType::Arq, None, &encode_frame(&reply)));
+    }
+
+    if arq.is_done() {
+        println!("Received all frames: {}", arq.message());
+        match &packet.code {
+            Some(code) => {
+                let report = decode_payload(
+                    arq.message_id as u8,
+                    code.clone(),
+                    arq.message(),
+                    truth.take(),
+                    decoding_table,
+                    stats,
+                );
+                replies.push(report.to_packet());
+            }
+            None => replies.push(error_packet("ARQ frame without a correction code")),
+        }
+
+        stats.arq.add(&arq.stats);
+        *arq = ArqReceiver::new(arq.scheme, 1 - arq.message_id);
+    }
+
+    replies
+}
+
+// Messages may only use the codes agreed on in the handshake
+fn check_negotiated(
+    accepted_codes: &Option<Vec<CorrectionType>>,
+    code: &CorrectionType,
+) -> Result<(), String> {
+    match accepted_codes {
+        None => Err("no handshake yet, send a Hello first".to_string()),
+        Some(codes) if codes.contains(code) => Ok(()),
+        Some(_) => Err(format!("{:?} was not accepted in the handshake", code)),
+    }
+}
+
+/// Receiver state for one connection, fed one packet at a time.
+pub struct ReceiverSession {
+    decoding_table: HuffmanDecoding,
+    // Keyed on the message id. The report of the last message finished is sent again
+    // when a HARQ round for it comes in after its Response got lost, a Data packet
+    // for it is a copy the channel made
+    harq_receiver: Option<(u8, HarqReceiver)>,
+    finished: Option<(u8, DecodeReport)>,
+    arq_receiver: Option<ArqReceiver>,
+    accepted_codes: Option<Vec<CorrectionType>>,
+    truth: Option<String>,
+    closed: bool,
+    pub stats: ReceiverStats,
+}
+
+impl ReceiverSession {
+    pub fn new(arq_scheme: Option<ArqScheme>) -> Self {
+        ReceiverSession {
+            decoding_table: HuffmanDecoding {
+                decoding: HashMap::new(),
+                max_size: 0,
+            },
+            harq_receiver: None,
+            finished: None,
+            arq_receiver: arq_scheme.map(|scheme| ArqReceiver::new(scheme, 0)),
+            accepted_codes: None,
+            truth: None,
+            closed: false,
+            stats: ReceiverStats {
+                connections: 1,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Once an Error went out the connection should be closed.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Returns the packets to send back, in order.
+    pub fn handle_packet(&mut self, packet: Packet) -> Vec<Packet> {
+        let replies = match packet.packet_type {
+            PacketType::Hello => {
+                let (answer, accepted) = answer_hello(&packet);
+                println!("Handshake: accepted codes {:?}", accepted);
+                self.accepted_codes = Some(accepted);
+                vec![answer]
+            }
+            PacketType::Table => {
+                match HuffmanEncoding::decode_table(&packet.payload_bits()) {
+                    Some(table) => self.decoding_table = table,
+                    None => println!("Received an empty encoding table"),
+                }
+                vec![]
+            }
+            PacketType::Truth => {
+                self.truth = Some(packet.payload_bits());
+                vec![]
+            }
+            PacketType::Data => {
+                let Some((message_id, payload)) = packet.data_fields() else {
+                    println!("Data packet without a message id, ignoring it");
+                    return vec![];
+                };
+                if matches!(&self.finished, Some((finished, _)) if *finished == message_id) {
+                    println!("Message {} came twice, ignoring it", message_id);
+                    return vec![];
+                }
+                println!("Received: {}", payload);
+                match packet.code {
+                    Some(correction_type) => {
+                        match check_negotiated(&self.accepted_codes, &correction_type) {
+                            Ok(()) => {
+                                self.stats.code(code_label(&correction_type)).bits_received +=
+                                    payload.len();
+                                let report = decode_payload(
+                                    message_id,
+                                    correction_type,
+                                    &payload,
+                                    self.truth.take(),
+                                    &self.decoding_table,
+                                    &mut self.stats,
+                                );
+                                self.finished = Some((message_id, report.clone()));
+                                vec![report.to_packet()]
+                            }
+                            Err(reason) => vec![error_packet(&reason)],
+                        }
+                    }
+                    None => {
+                        println!("Data packet without a correction code, ignoring it");
+                        vec![]
+                    }
+                }
+            }
+            PacketType::HarqIncrementalRedundancy | PacketType::HarqChaseCombining
+                if check_negotiated(&self.accepted_codes, &CorrectionType::Crc).is_err() =>
+            {
+                vec![error_packet("HARQ needs CRC, which was not negotiated")]
+            }
+            PacketType::HarqIncrementalRedundancy | PacketType::HarqChaseCombining => {
+                let Some((message_id, round, payload)) = packet.harq_fields() else {
+                    println!("HARQ packet without a message id and round, ignoring it");
+                    return vec![];
+                };
+                println!("Received: {}", payload);
+                let round = usize::from(round);
+                let mode = if packet.packet_type == PacketType::HarqChaseCombining {
+                    HarqMode::ChaseCombining
+                } else {
+                    HarqMode::IncrementalRedundancy
+                };
+
+                match &self.finished {
+                    Some((finished, report)) if *finished == message_id => {
+                        println!("Message {} is already done, answering again", message_id);
+                        return vec![report.to_packet()];
+                    }
+                    _ => {}
+                }
+                let harq = match self.harq_receiver.take() {
+                    Some((id, harq)) if id == message_id && harq.mode == mode => {
+                        if harq.has_round(round) {
+                            println!("Transmission {} came twice, ignoring it", round + 1);
+                            self.harq_receiver = Some((id, harq));
+                            return vec![];
+                        }
+                        harq
+                    }
+                    _ => HarqReceiver::new(mode),
+                };
+
+                let (report, state) = handle_harq_transmission(
+                    harq,
+                    message_id,
+                    round,
+                    &fill_erasures(&payload),
+                    &mut self.truth,
+                    &self.decoding_table,
+                    &mut self.stats,
+                );
+                self.harq_receiver = state.map(|harq| (message_id, harq));
+                match report {
+                    Some(report) => {
+                        self.finished = Some((message_id, report.clone()));
+                        vec![report.to_packet()]
+                    }
+                    None => vec![Packet::text(PacketType::Nack, "")],
+                }
+            }
+            PacketType::Arq => {
+                let negotiated = packet
+                    .code
+                    .as_ref()
+                    .map_or(Ok(()), |code| check_negotiated(&self.accepted_codes, code));
+                match (self.arq_receiver.as_mut(), negotiated) {
+                    (_, Err(reason)) => vec![error_packet(&reason)],
+                    (Some(arq), Ok(())) => handle_arq_frame(
+                        arq,
+                        &packet,
+                        &mut self.truth,
+                        &self.decoding_table,
+                        &mut self.stats,
+                    ),
+                    (None, Ok(())) => {
+                        println!("Got an ARQ frame, but the receiver was not started with --arq");
+                        vec![]
+                    }
+                }
+            }
+            PacketType::Response | PacketType::Nack | PacketType::Accept | PacketType::Error => {
+                println!("Unexpected {:?} packet from the sender", packet.packet_type);
+                vec![]
+            }
+        };
+
+        // After an Error the sender can't expect anything sensible from us
+        if let Some(error) = replies
+            .iter()
+            .find(|reply| reply.packet_type == PacketType::Error)
+        {
+            self.stats.errors_sent += 1;
+            self.closed = true;
+            println!("Closing connection: {}", error.payload_text());
+        }
+
+        replies
+    }
+
+    /// For problems found below the session, like a packet with an unknown code.
+    pub fn reject(&mut self, reason: &str) -> Packet {
+        self.stats.errors_sent += 1;
+        self.closed = true;
+        error_packet(reason)
+    }
+
+    /// Prints and returns the statistics for this connection.
+    pub fn finish(mut self) -> ReceiverStats {
+        if let Some(arq) = self.arq_receiver {
+            // Frames of a message that never completed
+            self.stats.arq.add(&arq.stats);
+            println!("ARQ scheme: {:?}", arq.scheme);
+        }
+        println!("Statistics for this connection:");
+        println!("{}", self.stats);
+
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::correction::encode_correction;
+    use crate::harq::harq_transmission;
+
+    fn harq_packet(message_id: u8, round: usize, bits: &str) -> Packet {
+        Packet::harq(
+            PacketType::HarqChaseCombining,
+            message_id,
+            round as u8,
+            bits,
+        )
+    }
+
+    #[test]
+    fn test_harq_round_after_lost_response_is_answered_again() {
+        let mut session = ReceiverSession::new(None);
+        session.accepted_codes = Some(vec![CorrectionType::Crc]);
+        let mode = HarqMode::ChaseCombining;
+        let word = harq_transmission(mode, "1101001", 0);
+        let mut broken: Vec<char> = word.chars().collect();
+        broken[3] = if broken[3] == '0' { '1' } else { '0' };
+        let broken: String = broken.into_iter().collect();
+
+        let nack = session.handle_packet(harq_packet(0, 0, &broken));
+        assert_eq!(nack[0].packet_type, PacketType::Nack);
+        let response = session.handle_packet(harq_packet(0, 1, &word));
+        assert_eq!(response[0].packet_type, PacketType::Response);
+        assert_eq!(DecodeReport::from_packet(&response[0]).unwrap().code, None);
+
+        // That Response got lost, so the sender NACKs itself and sends round 2
+        let again = session.handle_packet(harq_packet(0, 2, &broken));
+        assert_eq!(again, response);
+        assert_eq!(session.stats.code("harq-chase").messages, 1);
+
+        // The next message starts over
+        let next = session.handle_packet(harq_packet(1, 0, &broken));
+        assert_eq!(next[0].packet_type, PacketType::Nack);
+        assert_eq!(session.handle_packet(harq_packet(1, 0, &broken)), vec![]);
+    }
+
+    #[test]
+    fn test_filled_in_erasures_count_as_corrected() {
+        let mut session = ReceiverSession::new(None);
+        session.accepted_codes = Some(vec![CorrectionType::Triple]);
+        let (_, encoded) = encode_correction(&CorrectionType::Triple, "1011");
+        let erased = format!("{}?{}", &encoded[..9], &encoded[10..]);
+
+        let replies = session.handle_packet(Packet::data(CorrectionType::Triple, 0, &erased));
+        let report = DecodeReport::from_packet(&replies[0]).unwrap();
+        assert_eq!(report.outcome, DecodeOutcome::Corrected);
+        assert_eq!(report.corrected_positions, vec![0]);
+    }
+
+    #[test]
+    fn test_data_packet_delivered_twice_is_answered_once() {
+        let mut session = ReceiverSession::new(None);
+        session.accepted_codes = Some(vec![CorrectionType::Hamming]);
+        let (_, encoded) = encode_correction(&CorrectionType::Hamming, "1011");
+        let packet = Packet::data(CorrectionType::Hamming, 3, &encoded);
+
+        let replies = session.handle_packet(packet.clone());
+        assert_eq!(DecodeReport::from_packet(&replies[0]).unwrap().message_id, 3);
+        assert_eq!(session.handle_packet(packet), vec![]);
+        assert_eq!(session.stats.code("hamming").messages, 1);
+
+        // The same bits again as the next message are a message of their own
+        let next = session.handle_packet(Packet::data(CorrectionType::Hamming, 4, &encoded));
+        assert_eq!(DecodeReport::from_packet(&next[0]).unwrap().message_id, 4);
+    }
+}
@@ -0,0 +1,112 @@
+use std::fs;
+use std::io::IsTerminal;
+use std::process::ExitCode;
+
+use plot::{svg_plot, terminal_plot};
+use script::EXIT_USAGE;
+use simulation::{
+    plot_series, simulate_from_args, simulate_point, theory, PointResult, SimulationPoint,
+};
+use weights::{describe, weights_from_args};
+
+pub mod arq;
+pub mod bounds;
+pub mod correction;
+pub mod harq;
+pub mod modulation;
+pub mod noise;
+pub mod plot;
+pub mod protocol;
+pub mod rate_matching;
+pub mod report;
+pub mod script;
+pub mod simulation;
+pub mod stats;
+pub mod weights;
+
+// simulate weights: what the linear codes are made of, see weights.rs
+fn print_weights(args: &[String]) -> ExitCode {
+    let options = match weights_from_args(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+    for (name, correction_type) in &options.codes {
+        println!("{}", describe(name, correction_type, &options));
+    }
+    ExitCode::SUCCESS
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("weights") {
+        return print_weights(&args);
+    }
+    let options = match simulate_from_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+    let seed = options.seed.unwrap_or_else(rand::random);
+    // On stderr, so it stays out of the results
+    eprintln!("Seed: {}, {} threads", seed, options.threads);
+
+    if !options.json {
+        println!("{}", PointResult::csv_header(options.theory));
+    }
+    let mut results = vec![];
+    for (name, correction_type) in &options.codes {
+        // Every code sees the same seed at the same point
+        for (index, parameter) in options.parameters.iter().enumerate() {
+            let point = SimulationPoint {
+                code: name.clone(),
+                correction_type: correction_type.clone(),
+                sweep: options.sweep,
+                parameter: *parameter,
+                frame_bits: options.frame_bits,
+                frame_length: options.frame_length,
+            };
+            let mut result = match simulate_point(
+                &point,
+                options.stop,
+                seed.wrapping_add(index as u64),
+                options.threads,
+            ) {
+                Ok(result) => result,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return ExitCode::FAILURE;
+                }
+            };
+            if options.theory {
+                result.theory = Some(theory(&point, result.coded_bits_per_frame));
+            }
+            if options.json {
+                println!("{}", result.to_json());
+            } else {
+                println!("{}", result.to_csv());
+            }
+            results.push(result);
+        }
+    }
+
+    let series = plot_series(&results, options.plot_fer);
+    let axes = options.sweep.axes(options.plot_fer);
+    if let Some(style) = options.plot {
+        // After the results, so the numbers come first
+        let color = std::io::stdout().is_terminal();
+        print!("{}", terminal_plot(&series, &axes, style, color));
+    }
+    if let Some(path) = &options.svg {
+        if let Err(e) = fs::write(path, svg_plot(&series, &axes)) {
+            eprintln!("Can't write {}: {}", path, e);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    ExitCode::SUCCESS
+}
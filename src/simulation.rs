@@ -0,0 +1,962 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::bounds::{
+    bsc_capacity, frame_error_rate, hard_decision_crossover, shannon_limit_db,
+    sphere_packing_bound, union_bound_awgn, union_bound_bsc, weight_distribution,
+};
+use crate::correction::{decode_stage, encode_correction, CorrectionType};
+use crate::modulation::Modulation;
+use crate::noise::{seed_from_args, BinarySymmetricChannel, Channel, Fading, ModulatedChannel};
+use crate::plot::{Axes, PlotStyle, Series};
+use crate::rate_matching::{decodes_erasures, fill_erasures, rate_match, rate_recover};
+use crate::script::{code_from_name, flag_value, json_string};
+use crate::stats::bit_errors;
+
+/* Monte Carlo error rates of the codes in correction.rs, with no sockets and no
+ * Huffman coding in between: random data bits, encode, channel, decode, compare.
+ * Every point of a sweep runs until it has seen enough frame errors to say something,
+ * or until it has sent the most frames it may.
+ *
+ *   simulate --code hamming,vt --bsc 0.001,0.01,0.05
+ *   simulate --ebn0 0:10:1 --modulation qpsk --fading rayleigh --format json
+ *
+ * A frame is `frame_bits` data bits. It counts as an error when the decoded data is
+ * not exactly what was sent. The frames are spread over all cores, see simulate_point.
+ * With a `frame_length` every codeword is rate matched to exactly that many bits
+ * before the channel, like frames that have to fill a fixed size MTU.
+ */
+
+pub const DEFAULT_FRAME_BITS: usize = 64;
+pub const DEFAULT_TARGET_ERRORS: usize = 100;
+pub const DEFAULT_MAX_FRAMES: usize = 100_000;
+// Frames one thread simulates at a time
+pub const BATCH_FRAMES: usize = 256;
+pub const DEFAULT_BSC_SWEEP: [f64; 5] = [0.001, 0.003, 0.01, 0.03, 0.1];
+// Past a few threads per core they only get in each other's way
+pub const MAX_THREADS_PER_CORE: usize = 4;
+// More than anyone would wait for, a range like 0:1e9:1 is a typo
+pub const MAX_SWEEP_POINTS: usize = 1000;
+
+/// The codes that need nothing but an encoder and a decoder. HARQ needs a receiver.
+pub const SIMULATED_CODES: [&str; 8] = [
+    "parity",
+    "triple",
+    "hamming",
+    "concatenated",
+    "product",
+    "crc",
+    "vt",
+    "convolutional",
+];
+
+// Two sided 95%
+const CONFIDENCE_Z: f64 = 1.96;
+
+/// What the sweep goes over.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SweepChannel {
+    // Over the crossover probability
+    BinarySymmetric,
+    // Over Eb/N0 per data bit in dB, the code rate is taken off before the channel
+    Modulated {
+        modulation: Modulation,
+        fading: Fading,
+    },
+}
+
+impl SweepChannel {
+    pub fn name(&self) -> String {
+        match self {
+            SweepChannel::BinarySymmetric => "bsc".to_string(),
+            SweepChannel::Modulated { modulation, fading } => {
+                let modulation = match modulation {
+                    Modulation::Bpsk => "bpsk",
+                    Modulation::Qpsk => "qpsk",
+                    Modulation::Qam16 => "16qam",
+                };
+                match fading {
+                    Fading::None => format!("awgn-{}", modulation),
+                    Fading::Rayleigh => format!("rayleigh-{}", modulation),
+                    Fading::Rician(k) => format!("rician{}-{}", k, modulation),
+                }
+            }
+        }
+    }
+
+    /// What the plots put on their axes, the bit or the frame error rate going up.
+    pub fn axes(&self, fer: bool) -> Axes {
+        let x_label = match self {
+            SweepChannel::BinarySymmetric => "crossover probability p",
+            SweepChannel::Modulated { .. } => "Eb/N0 (dB)",
+        };
+        Axes {
+            x_label: x_label.to_string(),
+            y_label: if fer { "FER" } else { "BER" }.to_string(),
+            log_x: *self == SweepChannel::BinarySymmetric,
+        }
+    }
+
+    // `rate` is data bits over coded bits
+    pub fn channel(&self, parameter: f64, rate: f64) -> Box<dyn Channel> {
+        match self {
+            SweepChannel::BinarySymmetric => Box::new(
+                BinarySymmetricChannel::new(parameter)
+                    .expect("--bsc points are checked in simulate_from_args"),
+            ),
+            SweepChannel::Modulated { modulation, fading } => Box::new(ModulatedChannel {
+                modulation: *modulation,
+                ebn0_db: parameter + 10.0 * rate.log10(),
+                fading: *fading,
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StopRule {
+    pub target_frame_errors: usize,
+    pub max_frames: usize,
+}
+
+/// Counts for one code at one point of the sweep.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PointResult {
+    pub code: String,
+    pub channel: String,
+    pub parameter: f64,
+    pub data_bits_per_frame: usize,
+    pub coded_bits_per_frame: usize,
+    pub frames: usize,
+    pub frame_errors: usize,
+    // Wrong frames the decoder said were fine
+    pub undetected_frame_errors: usize,
+    pub bit_errors: usize,
+    pub elapsed: Duration,
+    // Only with --theory
+    pub theory: Option<Theory>,
+}
+
+/// What bounds.rs says about a point, None where it has nothing to say.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Theory {
+    pub fer: Option<f64>,
+    pub sphere_packing_fer: Option<f64>,
+    pub union_bound_fer: Option<f64>,
+    // Of the BSC the decoder sees, in data bits per channel bit
+    pub capacity: Option<f64>,
+    pub shannon_limit_db: Option<f64>,
+}
+
+impl Theory {
+    // Column name and formatted value of every figure, in column order
+    fn cells(&self) -> [(&'static str, Option<String>); 5] {
+        let probability = |value: Option<f64>| value.map(|value| format!("{:.3e}", value));
+        [
+            ("theory_fer", probability(self.fer)),
+            ("sphere_packing_fer", probability(self.sphere_packing_fer)),
+            ("union_bound_fer", probability(self.union_bound_fer)),
+            (
+                "capacity",
+                self.capacity.map(|value| format!("{:.4}", value)),
+            ),
+            (
+                "shannon_limit_db",
+                self.shannon_limit_db.map(|value| format!("{:.2}", value)),
+            ),
+        ]
+    }
+
+    pub fn csv_header() -> String {
+        let names: Vec<&str> = Theory::default()
+            .cells()
+            .iter()
+            .map(|cell| cell.0)
+            .collect();
+        names.join(",")
+    }
+
+    // Empty cells where there's no figure
+    pub fn to_csv(&self) -> String {
+        let values: Vec<String> = self
+            .cells()
+            .into_iter()
+            .map(|(_, value)| value.unwrap_or_default())
+            .collect();
+        values.join(",")
+    }
+
+    // The fields to add to a JSON object, null where there's no figure
+    pub fn json_fields(&self) -> String {
+        let fields: Vec<String> = self
+            .cells()
+            .into_iter()
+            .map(|(name, value)| format!("\"{}\":{}", name, value.as_deref().unwrap_or("null")))
+            .collect();
+        fields.join(",")
+    }
+}
+
+/// Wilson score interval for `errors` out of `trials`, good near 0 too.
+pub fn wilson_interval(errors: usize, trials: usize) -> (f64, f64) {
+    if trials == 0 {
+        return (0.0, 1.0);
+    }
+    let n = trials as f64;
+    let p = errors as f64 / n;
+    let z2 = CONFIDENCE_Z * CONFIDENCE_Z;
+
+    let denominator = 1.0 + z2 / n;
+    let center = (p + z2 / (2.0 * n)) / denominator;
+    let half_width = CONFIDENCE_Z * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt() / denominator;
+    (
+        (center - half_width).max(0.0),
+        (center + half_width).min(1.0),
+    )
+}
+
+impl PointResult {
+    pub fn rate(&self) -> f64 {
+        self.data_bits_per_frame as f64 / self.coded_bits_per_frame as f64
+    }
+
+    pub fn ber(&self) -> f64 {
+        self.bit_errors as f64 / (self.frames * self.data_bits_per_frame) as f64
+    }
+
+    pub fn fer(&self) -> f64 {
+        self.frame_errors as f64 / self.frames as f64
+    }
+
+    // Treats bits as independent, which they aren't inside a frame, so it's optimistic
+    pub fn ber_interval(&self) -> (f64, f64) {
+        wilson_interval(self.bit_errors, self.frames * self.data_bits_per_frame)
+    }
+
+    pub fn fer_interval(&self) -> (f64, f64) {
+        wilson_interval(self.frame_errors, self.frames)
+    }
+
+    /// Data bits that arrive right per bit sent over the channel.
+    pub fn throughput(&self) -> f64 {
+        self.rate() * (1.0 - self.fer())
+    }
+
+    pub fn frames_per_second(&self) -> f64 {
+        self.frames as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    pub fn csv_header(theory: bool) -> String {
+        let mut header = "code,channel,parameter,rate,frames,frame_errors,undetected_frame_errors,bit_errors,ber,ber_low,ber_high,fer,fer_low,fer_high,throughput,frames_per_second".to_string();
+        if theory {
+            header.push(',');
+            header.push_str(&Theory::csv_header());
+        }
+        header
+    }
+
+    pub fn to_csv(&self) -> String {
+        let (ber_low, ber_high) = self.ber_interval();
+        let (fer_low, fer_high) = self.fer_interval();
+        let mut row = format!(
+            "{},{},{},{:.4},{},{},{},{},{:.3e},{:.3e},{:.3e},{:.3e},{:.3e},{:.3e},{:.4},{:.0}",
+            self.code,
+            self.channel,
+            self.parameter,
+            self.rate(),
+            self.frames,
+            self.frame_errors,
+            self.undetected_frame_errors,
+            self.bit_errors,
+            self.ber(),
+            ber_low,
+            ber_high,
+            self.fer(),
+            fer_low,
+            fer_high,
+            self.throughput(),
+            self.frames_per_second()
+        );
+        if let Some(theory) = &self.theory {
+            row.push(',');
+            row.push_str(&theory.to_csv());
+        }
+        row
+    }
+
+    // One line of JSON, no spaces, like the sender's
+    pub fn to_json(&self) -> String {
+        let (ber_low, ber_high) = self.ber_interval();
+        let (fer_low, fer_high) = self.fer_interval();
+        let mut output = String::new();
+        let _ = write!(
+            output,
+            "{{\"code\":{},\"channel\":{},\"parameter\":{},\"rate\":{},\"frames\":{},\"frame_errors\":{},\"undetected_frame_errors\":{},\"bit_errors\":{},",
+            json_string(&self.code),
+            json_string(&self.channel),
+            self.parameter,
+            self.rate(),
+            self.frames,
+            self.frame_errors,
+            self.undetected_frame_errors,
+            self.bit_errors
+        );
+        let _ = write!(
+            output,
+            "\"ber\":{:.3e},\"ber_interval\":[{:.3e},{:.3e}],\"fer\":{:.3e},\"fer_interval\":[{:.3e},{:.3e}],\"throughput\":{},\"frames_per_second\":{:.0}",
+            self.ber(),
+            ber_low,
+            ber_high,
+            self.fer(),
+            fer_low,
+            fer_high,
+            self.throughput(),
+            self.frames_per_second()
+        );
+        if let Some(theory) = &self.theory {
+            let _ = write!(output, ",{}", theory.json_fields());
+        }
+        output.push('}');
+        output
+    }
+}
+
+/// One code at one point of the sweep.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimulationPoint {
+    pub code: String,
+    pub correction_type: CorrectionType,
+    pub sweep: SweepChannel,
+    pub parameter: f64,
+    pub frame_bits: usize,
+    // Rate match every codeword to this many bits
+    pub frame_length: Option<usize>,
+}
+
+/* The figures from bounds.rs for a point. Over a radio channel they are for the BSC
+ * behind the demapper, which takes every bit error as independent. That is exact
+ * for BPSK. QPSK and 16-QAM put more than one bit in a symbol, and under fading the
+ * bits of a symbol fade together.
+ */
+pub fn theory(point: &SimulationPoint, coded_bits: usize) -> Theory {
+    let data_bits = point.frame_bits;
+    let rate = data_bits as f64 / coded_bits as f64;
+    let weights = weight_distribution(&point.correction_type, data_bits);
+
+    let (crossover, union_bound_fer, shannon_limit) = match point.sweep {
+        SweepChannel::BinarySymmetric => (
+            Some(point.parameter),
+            weights.map(|weights| union_bound_bsc(&weights, point.parameter)),
+            None,
+        ),
+        SweepChannel::Modulated { modulation, fading } => {
+            let crossover =
+                hard_decision_crossover(modulation, fading, point.parameter + 10.0 * rate.log10());
+            // The soft decision bound, for what a decoder that kept the LLRs could do
+            let union_bound_fer = match (modulation, fading) {
+                (Modulation::Bpsk | Modulation::Qpsk, Fading::None) => {
+                    weights.map(|weights| union_bound_awgn(&weights, rate, point.parameter))
+                }
+                _ => None,
+            };
+            let dimensions = if modulation == Modulation::Bpsk { 1 } else { 2 };
+            let bits_per_dimension = rate * modulation.bits_per_symbol() as f64 / dimensions as f64;
+            (
+                crossover,
+                union_bound_fer,
+                Some(shannon_limit_db(bits_per_dimension)),
+            )
+        }
+    };
+
+    Theory {
+        fer: crossover.and_then(|p| frame_error_rate(&point.correction_type, data_bits, p)),
+        sphere_packing_fer: crossover.map(|p| sphere_packing_bound(coded_bits, data_bits, p)),
+        union_bound_fer,
+        capacity: crossover.map(bsc_capacity),
+        shannon_limit_db: shannon_limit,
+    }
+}
+
+// The counters of one batch, to be added up in batch order
+#[derive(Clone, Copy, Debug, Default)]
+struct BatchCounts {
+    frames: usize,
+    frame_errors: usize,
+    undetected_frame_errors: usize,
+    bit_errors: usize,
+}
+
+// Every batch has its own stream, keyed by the point's seed and the batch number,
+// so it comes out the same whichever thread runs it
+fn batch_rng(seed: u64, batch: usize) -> StdRng {
+    let mut key = [0u8; 32];
+    key[..8].copy_from_slice(&seed.to_le_bytes());
+    key[8..16].copy_from_slice(&(batch as u64).to_le_bytes());
+    StdRng::from_seed(key)
+}
+
+fn simulate_batch(
+    point: &SimulationPoint,
+    rate: f64,
+    frames: usize,
+    rng: &mut StdRng,
+) -> Result<BatchCounts, String> {
+    let mut channel = point.sweep.channel(point.parameter, rate);
+    let mut counts = BatchCounts {
+        frames,
+        ..Default::default()
+    };
+
+    for _ in 0..frames {
+        let data: String = (0..point.frame_bits)
+            .map(|_| if rng.gen_bool(0.5) { '1' } else { '0' })
+            .collect();
+        let (ok, encoded) = encode_correction(&point.correction_type, &data);
+        if !ok {
+            return Err(cannot_encode(point));
+        }
+        let received = match point.frame_length {
+            None => channel.transmit(&encoded, rng),
+            Some(frame_length) => {
+                let sent = channel.transmit(&rate_match(&encoded, frame_length), rng);
+                let recovered = rate_recover(&sent, encoded.len());
+                if decodes_erasures(&point.correction_type) {
+                    recovered
+                } else {
+                    fill_erasures(&recovered)
+                }
+            }
+        };
+        let (has_error, _, _, decoded) = decode_stage(&point.correction_type, &received);
+
+        let errors = bit_errors(&decoded, &data);
+        if errors > 0 {
+            counts.bit_errors += errors;
+            counts.frame_errors += 1;
+            if !has_error {
+                counts.undetected_frame_errors += 1;
+            }
+        }
+    }
+
+    Ok(counts)
+}
+
+fn cannot_encode(point: &SimulationPoint) -> String {
+    format!(
+        "{} can't encode frames of {} data bits",
+        point.code, point.frame_bits
+    )
+}
+
+// Batches that are done, and how many of them from the start have been counted
+#[derive(Default)]
+struct Progress {
+    finished: BTreeMap<usize, BatchCounts>,
+    counted: usize,
+    total: BatchCounts,
+    failed: Option<String>,
+}
+
+/* Runs one point on `threads` threads until the stop rule says enough. The frames
+ * are cut into batches of BATCH_FRAMES that the threads take in turn, and the
+ * batches are only ever counted in order: up to and including the one that brings
+ * the frame errors to the target. Batches past that are thrown away. So the result
+ * only depends on the seed, never on the number of threads or which one was faster.
+ */
+pub fn simulate_point(
+    point: &SimulationPoint,
+    stop: StopRule,
+    seed: u64,
+    threads: usize,
+) -> Result<PointResult, String> {
+    let (ok, codeword) = encode_correction(&point.correction_type, &"0".repeat(point.frame_bits));
+    if !ok {
+        return Err(cannot_encode(point));
+    }
+    let coded_bits = point.frame_length.unwrap_or(codeword.len());
+    let mut result = PointResult {
+        code: point.code.clone(),
+        channel: point.sweep.name(),
+        parameter: point.parameter,
+        data_bits_per_frame: point.frame_bits,
+        coded_bits_per_frame: coded_bits,
+        ..Default::default()
+    };
+    let rate = result.rate();
+    let batches = stop.max_frames.div_ceil(BATCH_FRAMES);
+    let next_batch = AtomicUsize::new(0);
+    let enough = AtomicBool::new(false);
+    let progress = Mutex::new(Progress::default());
+    let start = Instant::now();
+
+    thread::scope(|scope| {
+        // A thread without a batch to take would only be started to quit
+        for _ in 0..threads.clamp(1, batches.max(1)) {
+            scope.spawn(|| loop {
+                if enough.load(Ordering::Relaxed) {
+                    break;
+                }
+                let batch = next_batch.fetch_add(1, Ordering::Relaxed);
+                if batch >= batches {
+                    break;
+                }
+                let frames = BATCH_FRAMES.min(stop.max_frames - batch * BATCH_FRAMES);
+                let counts = simulate_batch(point, rate, frames, &mut batch_rng(seed, batch));
+
+                let mut progress = progress.lock().unwrap();
+                let counts = match counts {
+                    Ok(counts) => counts,
+                    Err(e) => {
+                        progress.failed = Some(e);
+                        enough.store(true, Ordering::Relaxed);
+                        break;
+                    }
+                };
+                progress.finished.insert(batch, counts);
+                while !enough.load(Ordering::Relaxed) {
+                    let next = progress.counted;
+                    let Some(counts) = progress.finished.remove(&next) else {
+                        break;
+                    };
+                    progress.total.frames += counts.frames;
+                    progress.total.frame_errors += counts.frame_errors;
+                    progress.total.undetected_frame_errors += counts.undetected_frame_errors;
+                    progress.total.bit_errors += counts.bit_errors;
+                    progress.counted += 1;
+                    if progress.total.frame_errors >= stop.target_frame_errors {
+                        enough.store(true, Ordering::Relaxed);
+                    }
+                }
+            });
+        }
+    });
+
+    let progress = progress.into_inner().unwrap();
+    if let Some(e) = progress.failed {
+        return Err(e);
+    }
+    let total = progress.total;
+    result.frames = total.frames;
+    result.frame_errors = total.frame_errors;
+    result.undetected_frame_errors = total.undetected_frame_errors;
+    result.bit_errors = total.bit_errors;
+    result.elapsed = start.elapsed();
+    Ok(result)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimulateOptions {
+    pub codes: Vec<(String, CorrectionType)>,
+    pub sweep: SweepChannel,
+    pub parameters: Vec<f64>,
+    pub frame_bits: usize,
+    pub frame_length: Option<usize>,
+    pub stop: StopRule,
+    pub seed: Option<u64>,
+    pub threads: usize,
+    pub theory: bool,
+    pub json: bool,
+    pub plot: Option<PlotStyle>,
+    pub svg: Option<String>,
+    // Plot the frame error rate instead of the bit error rate
+    pub plot_fer: bool,
+}
+
+/// One series per code, in the order the codes came.
+pub fn plot_series(results: &[PointResult], fer: bool) -> Vec<Series> {
+    let mut series: Vec<Series> = vec![];
+    for result in results {
+        let point = (
+            result.parameter,
+            if fer { result.fer() } else { result.ber() },
+        );
+        match series.iter_mut().find(|series| series.name == result.code) {
+            Some(series) => series.points.push(point),
+            None => series.push(Series {
+                name: result.code.clone(),
+                points: vec![point],
+            }),
+        }
+    }
+    series
+}
+
+/// A comma separated list, or START:STOP:STEP with both ends included.
+pub fn parse_sweep(spec: &str) -> Result<Vec<f64>, String> {
+    let bad = || format!("{} is not a list like 1,2,4 or a range like 0:10:2", spec);
+    let too_many = || format!("{} has more than {} points", spec, MAX_SWEEP_POINTS);
+    let parts: Vec<&str> = spec.split(':').collect();
+
+    let points: Vec<f64> = match parts.as_slice() {
+        [start, stop, step] => {
+            let (start, stop, step) = match (
+                start.parse::<f64>(),
+                stop.parse::<f64>(),
+                step.parse::<f64>(),
+            ) {
+                (Ok(start), Ok(stop), Ok(step))
+                    if step > 0.0 && start <= stop && start.is_finite() && stop.is_finite() =>
+                {
+                    (start, stop, step)
+                }
+                _ => return Err(bad()),
+            };
+            // Counted in floating point, before anything gets allocated
+            let points = ((stop - start) / step + 1e-9).floor() + 1.0;
+            if points > MAX_SWEEP_POINTS as f64 {
+                return Err(too_many());
+            }
+            (0..points as usize)
+                .map(|i| start + i as f64 * step)
+                .collect()
+        }
+        [list] => list
+            .split(',')
+            .map(|value| value.trim().parse::<f64>().map_err(|_| bad()))
+            .collect::<Result<_, _>>()?,
+        _ => return Err(bad()),
+    };
+
+    if points.len() > MAX_SWEEP_POINTS {
+        return Err(too_many());
+    }
+    if points.iter().any(|point| !point.is_finite()) {
+        return Err(bad());
+    }
+    Ok(points)
+}
+
+fn count_from_args(args: &[String], flag: &str, default: usize) -> Result<usize, String> {
+    match flag_value(args, flag)?.map(|value| value.parse::<usize>()) {
+        None => Ok(default),
+        Some(Ok(count)) if count > 0 => Ok(count),
+        _ => Err(format!("{} needs a number of at least 1", flag)),
+    }
+}
+
+fn fading_from_args(args: &[String]) -> Result<Fading, String> {
+    let Some(spec) = flag_value(args, "--fading")? else {
+        return Ok(Fading::None);
+    };
+    match spec.split_once(':') {
+        None if spec == "none" => Ok(Fading::None),
+        None if spec == "rayleigh" => Ok(Fading::Rayleigh),
+        Some(("rician", k)) => match k.parse::<f64>() {
+            Ok(k) if k >= 0.0 => Ok(Fading::Rician(k)),
+            _ => Err(format!("K factor {} has to be 0 or more", k)),
+        },
+        _ => Err(format!(
+            "Unknown fading: {}, use none, rayleigh or rician:K",
+            spec
+        )),
+    }
+}
+
+/// The codes named by --code, all of SIMULATED_CODES without it.
+pub fn codes_from_args(args: &[String]) -> Result<Vec<(String, CorrectionType)>, String> {
+    let names: Vec<&str> = match flag_value(args, "--code")? {
+        Some(names) => names.split(',').collect(),
+        None => SIMULATED_CODES.to_vec(),
+    };
+    let mut codes = vec![];
+    for name in names {
+        if !SIMULATED_CODES.contains(&name) {
+            return Err(format!(
+                "Can't simulate {}, use some of {:?}",
+                name, SIMULATED_CODES
+            ));
+        }
+        let (correction_type, _) = code_from_name(name).ok_or(format!("Unknown code {}", name))?;
+        codes.push((name.to_string(), correction_type));
+    }
+    Ok(codes)
+}
+
+pub fn simulate_from_args(args: &[String]) -> Result<SimulateOptions, String> {
+    let codes = codes_from_args(args)?;
+
+    let (sweep, parameters) = match (flag_value(args, "--bsc")?, flag_value(args, "--ebn0")?) {
+        (Some(_), Some(_)) => return Err("Sweep either --bsc or --ebn0, not both".to_string()),
+        (Some(spec), None) => {
+            let parameters = parse_sweep(spec)?;
+            for p in &parameters {
+                BinarySymmetricChannel::new(*p)?;
+            }
+            (SweepChannel::BinarySymmetric, parameters)
+        }
+        (None, Some(spec)) => {
+            let modulation = match flag_value(args, "--modulation")? {
+                Some(name) => Modulation::parse(name)?,
+                None => Modulation::Bpsk,
+            };
+            let sweep = SweepChannel::Modulated {
+                modulation,
+                fading: fading_from_args(args)?,
+            };
+            (sweep, parse_sweep(spec)?)
+        }
+        (None, None) => (SweepChannel::BinarySymmetric, DEFAULT_BSC_SWEEP.to_vec()),
+    };
+
+    let json = match flag_value(args, "--format")?.map(String::as_str) {
+        None | Some("csv") => false,
+        Some("json") => true,
+        Some(format) => return Err(format!("Unknown format {}, use csv or json", format)),
+    };
+
+    let plot_fer = match flag_value(args, "--metric")?.map(String::as_str) {
+        None | Some("ber") => false,
+        Some("fer") => true,
+        Some(metric) => return Err(format!("Unknown metric {}, use ber or fer", metric)),
+    };
+
+    let frame_length = flag_value(args, "--frame-length")?
+        .map(|_| count_from_args(args, "--frame-length", 0))
+        .transpose()?;
+    let frame_bits = count_from_args(args, "--frame-bits", DEFAULT_FRAME_BITS)?;
+    let cores = thread::available_parallelism().map_or(1, |threads| threads.get());
+    // TPC and Hamming only take messages up to a size, better to hear it before the sweep
+    for (name, correction_type) in &codes {
+        let (ok, reason) = encode_correction(correction_type, &"0".repeat(frame_bits));
+        if !ok {
+            return Err(format!(
+                "{} can't encode frames of {} data bits: {}",
+                name, frame_bits, reason
+            ));
+        }
+    }
+
+    let theory = args.iter().any(|arg| arg == "--theory");
+    // The bounds are for the codes as they are, not punctured or repeated
+    if theory && frame_length.is_some() {
+        return Err("--theory has no figures for rate matched frames".to_string());
+    }
+
+    Ok(SimulateOptions {
+        codes,
+        sweep,
+        parameters,
+        frame_bits,
+        frame_length,
+        stop: StopRule {
+            target_frame_errors: count_from_args(args, "--target-errors", DEFAULT_TARGET_ERRORS)?,
+            max_frames: count_from_args(args, "--max-frames", DEFAULT_MAX_FRAMES)?,
+        },
+        seed: seed_from_args(args)?,
+        threads: count_from_args(args, "--threads", cores)?.min(cores * MAX_THREADS_PER_CORE),
+        theory,
+        json,
+        plot: flag_value(args, "--plot")?
+            .map(|style| PlotStyle::parse(style))
+            .transpose()?,
+        svg: flag_value(args, "--svg")?.cloned(),
+        plot_fer,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_simulate_from_args() {
+        assert_eq!(parse_sweep("0:4:2"), Ok(vec![0.0, 2.0, 4.0]));
+        assert_eq!(parse_sweep("0.1,0.01"), Ok(vec![0.1, 0.01]));
+        assert!(parse_sweep("4:0:1").is_err());
+        assert!(parse_sweep("0:1e12:1").is_err());
+        assert!(parse_sweep("0:inf:1").is_err());
+        assert!(parse_sweep("0:1:1e-300").is_err());
+        assert_eq!(parse_sweep("1:1000:1").map(|points| points.len()), Ok(1000));
+        assert!(parse_sweep(&vec!["1"; 1001].join(",")).is_err());
+
+        let options = simulate_from_args(&to_args(&[
+            "simulate",
+            "--code",
+            "hamming,vt",
+            "--ebn0",
+            "0:10:5",
+            "--modulation",
+            "qpsk",
+            "--fading",
+            "rician:2",
+            "--format",
+            "json",
+            "--threads",
+            "3",
+            "--theory",
+            "--plot",
+            "ascii",
+            "--svg",
+            "ber.svg",
+            "--metric",
+            "fer",
+        ]))
+        .unwrap();
+        assert_eq!(options.codes.len(), 2);
+        assert_eq!(options.parameters, vec![0.0, 5.0, 10.0]);
+        assert_eq!(options.sweep.name(), "rician2-qpsk");
+        assert!(options.json);
+        assert_eq!(options.threads, 3);
+        assert!(options.theory);
+        assert_eq!(options.plot, Some(PlotStyle::Ascii));
+        assert_eq!(options.svg, Some("ber.svg".to_string()));
+        assert!(options.plot_fer);
+
+        let defaults = simulate_from_args(&to_args(&["simulate"])).unwrap();
+        assert_eq!(defaults.codes.len(), SIMULATED_CODES.len());
+        assert_eq!(defaults.sweep, SweepChannel::BinarySymmetric);
+        assert!(defaults.threads >= 1);
+        let many = simulate_from_args(&to_args(&["simulate", "--threads", "1000000"])).unwrap();
+        assert!(many.threads <= defaults.threads * MAX_THREADS_PER_CORE);
+        assert_eq!(defaults.plot, None);
+        assert!(simulate_from_args(&to_args(&["simulate", "--plot", "gif"])).is_err());
+        // Within the 510 bits TPC takes, over the 501 Hamming takes
+        let too_long = |code| {
+            simulate_from_args(&to_args(&[
+                "simulate",
+                "--code",
+                code,
+                "--frame-bits",
+                "505",
+            ]))
+        };
+        assert!(too_long("triple").is_ok());
+        assert!(too_long("hamming").is_err());
+        assert!(too_long("parity,triple,hamming").is_err());
+
+        assert!(simulate_from_args(&to_args(&["simulate", "--code", "harq-ir"])).is_err());
+        assert!(
+            simulate_from_args(&to_args(&["simulate", "--bsc", "0.1", "--ebn0", "3"])).is_err()
+        );
+        assert!(simulate_from_args(&to_args(&["simulate", "--bsc", "2"])).is_err());
+    }
+
+    fn hamming_point() -> SimulationPoint {
+        SimulationPoint {
+            code: "hamming".to_string(),
+            correction_type: CorrectionType::Hamming,
+            sweep: SweepChannel::BinarySymmetric,
+            parameter: 0.01,
+            frame_bits: 64,
+            frame_length: None,
+        }
+    }
+
+    #[test]
+    fn test_hamming_on_the_bsc() {
+        let stop = StopRule {
+            target_frame_errors: 200,
+            max_frames: 20_000,
+        };
+        let result = simulate_point(&hamming_point(), stop, 5, 2).unwrap();
+        // 71 coded bits: a frame fails when 2 or more of them flip, about 15.9% of the time
+        assert_eq!(result.coded_bits_per_frame, 71);
+        assert!(result.frame_errors >= 200);
+        assert_eq!(result.frames % BATCH_FRAMES, 0);
+        let (low, high) = result.fer_interval();
+        assert!(low < 0.159 && 0.159 < high, "{:?}", (low, high));
+        assert!(result.ber() < result.fer());
+
+        // Bounded distance decoding says the same
+        let theory = theory(&hamming_point(), result.coded_bits_per_frame);
+        assert!(low < theory.fer.unwrap() && theory.fer.unwrap() < high);
+        assert!(theory.sphere_packing_fer.unwrap() < theory.fer.unwrap());
+        // Hamming's weights come from its dual, but thousands of weight 3 codewords put
+        // the bound past 1 at this p
+        assert_eq!(theory.union_bound_fer, Some(1.0));
+        assert_eq!(theory.shannon_limit_db, None);
+    }
+
+    #[test]
+    fn test_rate_matched_frames() {
+        let stop = StopRule {
+            target_frame_errors: 100,
+            max_frames: 2_560,
+        };
+        // Sending the 71 bit codeword 3 times over makes every bit a majority vote
+        let point = SimulationPoint {
+            frame_length: Some(213),
+            ..hamming_point()
+        };
+        let result = simulate_point(&point, stop, 5, 2).unwrap();
+        assert_eq!(result.coded_bits_per_frame, 213);
+        assert_eq!(result.frames, 2_560);
+        assert!(result.fer() < 0.01, "{}", result.fer());
+
+        assert!(
+            simulate_from_args(&to_args(&["simulate", "--frame-length", "100", "--theory"]))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_thread_count_does_not_change_the_result() {
+        let stop = StopRule {
+            target_frame_errors: 300,
+            max_frames: 20_000,
+        };
+        let counts = |threads| {
+            let result = simulate_point(&hamming_point(), stop, 11, threads).unwrap();
+            (
+                result.frames,
+                result.frame_errors,
+                result.undetected_frame_errors,
+                result.bit_errors,
+            )
+        };
+        let one = counts(1);
+        assert_eq!(counts(3), one);
+        assert_eq!(counts(8), one);
+
+        // The last batch is cut short to stay within max_frames
+        let short = StopRule {
+            target_frame_errors: 1_000_000,
+            max_frames: 600,
+        };
+        assert_eq!(
+            simulate_point(&hamming_point(), short, 11, 4)
+                .unwrap()
+                .frames,
+            600
+        );
+    }
+
+    #[test]
+    fn test_frames_the_code_cannot_encode() {
+        let stop = StopRule {
+            target_frame_errors: 10,
+            max_frames: 100,
+        };
+        // The TPC length header only has 9 bits
+        let point = SimulationPoint {
+            code: "triple".to_string(),
+            correction_type: CorrectionType::Triple,
+            frame_bits: 600,
+            ..hamming_point()
+        };
+        assert!(simulate_point(&point, stop, 1, 1).is_err());
+        assert!(simulate_batch(&point, 0.3, 1, &mut batch_rng(1, 0)).is_err());
+    }
+
+    #[test]
+    fn test_wilson_interval() {
+        let (low, high) = wilson_interval(0, 100);
+        assert_eq!(low, 0.0);
+        assert!((high - 0.037).abs() < 0.001);
+        let (low, high) = wilson_interval(50, 100);
+        assert!((low - 0.404).abs() < 0.001 && (high - 0.596).abs() < 0.001);
+    }
+}
@@ -0,0 +1,254 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::arq::ArqReceiverStats;
+use crate::correction::CorrectionType;
+use crate::harq::HarqMode;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DecodeOutcome {
+    Clean,
+    Corrected,
+    Uncorrectable,
+}
+
+/// The name a code goes by in the statistics, the same ones the sender's --code takes.
+pub fn code_label(correction_type: &CorrectionType) -> &'static str {
+    match correction_type {
+        CorrectionType::Parity => "parity",
+        CorrectionType::Triple => "triple",
+        CorrectionType::Hamming => "hamming",
+        CorrectionType::Concatenated(_) => "concatenated",
+        CorrectionType::Product(_) => "product",
+        CorrectionType::Crc => "crc",
+        CorrectionType::Vt => "vt",
+        CorrectionType::Convolutional => "convolutional",
+    }
+}
+
+pub fn harq_label(mode: HarqMode) -> &'static str {
+    match mode {
+        HarqMode::IncrementalRedundancy => "harq-ir",
+        HarqMode::ChaseCombining => "harq-chase",
+    }
+}
+
+// Bits that differ from the ground truth, a length difference counts as errors too
+pub fn bit_errors(decoded: &str, truth: &str) -> usize {
+    let differing = decoded
+        .chars()
+        .zip(truth.chars())
+        .filter(|(decoded, truth)| decoded != truth)
+        .count();
+    differing + decoded.len().abs_diff(truth.len())
+}
+
+/// Counters for the messages sent with one code.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CodeStats {
+    pub messages: usize,
+    // Coded bits, every HARQ transmission and ARQ frame included
+    pub bits_received: usize,
+    // Messages where the code found errors, corrected or not
+    pub errors_detected: usize,
+    pub corrected: usize,
+    pub corrected_bits: usize,
+    pub uncorrectable: usize,
+    // Only for messages the sender told us the truth about (--ground-truth)
+    pub truth_messages: usize,
+    pub residual_bit_errors: usize,
+    pub residual_message_errors: usize,
+}
+
+impl CodeStats {
+    pub fn add(&mut self, other: &CodeStats) {
+        self.messages += other.messages;
+        self.bits_received += other.bits_received;
+        self.errors_detected += other.errors_detected;
+        self.corrected += other.corrected;
+        self.corrected_bits += other.corrected_bits;
+        self.uncorrectable += other.uncorrectable;
+        self.truth_messages += other.truth_messages;
+        self.residual_bit_errors += other.residual_bit_errors;
+        self.residual_message_errors += other.residual_message_errors;
+    }
+}
+
+impl fmt::Display for CodeStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} messages, {} bits, errors found in {}, {} corrected ({} bits), {} uncorrectable",
+            self.messages,
+            self.bits_received,
+            self.errors_detected,
+            self.corrected,
+            self.corrected_bits,
+            self.uncorrectable
+        )?;
+        if self.truth_messages > 0 {
+            write!(
+                f,
+                ", {} of {} checked messages wrong ({} bit errors left)",
+                self.residual_message_errors, self.truth_messages, self.residual_bit_errors
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// What the receiver saw on one connection, or summed over all of them.
+#[derive(Clone, Debug, Default)]
+pub struct ReceiverStats {
+    pub connections: usize,
+    pub messages: usize,
+    pub clean: usize,
+    pub corrected: usize,
+    pub uncorrectable: usize,
+    pub harq_nacks_sent: usize,
+    pub errors_sent: usize,
+    pub datagrams_received: usize,
+    pub datagrams_lost: usize,
+    pub datagrams_reordered: usize,
+    pub datagram_duplicates: usize,
+    pub arq: ArqReceiverStats,
+    // By code_label, sorted so they always print in the same order
+    pub codes: BTreeMap<String, CodeStats>,
+}
+
+impl ReceiverStats {
+    pub fn code(&mut self, label: &str) -> &mut CodeStats {
+        self.codes.entry(label.to_string()).or_default()
+    }
+
+    pub fn record(&mut self, label: &str, outcome: DecodeOutcome, corrected_bits: usize) {
+        self.messages += 1;
+        match outcome {
+            DecodeOutcome::Clean => self.clean += 1,
+            DecodeOutcome::Corrected => self.corrected += 1,
+            DecodeOutcome::Uncorrectable => self.uncorrectable += 1,
+        }
+
+        let code = self.code(label);
+        code.messages += 1;
+        match outcome {
+            DecodeOutcome::Clean => {}
+            DecodeOutcome::Corrected => {
+                code.errors_detected += 1;
+                code.corrected += 1;
+                code.corrected_bits += corrected_bits;
+            }
+            DecodeOutcome::Uncorrectable => {
+                code.errors_detected += 1;
+                code.uncorrectable += 1;
+            }
+        }
+    }
+
+    pub fn record_truth(&mut self, label: &str, decoded: &str, truth: &str) {
+        let errors = bit_errors(decoded, truth);
+        let code = self.code(label);
+        code.truth_messages += 1;
+        code.residual_bit_errors += errors;
+        if errors > 0 {
+            code.residual_message_errors += 1;
+        }
+    }
+
+    pub fn add(&mut self, other: &ReceiverStats) {
+        self.connections += other.connections;
+        self.messages += other.messages;
+        self.clean += other.clean;
+        self.corrected += other.corrected;
+        self.uncorrectable += other.uncorrectable;
+        self.harq_nacks_sent += other.harq_nacks_sent;
+        self.errors_sent += other.errors_sent;
+        self.datagrams_received += other.datagrams_received;
+        self.datagrams_lost += other.datagrams_lost;
+        self.datagrams_reordered += other.datagrams_reordered;
+        self.datagram_duplicates += other.datagram_duplicates;
+        self.arq.add(&other.arq);
+        for (label, code) in &other.codes {
+            self.code(label).add(code);
+        }
+    }
+}
+
+impl fmt::Display for ReceiverStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Connections: {}", self.connections)?;
+        writeln!(
+            f,
+            "Messages decoded: {} ({} clean, {} corrected, {} with errors left)",
+            self.messages, self.clean, self.corrected, self.uncorrectable
+        )?;
+        write!(
+            f,
+            "HARQ NACKs sent: {}, error packets sent: {}",
+            self.harq_nacks_sent, self.errors_sent
+        )?;
+        if self.datagrams_received > 0 {
+            write!(
+                f,
+                "\nDatagrams received: {} ({} lost, {} reordered, {} duplicates)",
+                self.datagrams_received,
+                self.datagrams_lost,
+                self.datagrams_reordered,
+                self.datagram_duplicates
+            )?;
+        }
+        if self.arq.frames_received > 0 {
+            write!(f, "\n{}", self.arq)?;
+        }
+        for (label, code) in &self.codes {
+            write!(f, "\n  {}: {}", label, code)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_receiver_stats_add_up() {
+        let mut first = ReceiverStats {
+            connections: 1,
+            ..Default::default()
+        };
+        first.record("hamming", DecodeOutcome::Clean, 0);
+        first.record("hamming", DecodeOutcome::Corrected, 1);
+        first.record_truth("hamming", "1011", "1001");
+
+        let mut second = ReceiverStats {
+            connections: 1,
+            ..Default::default()
+        };
+        second.record("hamming", DecodeOutcome::Uncorrectable, 0);
+        second.record("crc", DecodeOutcome::Clean, 0);
+
+        let mut total = ReceiverStats::default();
+        total.add(&first);
+        total.add(&second);
+        assert_eq!(total.connections, 2);
+        assert_eq!(total.messages, 4);
+        assert_eq!(
+            (total.clean, total.corrected, total.uncorrectable),
+            (2, 1, 1)
+        );
+
+        let hamming = &total.codes["hamming"];
+        assert_eq!((hamming.messages, hamming.errors_detected), (3, 2));
+        assert_eq!((hamming.corrected_bits, hamming.uncorrectable), (1, 1));
+        assert_eq!(hamming.residual_bit_errors, 1);
+        assert_eq!(total.codes["crc"].messages, 1);
+    }
+
+    #[test]
+    fn test_bit_errors_against_truth() {
+        assert_eq!(bit_errors("1011", "1011"), 0);
+        assert_eq!(bit_errors("1011", "0010"), 2);
+        assert_eq!(bit_errors("10", "1011"), 2);
+    }
+}
@@ -0,0 +1,310 @@
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::net::UdpSocket;
+use std::time::Duration;
+
+use crate::endpoint::Stream;
+use crate::protocol::{
+    encode_packet, parse_packet, read_packet, write_packet, Packet, PacketDecoder, ProtocolError,
+};
+
+/* The sender can talk to the receiver over a stream (TCP by default, or a Unix
+ * socket) or over UDP (--udp).
+ *
+ * Over UDP every packet goes in its own datagram, behind a 4 byte big endian
+ * sequence number counting up from 0 in each direction:
+ *
+ *   sequence   4 bytes
+ *   packet     one whole packet, as on TCP
+ *
+ * Nothing is retransmitted at this level, lost and reordered datagrams are only
+ * counted, so whatever the network (or a shaping proxy) does reaches the codes and
+ * the ARQ/HARQ logic above it.
+ */
+
+pub const MAX_DATAGRAM_SIZE: usize = 65507;
+// UDP never tells us the other side is gone, so we never wait longer than this
+pub const UDP_RESPONSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub fn udp_from_args(args: &[String]) -> bool {
+    args.iter().any(|arg| arg == "--udp")
+}
+
+pub fn is_timeout(error: &io::Error) -> bool {
+    matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+pub fn encode_datagram(sequence: u32, packet: &Packet) -> Result<Vec<u8>, ProtocolError> {
+    let mut bytes = sequence.to_be_bytes().to_vec();
+    bytes.extend(encode_packet(packet)?);
+    Ok(bytes)
+}
+
+pub fn decode_datagram(bytes: &[u8]) -> Result<(u32, Packet), ProtocolError> {
+    let (sequence, packet_bytes) = match bytes.split_first_chunk::<4>() {
+        Some((sequence, packet_bytes)) => (u32::from_be_bytes(*sequence), packet_bytes),
+        None => return Err(ProtocolError::Truncated),
+    };
+
+    match parse_packet(packet_bytes) {
+        Ok(Some((packet, length))) if length == packet_bytes.len() => Ok((sequence, packet)),
+        Ok(_) => Err(ProtocolError::Truncated),
+        Err((error, _)) => Err(error),
+    }
+}
+
+/* Only the last SEQUENCE_WINDOW sequence numbers are remembered, one bit each, so
+ * whatever number comes off the wire the tracker stays the same size. Numbers are
+ * compared as serial numbers (RFC 1982): up to half the number space ahead of the
+ * one we expect is ahead, the rest is behind, so the count can wrap around.
+ * A skipped number that leaves the window without turning up counts as lost. A
+ * datagram from before the window is too late to tell from a duplicate and is
+ * dropped like one.
+ */
+const SEQUENCE_WINDOW: u64 = u64::BITS as u64;
+
+/// Keeps track of the sequence numbers seen from one peer.
+#[derive(Clone, Debug)]
+pub struct SequenceTracker {
+    expected: u32,
+    // Bit i is set when expected - 1 - i arrived
+    seen: u64,
+    lost_before_window: u64,
+    pub received: usize,
+    pub reordered: usize,
+    pub duplicates: usize,
+}
+
+impl Default for SequenceTracker {
+    fn default() -> Self {
+        SequenceTracker {
+            expected: 0,
+            // Nothing comes before 0, so nothing there is missing
+            seen: u64::MAX,
+            lost_before_window: 0,
+            received: 0,
+            reordered: 0,
+            duplicates: 0,
+        }
+    }
+}
+
+impl SequenceTracker {
+    pub fn new() -> Self {
+        SequenceTracker::default()
+    }
+
+    /// Whether the datagram should be used, false for duplicates.
+    pub fn observe(&mut self, sequence: u32) -> bool {
+        let ahead = sequence.wrapping_sub(self.expected);
+
+        if ahead < 1 << 31 {
+            // Move the window up to `sequence`, the numbers skipped over start out missing
+            let shift = ahead as u64 + 1;
+            if shift < SEQUENCE_WINDOW {
+                let leaving = self.seen >> (SEQUENCE_WINDOW - shift);
+                self.lost_before_window += shift - leaving.count_ones() as u64;
+                self.seen = (self.seen << shift) | 1;
+            } else {
+                self.lost_before_window +=
+                    self.seen.count_zeros() as u64 + (shift - SEQUENCE_WINDOW);
+                self.seen = 1;
+            }
+            self.expected = sequence.wrapping_add(1);
+        } else {
+            let behind = self.expected.wrapping_sub(sequence) as u64;
+            let bit = 1u64.checked_shl(behind as u32 - 1).unwrap_or(0);
+            if behind > SEQUENCE_WINDOW || self.seen & bit != 0 {
+                self.duplicates += 1;
+                return false;
+            }
+            self.seen |= bit;
+            self.reordered += 1;
+        }
+
+        self.received += 1;
+        true
+    }
+
+    // Everything skipped over that never turned up
+    pub fn lost(&self) -> usize {
+        (self.lost_before_window + self.seen.count_zeros() as u64) as usize
+    }
+}
+
+impl fmt::Display for SequenceTracker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Datagrams received: {} ({} lost, {} reordered, {} duplicates)",
+            self.received,
+            self.lost(),
+            self.reordered,
+            self.duplicates
+        )
+    }
+}
+
+/// One end of a link, so the sender doesn't have to care what is underneath.
+pub trait Transport {
+    fn send_packet(&mut self, packet: &Packet) -> io::Result<()>;
+
+    /// Ok(None) when the other side hung up. Timeouts come back as errors.
+    fn recv_packet(&mut self) -> io::Result<Option<Packet>>;
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()>;
+
+    // Anything worth printing at the end of a session
+    fn summary(&self) -> Option<String> {
+        None
+    }
+}
+
+pub struct StreamTransport {
+    stream: Stream,
+    decoder: PacketDecoder,
+    // How long to wait when no other timeout is asked for, None to wait for ever
+    response_timeout: Option<Duration>,
+}
+
+impl StreamTransport {
+    pub fn new(stream: Stream) -> Self {
+        StreamTransport {
+            stream,
+            decoder: PacketDecoder::new(),
+            response_timeout: None,
+        }
+    }
+
+    /// For a stream that can lose packets on the way, like one through the channel proxy.
+    pub fn with_response_timeout(stream: Stream, timeout: Duration) -> io::Result<Self> {
+        stream.set_read_timeout(Some(timeout))?;
+        Ok(StreamTransport {
+            response_timeout: Some(timeout),
+            ..StreamTransport::new(stream)
+        })
+    }
+}
+
+impl Transport for StreamTransport {
+    fn send_packet(&mut self, packet: &Packet) -> io::Result<()> {
+        write_packet(&mut self.stream, packet)
+    }
+
+    fn recv_packet(&mut self) -> io::Result<Option<Packet>> {
+        read_packet(&mut self.stream, &mut self.decoder)
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout.or(self.response_timeout))
+    }
+}
+
+/// A UDP socket connected to the receiver.
+pub struct UdpTransport {
+    socket: UdpSocket,
+    next_sequence: u32,
+    pub tracker: SequenceTracker,
+}
+
+impl UdpTransport {
+    pub fn new(socket: UdpSocket) -> io::Result<Self> {
+        socket.set_read_timeout(Some(UDP_RESPONSE_TIMEOUT))?;
+        Ok(UdpTransport {
+            socket,
+            next_sequence: 0,
+            tracker: SequenceTracker::new(),
+        })
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send_packet(&mut self, packet: &Packet) -> io::Result<()> {
+        self.socket
+            .send(&encode_datagram(self.next_sequence, packet)?)?;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        Ok(())
+    }
+
+    fn recv_packet(&mut self) -> io::Result<Option<Packet>> {
+        let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+
+        loop {
+            let length = self.socket.recv(&mut buffer)?;
+            match decode_datagram(&buffer[..length]) {
+                Ok((sequence, packet)) => {
+                    if self.tracker.observe(sequence) {
+                        return Ok(Some(packet));
+                    }
+                }
+                Err(error) if error.is_fatal() => return Err(error.into()),
+                Err(error) => println!("Skipping bad datagram: {}", error),
+            }
+        }
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.socket
+            .set_read_timeout(Some(timeout.unwrap_or(UDP_RESPONSE_TIMEOUT)))
+    }
+
+    fn summary(&self) -> Option<String> {
+        Some(self.tracker.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::PacketType;
+
+    #[test]
+    fn test_datagram_round_trip() {
+        let packet = Packet::bits(PacketType::Table, None, "1011001");
+        let bytes = encode_datagram(7, &packet).unwrap();
+        assert_eq!(decode_datagram(&bytes), Ok((7, packet)));
+        assert_eq!(
+            decode_datagram(&bytes[..bytes.len() - 1]),
+            Err(ProtocolError::Truncated)
+        );
+        assert_eq!(decode_datagram(&bytes[..3]), Err(ProtocolError::Truncated));
+    }
+
+    #[test]
+    fn test_sequence_tracker_counts_loss_reordering_and_duplicates() {
+        let mut tracker = SequenceTracker::new();
+        for sequence in [0, 1, 3, 5, 2, 2] {
+            tracker.observe(sequence);
+        }
+        assert!(!tracker.observe(1));
+
+        // 4 never arrived, 2 came late and then once more
+        assert_eq!(tracker.received, 5);
+        assert_eq!(tracker.lost(), 1);
+        assert_eq!(tracker.reordered, 1);
+        assert_eq!(tracker.duplicates, 2);
+    }
+
+    #[test]
+    fn test_sequence_tracker_wraps_around() {
+        let mut tracker = SequenceTracker::new();
+
+        // Two jumps of just under half the number space, then over the top to 0
+        let mut sequence = 0u32;
+        for _ in 0..3 {
+            assert!(tracker.observe(sequence));
+            sequence = sequence.wrapping_add((1 << 31) - 1);
+        }
+        assert!(tracker.observe(u32::MAX));
+        assert!(tracker.observe(0));
+
+        assert_eq!(tracker.received, 5);
+        assert_eq!(tracker.reordered, 0);
+        assert_eq!(tracker.duplicates, 0);
+        assert_eq!(tracker.lost(), 2 * ((1 << 31) - 2));
+
+        // Far behind the window is dropped like a duplicate
+        assert!(!tracker.observe(u32::MAX - 100));
+        assert_eq!(tracker.duplicates, 1);
+    }
+}
@@ -0,0 +1,501 @@
+use std::fmt::Write;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::correction::{encode_correction, CorrectionType};
+use crate::script::flag_value;
+use crate::simulation::{codes_from_args, parse_sweep, DEFAULT_BSC_SWEEP};
+
+/* Weight distribution, minimum distance and covering radius of the linear codes in
+ * correction.rs, worked out from the encoders themselves rather than taken on faith.
+ *
+ *   simulate weights --code hamming,crc --data-bits 16 --bsc 0.001,0.01
+ *
+ * The generator matrix comes from encoding every single data bit. Some encoders add
+ * the same bits to every codeword, like the length in front of triple or the padding
+ * of a product code. That changes no distance, so it's taken off. Random messages
+ * then check that the encoder really is linear, VT for one isn't.
+ *
+ * The weights come from going through every codeword if there aren't too many of
+ * them, or else through the dual code and the MacWilliams identity:
+ *
+ *   A_w = 1/|dual| * sum over j of B_j K_w(j)
+ *
+ * with B_j the dual's weights and K_w the Krawtchouk polynomials. Hamming codes have
+ * a tiny dual, so they can be counted at any length.
+ */
+
+// 2^24 codewords, or cosets for the covering radius, take a second or so
+pub const MAX_ENUMERATED_BITS: usize = 24;
+pub const DEFAULT_DATA_BITS: usize = 8;
+const LINEARITY_CHECKS: usize = 32;
+
+// Codewords as bits packed into 64 bit words
+type Word = Vec<u64>;
+
+fn word_count(length: usize) -> usize {
+    length.div_ceil(64)
+}
+
+fn get_bit(word: &[u64], index: usize) -> bool {
+    (word[index / 64] >> (index % 64)) & 1 == 1
+}
+
+fn set_bit(word: &mut [u64], index: usize) {
+    word[index / 64] |= 1 << (index % 64);
+}
+
+fn xor_into(word: &mut [u64], other: &[u64]) {
+    for (a, b) in word.iter_mut().zip(other) {
+        *a ^= b;
+    }
+}
+
+fn weight(word: &[u64]) -> usize {
+    word.iter().map(|part| part.count_ones() as usize).sum()
+}
+
+// The bits where two encodings differ
+fn difference(first: &str, second: &str) -> Word {
+    let mut word = vec![0; word_count(first.len())];
+    for (index, (a, b)) in first.chars().zip(second.chars()).enumerate() {
+        if a != b {
+            set_bit(&mut word, index);
+        }
+    }
+    word
+}
+
+/// A binary linear code, one generator row per data bit.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinearCode {
+    pub length: usize,
+    pub generator: Vec<Word>,
+}
+
+// Row reduced echelon form, with the pivot column of every row that's left
+fn reduced_echelon(rows: &[Word], length: usize) -> (Vec<Word>, Vec<usize>) {
+    let mut rows = rows.to_vec();
+    let mut pivots = vec![];
+
+    for column in 0..length {
+        let rank = pivots.len();
+        let Some(found) = (rank..rows.len()).find(|&row| get_bit(&rows[row], column)) else {
+            continue;
+        };
+        rows.swap(rank, found);
+        let pivot_row = rows[rank].clone();
+        for (index, row) in rows.iter_mut().enumerate() {
+            if index != rank && get_bit(row, column) {
+                xor_into(row, &pivot_row);
+            }
+        }
+        pivots.push(column);
+    }
+
+    rows.truncate(pivots.len());
+    (rows, pivots)
+}
+
+// How many of the 2^rows codewords have each weight, stepping through them in Gray code order
+fn count_weights(generator: &[Word], length: usize) -> Vec<u128> {
+    let mut weights = vec![0u128; length + 1];
+    let mut word = vec![0; word_count(length)];
+    weights[0] = 1;
+
+    for step in 1u64..1 << generator.len() {
+        xor_into(&mut word, &generator[step.trailing_zeros() as usize]);
+        weights[weight(&word)] += 1;
+    }
+
+    weights
+}
+
+// Exact, as long as the numbers fit in an i128
+fn macwilliams(dual_weights: &[u128], dual_dimension: usize) -> Result<Vec<u128>, String> {
+    let length = dual_weights.len() - 1;
+    let too_big = || format!("A code of {} bits is too long to count exactly", length);
+
+    let mut binomials = vec![vec![0i128; length + 1]; length + 1];
+    for n in 0..=length {
+        binomials[n][0] = 1;
+        for k in 1..=n {
+            binomials[n][k] = binomials[n - 1][k - 1]
+                .checked_add(binomials[n - 1][k])
+                .ok_or_else(too_big)?;
+        }
+    }
+
+    let krawtchouk = |w: usize, j: usize| -> Option<i128> {
+        (0..=w.min(j))
+            .filter(|s| w - s <= length - j)
+            .try_fold(0i128, |sum, s| {
+                let term = binomials[j][s].checked_mul(binomials[length - j][w - s])?;
+                if s % 2 == 0 {
+                    sum.checked_add(term)
+                } else {
+                    sum.checked_sub(term)
+                }
+            })
+    };
+
+    (0..=length)
+        .map(|w| {
+            let sum = dual_weights
+                .iter()
+                .enumerate()
+                .filter(|(_, count)| **count > 0)
+                .try_fold(0i128, |sum, (j, count)| {
+                    sum.checked_add(krawtchouk(w, j)?.checked_mul(*count as i128)?)
+                })
+                .ok_or_else(too_big)?;
+            Ok((sum >> dual_dimension) as u128)
+        })
+        .collect()
+}
+
+impl LinearCode {
+    /// The code `correction_type` makes of `data_bits` long messages, if it is linear.
+    pub fn from_encoder(
+        correction_type: &CorrectionType,
+        data_bits: usize,
+    ) -> Result<Self, String> {
+        let encode = |data: &[bool]| -> Result<String, String> {
+            let data: String = data
+                .iter()
+                .map(|bit| if *bit { '1' } else { '0' })
+                .collect();
+            match encode_correction(correction_type, &data) {
+                (true, encoded) => Ok(encoded),
+                (false, error) => Err(error),
+            }
+        };
+        let not_linear = || "The encoder is not linear".to_string();
+
+        let zero = encode(&vec![false; data_bits])?;
+        let length = zero.len();
+        let mut generator = vec![];
+        for bit in 0..data_bits {
+            let mut data = vec![false; data_bits];
+            data[bit] = true;
+            let codeword = encode(&data)?;
+            if codeword.len() != length {
+                return Err(not_linear());
+            }
+            generator.push(difference(&codeword, &zero));
+        }
+
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..LINEARITY_CHECKS {
+            let data: Vec<bool> = (0..data_bits).map(|_| rng.gen_bool(0.5)).collect();
+            let codeword = encode(&data)?;
+            let mut expected = vec![0; word_count(length)];
+            for (row, _) in generator.iter().zip(&data).filter(|(_, bit)| **bit) {
+                xor_into(&mut expected, row);
+            }
+            if codeword.len() != length || difference(&codeword, &zero) != expected {
+                return Err(not_linear());
+            }
+        }
+
+        let code = LinearCode { length, generator };
+        if code.dimension() < data_bits {
+            return Err("Different messages give the same codeword".to_string());
+        }
+        Ok(code)
+    }
+
+    pub fn dimension(&self) -> usize {
+        reduced_echelon(&self.generator, self.length).1.len()
+    }
+
+    /// The codewords orthogonal to every one of this code, its parity checks.
+    pub fn dual(&self) -> LinearCode {
+        let (rows, pivots) = reduced_echelon(&self.generator, self.length);
+        let generator = (0..self.length)
+            .filter(|column| !pivots.contains(column))
+            .map(|column| {
+                let mut check = vec![0; word_count(self.length)];
+                set_bit(&mut check, column);
+                for (row, pivot) in rows.iter().zip(&pivots) {
+                    if get_bit(row, column) {
+                        set_bit(&mut check, *pivot);
+                    }
+                }
+                check
+            })
+            .collect();
+
+        LinearCode {
+            length: self.length,
+            generator,
+        }
+    }
+
+    /// `weights[w]` is the number of codewords of weight w.
+    pub fn weight_distribution(&self) -> Result<Vec<u128>, String> {
+        if self.generator.len() <= MAX_ENUMERATED_BITS {
+            return Ok(count_weights(&self.generator, self.length));
+        }
+        let dual = self.dual();
+        if dual.generator.len() <= MAX_ENUMERATED_BITS {
+            return macwilliams(
+                &count_weights(&dual.generator, self.length),
+                dual.generator.len(),
+            );
+        }
+        Err(format!(
+            "2^{} codewords and 2^{} in the dual are too many to count",
+            self.generator.len(),
+            dual.generator.len()
+        ))
+    }
+
+    /* The most errors that can be needed to reach a received word from the nearest
+     * codeword: every syndrome is some sum of columns of the parity check matrix, and
+     * the fewest columns it takes is the weight of the best error pattern for it.
+     * A breadth first search over all 2^(n - k) syndromes.
+     */
+    pub fn covering_radius(&self) -> Result<usize, String> {
+        let checks = self.dual().generator;
+        if checks.len() > MAX_ENUMERATED_BITS {
+            return Err(format!(
+                "2^{} cosets are too many to go through",
+                checks.len()
+            ));
+        }
+
+        let mut columns: Vec<usize> = (0..self.length)
+            .map(|column| {
+                checks
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, check)| get_bit(check, column))
+                    .fold(0, |syndrome, (row, _)| syndrome | 1 << row)
+            })
+            .filter(|syndrome| *syndrome != 0)
+            .collect();
+        columns.sort_unstable();
+        columns.dedup();
+
+        let mut distance = vec![usize::MAX; 1 << checks.len()];
+        distance[0] = 0;
+        let mut frontier = vec![0];
+        let mut radius = 0;
+        while !frontier.is_empty() {
+            let mut next = vec![];
+            for syndrome in frontier {
+                for column in &columns {
+                    let reached = syndrome ^ column;
+                    if distance[reached] == usize::MAX {
+                        distance[reached] = distance[syndrome] + 1;
+                        radius = distance[reached];
+                        next.push(reached);
+                    }
+                }
+            }
+            frontier = next;
+        }
+
+        Ok(radius)
+    }
+}
+
+/// The lowest weight of a codeword other than 0, None for a code with only that one.
+pub fn minimum_distance(weights: &[u128]) -> Option<usize> {
+    weights
+        .iter()
+        .enumerate()
+        .skip(1)
+        .find(|(_, count)| **count > 0)
+        .map(|(weight, _)| weight)
+}
+
+/// The chance on the BSC that the errors make up a codeword, so the syndrome says clean.
+pub fn undetected_error_probability(weights: &[u128], p: f64) -> f64 {
+    let length = weights.len() - 1;
+    weights
+        .iter()
+        .enumerate()
+        .skip(1)
+        .filter(|(_, count)| **count > 0)
+        .map(|(weight, count)| {
+            *count as f64 * (weight as f64 * p.ln() + (length - weight) as f64 * (-p).ln_1p()).exp()
+        })
+        .sum()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WeightsOptions {
+    pub codes: Vec<(String, CorrectionType)>,
+    pub data_bits: usize,
+    pub crossovers: Vec<f64>,
+}
+
+pub fn weights_from_args(args: &[String]) -> Result<WeightsOptions, String> {
+    let data_bits = match flag_value(args, "--data-bits")?.map(|value| value.parse::<usize>()) {
+        None => DEFAULT_DATA_BITS,
+        Some(Ok(bits)) if bits > 0 => bits,
+        _ => return Err("--data-bits needs a number of at least 1".to_string()),
+    };
+    let crossovers = match flag_value(args, "--bsc")? {
+        Some(spec) => parse_sweep(spec)?,
+        None => DEFAULT_BSC_SWEEP.to_vec(),
+    };
+
+    Ok(WeightsOptions {
+        codes: codes_from_args(args)?,
+        data_bits,
+        crossovers,
+    })
+}
+
+fn errors(count: usize) -> String {
+    match count {
+        1 => "1 error".to_string(),
+        _ => format!("{} errors", count),
+    }
+}
+
+/// What `simulate weights` prints for one code.
+pub fn describe(name: &str, correction_type: &CorrectionType, options: &WeightsOptions) -> String {
+    let mut output = format!("{}, {} data bits: ", name, options.data_bits);
+    let code = match LinearCode::from_encoder(correction_type, options.data_bits) {
+        Ok(code) => code,
+        Err(e) => {
+            output.push_str(&e);
+            return output;
+        }
+    };
+    let length = code.length;
+    let dimension = code.dimension();
+    let _ = writeln!(
+        output,
+        "({}, {}) code, rate {:.4}",
+        length,
+        dimension,
+        dimension as f64 / length as f64
+    );
+
+    let weights = match code.weight_distribution() {
+        Ok(weights) => weights,
+        Err(e) => {
+            output.push_str(&e);
+            return output;
+        }
+    };
+    if let Some(distance) = minimum_distance(&weights) {
+        let _ = writeln!(
+            output,
+            "  minimum distance {}: corrects {}, detects {}",
+            distance,
+            errors((distance - 1) / 2),
+            errors(distance - 1)
+        );
+    }
+    match code.covering_radius() {
+        Ok(radius) => {
+            let _ = writeln!(output, "  covering radius {}", radius);
+        }
+        Err(e) => {
+            let _ = writeln!(output, "  covering radius: {}", e);
+        }
+    }
+
+    let counts: Vec<String> = weights
+        .iter()
+        .enumerate()
+        .filter(|(_, count)| **count > 0)
+        .map(|(weight, count)| format!("{}:{}", weight, count))
+        .collect();
+    let _ = writeln!(output, "  weights {}", counts.join(" "));
+
+    let undetected: Vec<String> = options
+        .crossovers
+        .iter()
+        .map(|p| format!("{}: {:.3e}", p, undetected_error_probability(&weights, *p)))
+        .collect();
+    let _ = write!(
+        output,
+        "  undetected errors at p = {}",
+        undetected.join(", ")
+    );
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hamming_is_perfect_at_4_bits() {
+        let code = LinearCode::from_encoder(&CorrectionType::Hamming, 4).unwrap();
+        let weights = code.weight_distribution().unwrap();
+        assert_eq!(weights, vec![1, 0, 0, 7, 7, 0, 0, 1]);
+        assert_eq!(minimum_distance(&weights), Some(3));
+        assert_eq!(code.covering_radius(), Ok(1));
+
+        // Shortened it still corrects 1, but not every word is that close to a codeword
+        let code = LinearCode::from_encoder(&CorrectionType::Hamming, 8).unwrap();
+        assert_eq!(code.length, 12);
+        assert_eq!(code.covering_radius(), Ok(2));
+
+        // 2^64 codewords, counted through the 2^7 of the dual
+        let code = LinearCode::from_encoder(&CorrectionType::Hamming, 64).unwrap();
+        let weights = code.weight_distribution().unwrap();
+        assert_eq!(weights.iter().sum::<u128>(), 1 << 64);
+        assert_eq!(minimum_distance(&weights), Some(3));
+    }
+
+    #[test]
+    fn test_macwilliams_agrees_with_counting() {
+        for correction_type in [CorrectionType::Hamming, CorrectionType::Crc] {
+            let code = LinearCode::from_encoder(&correction_type, 12).unwrap();
+            let dual = code.dual();
+            assert_eq!(code.dimension() + dual.dimension(), code.length);
+            assert_eq!(
+                macwilliams(
+                    &count_weights(&dual.generator, code.length),
+                    dual.generator.len()
+                ),
+                Ok(count_weights(&code.generator, code.length))
+            );
+        }
+    }
+
+    #[test]
+    fn test_the_other_codes() {
+        // Every even weight
+        let weights = LinearCode::from_encoder(&CorrectionType::Parity, 6)
+            .unwrap()
+            .weight_distribution()
+            .unwrap();
+        assert_eq!(weights, vec![1, 0, 21, 0, 35, 0, 7, 0]);
+        // With the even weights of 7 bits: (1 + (1 - 2p)^7) / 2 - (1 - p)^7
+        let p: f64 = 0.1;
+        let expected = (1.0 + (1.0 - 2.0 * p).powi(7)) / 2.0 - (1.0 - p).powi(7);
+        assert!((undetected_error_probability(&weights, p) - expected).abs() < 1e-12);
+
+        // The length in front doesn't count, every data bit is 3 bits apart
+        let code = LinearCode::from_encoder(&CorrectionType::Triple, 4).unwrap();
+        assert_eq!(code.length, 9 + 12);
+        let weights = code.weight_distribution().unwrap();
+        assert_eq!(minimum_distance(&weights), Some(3));
+        assert_eq!(weights[3], 4);
+
+        // x + 1 divides the CRC polynomial, so every codeword has an even weight
+        let weights = LinearCode::from_encoder(&CorrectionType::Crc, 16)
+            .unwrap()
+            .weight_distribution()
+            .unwrap();
+        assert!(weights.iter().skip(1).step_by(2).all(|count| *count == 0));
+
+        // Row/column parity corrects any single error
+        let code = LinearCode::from_encoder(&CorrectionType::Product(Box::default()), 8).unwrap();
+        let weights = code.weight_distribution().unwrap();
+        assert_eq!(minimum_distance(&weights), Some(4));
+
+        assert!(LinearCode::from_encoder(&CorrectionType::Vt, 8).is_err());
+    }
+}